@@ -0,0 +1,430 @@
+use std::error::Error;
+use std::io;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::openai::{images, FunctionCall, FunctionParameters, ImageUrl, Message, Model, ResponseFormat, Role, ToolCall, ToolChoice, Usage, PromptTokensDetails};
+use crate::provider::{self, ChatProvider, ChatRequest, ChatResponse, LineBuffer, ProviderSettings, StreamEvent};
+
+const BASE_URL: &str = "https://api.anthropic.com/v1";
+const API_KEY_ENV: &str = "ANTHROPIC_API_KEY";
+const API_VERSION: &str = "2023-06-01";
+// the messages api requires an upper limit for the reply
+const MAX_TOKENS: u32 = 4096;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnthropicRequest {
+    pub model: String,
+    pub max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub messages: Vec<AnthropicMessage>,
+    pub temperature: f32,
+    pub stream: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<AnthropicTool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnthropicMessage {
+    pub role: String,
+    pub content: Vec<ContentBlock>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentBlock {
+    Text { text: String },
+    ToolUse { id: String, name: String, input: Value },
+    ToolResult { tool_use_id: String, content: String },
+    Image { source: ImageSource },
+    // block types this client does not use, like thinking
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AnthropicTool {
+    pub name: String,
+    pub description: String,
+    pub input_schema: FunctionParameters,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamingEvent {
+    MessageStart { message: MessageStart },
+    ContentBlockStart { index: usize, content_block: ContentBlock },
+    ContentBlockDelta { index: usize, delta: BlockDelta },
+    ContentBlockStop,
+    MessageDelta {
+        #[serde(default)]
+        delta: Option<MessageDelta>,
+        #[serde(default)]
+        usage: Option<AnthropicUsage>,
+    },
+    MessageStop,
+    Ping,
+    Error { error: ApiError },
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageStart {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageDelta {
+    #[serde(default)]
+    stop_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BlockDelta {
+    TextDelta { text: String },
+    InputJsonDelta { partial_json: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AnthropicUsage {
+    #[serde(default)]
+    input_tokens: Option<u64>,
+    #[serde(default)]
+    output_tokens: Option<u64>,
+    #[serde(default)]
+    cache_read_input_tokens: Option<u64>,
+    #[serde(default)]
+    cache_creation_input_tokens: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    r#type: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicModel {
+    id: String,
+    #[serde(default)]
+    created_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicModelList {
+    data: Vec<AnthropicModel>,
+}
+
+// consecutive messages with the same role are merged, the api expects alternating roles
+fn push_blocks(messages: &mut Vec<AnthropicMessage>, role: &str, mut blocks: Vec<ContentBlock>) {
+    if blocks.is_empty() {
+        return;
+    }
+    match messages.last_mut() {
+        Some(last) if last.role == role => last.content.append(&mut blocks),
+        _ => messages.push(AnthropicMessage { role: role.to_string(), content: blocks }),
+    }
+}
+
+fn text_block(text: &str) -> Vec<ContentBlock> {
+    // the api rejects empty text blocks
+    if text.trim().is_empty() {
+        vec![]
+    } else {
+        vec![ContentBlock::Text { text: text.to_string() }]
+    }
+}
+
+fn image_block(image: &ImageUrl) -> ContentBlock {
+    let source = match images::parse_data_url(&image.url) {
+        Some((media_type, data)) => ImageSource::Base64 { media_type: media_type.to_string(), data: data.to_string() },
+        None => ImageSource::Url { url: image.url.clone() },
+    };
+    ContentBlock::Image { source }
+}
+
+fn to_tool_choice(tool_choice: &ToolChoice) -> Value {
+    match tool_choice {
+        ToolChoice::None => serde_json::json!({"type": "none"}),
+        ToolChoice::Auto => serde_json::json!({"type": "auto"}),
+        ToolChoice::Required => serde_json::json!({"type": "any"}),
+        ToolChoice::Function(name) => serde_json::json!({"type": "tool", "name": name}),
+    }
+}
+
+// the messages api has no json mode, the format is asked for in the system prompt instead
+fn format_instruction(response_format: &ResponseFormat) -> Option<String> {
+    match response_format {
+        ResponseFormat::Text => None,
+        ResponseFormat::JsonObject => Some("Reply with a single JSON object and nothing else.".to_string()),
+        ResponseFormat::JsonSchema { json_schema } => Some(format!(
+            "Reply with a single JSON object that follows this JSON schema, and nothing else:\n{}", json_schema.schema)),
+    }
+}
+
+/// Maps an openai shaped request to the messages api.
+/// System messages move to the `system` field, and tool calls and results become
+/// `tool_use` and `tool_result` blocks that keep the tool call ids.
+pub fn to_request(request: ChatRequest) -> AnthropicRequest {
+    let mut system = vec![];
+    let mut messages = vec![];
+    // tool uses that did not get a result yet, as (tool use id, function name)
+    let mut open_tool_uses: Vec<(String, String)> = vec![];
+    for msg in request.messages.0.iter() {
+        let text = msg.text();
+        match msg.role {
+            Role::System | Role::Developer => {
+                if !text.trim().is_empty() {
+                    system.push(text.into_owned());
+                }
+            }
+            Role::Assistant => {
+                let mut blocks = text_block(&text);
+                for tool_call in msg.tool_calls.iter().flatten() {
+                    let input = serde_json::from_str(&tool_call.function.arguments)
+                        .unwrap_or_else(|_| Value::Object(Default::default()));
+                    blocks.push(ContentBlock::ToolUse { id: tool_call.id.clone(), name: tool_call.function.name.clone(), input });
+                    open_tool_uses.push((tool_call.id.clone(), tool_call.function.name.clone()));
+                }
+                push_blocks(&mut messages, "assistant", blocks);
+            }
+            Role::Tool | Role::Function => {
+                let id = msg.tool_call_id.clone().unwrap_or_default();
+                let blocks = match open_tool_uses.iter().position(|(tool_use_id, _)| *tool_use_id == id) {
+                    Some(position) => {
+                        let (tool_use_id, _) = open_tool_uses.remove(position);
+                        vec![ContentBlock::ToolResult { tool_use_id, content: text.into_owned() }]
+                    }
+                    // the call was trimmed from the history, a result without its call is rejected
+                    None => text_block(&format!("Result of {}:\n{}", msg.name.as_deref().unwrap_or("tool call"), text)),
+                };
+                push_blocks(&mut messages, "user", blocks);
+            }
+            Role::User => {
+                let mut blocks = text_block(&text);
+                blocks.extend(msg.images().into_iter().map(image_block));
+                push_blocks(&mut messages, "user", blocks);
+            }
+        }
+    }
+    system.extend(request.response_format.as_ref().and_then(format_instruction));
+    let tools = request.tools.into_iter()
+        .map(|function| AnthropicTool {
+            name: function.name,
+            description: function.description,
+            input_schema: function.parameters,
+        })
+        .collect::<Vec<_>>();
+    let tool_choice = if tools.is_empty() { None } else { request.tool_choice.as_ref().map(to_tool_choice) };
+    AnthropicRequest {
+        model: request.model,
+        max_tokens: MAX_TOKENS,
+        system: if system.is_empty() { None } else { Some(system.join("\n\n")) },
+        messages,
+        temperature: request.temperature,
+        stream: true,
+        tools,
+        tool_choice,
+    }
+}
+
+#[derive(Debug)]
+struct ToolUse {
+    index: usize,
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+/// Decodes the server sent events of a streamed messages api reply
+#[derive(Debug, Default)]
+pub struct StreamDecoder {
+    lines: LineBuffer,
+    content: String,
+    tool_uses: Vec<ToolUse>,
+    usage: Option<Usage>,
+    id: Option<String>,
+    stop_reason: Option<String>,
+}
+
+impl StreamDecoder {
+    pub fn feed(&mut self, chunk: &[u8], on_event: &mut dyn FnMut(StreamEvent)) -> Result<(), Box<dyn Error>> {
+        for line in self.lines.push(chunk) {
+            self.decode_line(&line, on_event)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self, on_event: &mut dyn FnMut(StreamEvent)) -> Result<ChatResponse, Box<dyn Error>> {
+        if let Some(line) = self.lines.finish() {
+            self.decode_line(&line, on_event)?;
+        }
+        let tool_calls = self.tool_uses.into_iter()
+            .map(|tool_use| ToolCall::new(&tool_use.id, FunctionCall {
+                name: tool_use.name,
+                // a tool without parameters streams no input at all
+                arguments: if tool_use.arguments.is_empty() { "{}".to_string() } else { tool_use.arguments },
+            }))
+            .collect::<Vec<_>>();
+        let message = Message::assistant(self.content, tool_calls);
+        Ok(ChatResponse { message, usage: self.usage, request_id: self.id, finish_reason: self.stop_reason })
+    }
+
+    fn decode_line(&mut self, line: &str, on_event: &mut dyn FnMut(StreamEvent)) -> Result<(), Box<dyn Error>> {
+        // the `event:` lines repeat the type that is also part of the data
+        let Some(data) = line.strip_prefix("data:") else {
+            return Ok(());
+        };
+        let event = match serde_json::from_str::<StreamingEvent>(data.trim_start()) {
+            Ok(event) => event,
+            Err(e) => {
+                log::debug!("Skipping unknown event '{data}': {e}");
+                return Ok(());
+            }
+        };
+        match event {
+            StreamingEvent::MessageStart { message } => {
+                on_event(StreamEvent::Role(Role::Assistant.as_str()));
+                self.id = message.id;
+                if let Some(usage) = message.usage {
+                    self.add_usage(usage);
+                }
+            }
+            StreamingEvent::ContentBlockStart { index, content_block } => match content_block {
+                ContentBlock::Text { text } => self.push_text(&text, on_event),
+                ContentBlock::ToolUse { id, name, .. } => {
+                    on_event(StreamEvent::ToolCall(&name));
+                    self.tool_uses.push(ToolUse { index, id, name, arguments: String::new() });
+                }
+                _ => {}
+            }
+            StreamingEvent::ContentBlockDelta { index, delta } => match delta {
+                BlockDelta::TextDelta { text } => self.push_text(&text, on_event),
+                BlockDelta::InputJsonDelta { partial_json } => {
+                    if let Some(tool_use) = self.tool_uses.iter_mut().find(|tool_use| tool_use.index == index) {
+                        on_event(StreamEvent::ToolArguments(&partial_json));
+                        tool_use.arguments.push_str(&partial_json);
+                    }
+                }
+                BlockDelta::Other => {}
+            }
+            StreamingEvent::MessageDelta { delta, usage } => {
+                if let Some(stop_reason) = delta.and_then(|delta| delta.stop_reason) {
+                    self.stop_reason = Some(stop_reason);
+                }
+                if let Some(usage) = usage {
+                    self.add_usage(usage);
+                }
+            }
+            StreamingEvent::ContentBlockStop | StreamingEvent::MessageStop | StreamingEvent::Ping => {}
+            StreamingEvent::Error { error } => {
+                return Err(Box::new(io::Error::other(format!("Error from anthropic api ({}): {}", error.r#type, error.message))));
+            }
+        }
+        Ok(())
+    }
+
+    fn push_text(&mut self, text: &str, on_event: &mut dyn FnMut(StreamEvent)) {
+        if !text.is_empty() {
+            on_event(StreamEvent::Content(text));
+            self.content.push_str(text);
+        }
+    }
+
+    // message_start holds the input tokens, message_delta the running total of output tokens
+    fn add_usage(&mut self, anthropic_usage: AnthropicUsage) {
+        let usage = self.usage.get_or_insert_with(Usage::default);
+        if let Some(input_tokens) = anthropic_usage.input_tokens {
+            let cache_read = anthropic_usage.cache_read_input_tokens.unwrap_or(0);
+            let cache_creation = anthropic_usage.cache_creation_input_tokens.unwrap_or(0);
+            usage.prompt_tokens = input_tokens + cache_read + cache_creation;
+            usage.prompt_tokens_details = Some(PromptTokensDetails { cached_tokens: cache_read });
+        }
+        if let Some(output_tokens) = anthropic_usage.output_tokens {
+            usage.completion_tokens = output_tokens;
+        }
+        usage.total_tokens = usage.prompt_tokens + usage.completion_tokens;
+    }
+}
+
+pub struct AnthropicProvider {
+    client: reqwest::Client,
+    api_key: String,
+    base_url: String,
+}
+
+impl AnthropicProvider {
+    pub fn new(client: reqwest::Client, settings: &ProviderSettings) -> Result<AnthropicProvider, Box<dyn Error>> {
+        Ok(AnthropicProvider {
+            client,
+            api_key: settings.api_key(API_KEY_ENV)?,
+            base_url: settings.base_url(BASE_URL).to_string(),
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl ChatProvider for AnthropicProvider {
+    fn name(&self) -> &str {
+        "anthropic"
+    }
+
+    async fn chat(&self, request: ChatRequest, on_event: &mut dyn FnMut(StreamEvent)) -> Result<ChatResponse, Box<dyn Error>> {
+        let body_str = serde_json::to_string(&to_request(request))?;
+        log::debug!("POST {}/messages with message: {body_str}", self.base_url);
+        let mut response = self.client.post(format!("{}/messages", self.base_url))
+            .header("Content-Type", "application/json")
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .body(body_str)
+            .send().await?;
+        if response.status() != 200 {
+            let status = response.status();
+            return Err(provider::api_error("anthropic", status, &response.text().await?));
+        }
+        let mut decoder = StreamDecoder::default();
+        while let Some(chunk) = response.chunk().await? {
+            log::debug!("Parsing chunk:'{}'", String::from_utf8_lossy(chunk.as_ref()));
+            decoder.feed(&chunk, on_event)?;
+        }
+        decoder.finish(on_event)
+    }
+
+    async fn list_models(&self) -> Result<Vec<Model>, Box<dyn Error>> {
+        log::debug!("GET {}/models", self.base_url);
+        let response = self.client.get(format!("{}/models?limit=1000", self.base_url))
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", API_VERSION)
+            .send().await?;
+        if response.status() != 200 {
+            let status = response.status();
+            return Err(provider::api_error("anthropic", status, &response.text().await?));
+        }
+        let model_list: AnthropicModelList = response.json().await?;
+        Ok(model_list.data.into_iter()
+            .map(|model| Model {
+                created: model.created_at
+                    .and_then(|created| chrono::DateTime::parse_from_rfc3339(&created).ok())
+                    .map_or(0, |created| created.timestamp() as u64),
+                id: model.id,
+                object: "model".to_string(),
+                owned_by: "anthropic".to_string(),
+            })
+            .collect())
+    }
+}
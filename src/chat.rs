@@ -0,0 +1,255 @@
+use std::error::Error;
+use std::io;
+use std::time::Instant;
+use serde_json::Value;
+use crate::openai::{ChatHistory, Message, Messages, Metadata, OpenaiFunction, ResponseFormat, Role, Summary, Usage};
+use crate::openai::config::Settings;
+use crate::openai::context::{self, ContextSettings, ContextStrategy};
+use crate::openai::{attachments, images, schema};
+use crate::openai::attachments::Status;
+use crate::openai::usage::{self, UsageRecord};
+use crate::powershell;
+use crate::provider::{ChatProvider, ChatRequest, StreamEvent};
+
+const CHAT_TEMPERATURE: f32 = 0.5;
+const POWERSHELL_TEMPERATURE: f32 = 0.1;
+const SUMMARY_TEMPERATURE: f32 = 0.2;
+const EXTRACT_TEMPERATURE: f32 = 0.0;
+
+// prints the streamed reply, every tool call after the first starts on a new line
+fn event_printer() -> impl FnMut(StreamEvent) {
+    let mut tool_calls = 0;
+    move |event| match event {
+        StreamEvent::Role(role) => print!("{role}: "),
+        StreamEvent::ToolCall(name) => {
+            if tool_calls > 0 {
+                println!();
+            }
+            tool_calls += 1;
+            print!("{name}");
+        }
+        StreamEvent::Content(text) | StreamEvent::Refusal(text) | StreamEvent::ToolArguments(text) => print!("{text}"),
+    }
+}
+
+pub fn record_usage(settings: &Settings, model: &str, usage: Option<Usage>) {
+    let Some(usage) = usage else {
+        log::warn!("No usage returned for request to {model}");
+        return;
+    };
+    let record = UsageRecord::new(model, &settings.session_name(), &usage);
+    if let Err(e) = usage::append_record(settings.usage_file(), &record) {
+        log::warn!("Could not record usage: {e}");
+    }
+}
+
+fn check_budget(settings: &Settings) -> Result<(), Box<dyn Error>> {
+    let budget = settings.budget();
+    if budget.daily.is_none() && budget.monthly.is_none() {
+        return Ok(());
+    }
+    let records = usage::read_records(settings.usage_file())?;
+    usage::check_budget(budget, &records, settings.prices())
+}
+
+// summarizes the messages, a previous summary is extended with them
+async fn summarize(provider: &dyn ChatProvider, settings: &Settings, previous: Option<&str>, messages: &[Message]) -> Result<String, Box<dyn Error>> {
+    let transcript = previous.map(|summary| format!("Summary of the earlier conversation:\n{summary}")).into_iter()
+        .chain(messages.iter().map(|msg| msg.to_string()))
+        .collect::<Vec<String>>()
+        .join("\n");
+    let mut request_messages = Messages::new();
+    request_messages.set_system_message("Summarize the following conversation in a few short paragraphs, \
+        it can start with a summary of its earlier part. \
+        Keep facts, decisions, names, commands and open questions that later messages could refer to.");
+    request_messages.add_user_message(&transcript);
+    let request = ChatRequest {
+        model: settings.model().to_string(),
+        messages: request_messages,
+        temperature: SUMMARY_TEMPERATURE,
+        tools: vec![],
+        tool_choice: None,
+        response_format: None,
+    };
+    let response = provider.complete(request).await?;
+    record_usage(settings, settings.model(), response.usage);
+    let summary = response.message.text();
+    if summary.is_empty() {
+        return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, "No summary in api response")));
+    }
+    Ok(summary.into_owned())
+}
+
+// builds the messages sent to the api from a trimmed copy of the history, the history itself is never modified.
+// A summary that was made or extended for them is returned to be stored with the reply.
+async fn prepare_messages(provider: &dyn ChatProvider, settings: &Settings, history: &Messages, system: &str) -> Result<(Messages, Option<Summary>), Box<dyn Error>> {
+    let system = (!system.is_empty()).then(|| Message::new(Role::System, system));
+    // the system prompt is added after trimming, so it is left out of the budget for the history
+    let context = &ContextSettings {
+        max_tokens: settings.context().max_tokens.saturating_sub(system.as_ref().map_or(0, context::estimate_tokens)),
+        ..settings.context().clone()
+    };
+    let mut made = None;
+    let mut messages = match (context.strategy, context::split_for_summary(history, context)) {
+        (ContextStrategy::Summarize, Some((old, mut recent))) => {
+            let cached = context::cached_summary(history, &old);
+            let summary = match cached {
+                Some((summary, [])) => summary.text.clone(),
+                Some((summary, newer)) => {
+                    log::info!("Extending the summary with {} older messages", newer.len());
+                    summarize(provider, settings, Some(&summary.text), newer).await?
+                }
+                None => {
+                    log::info!("Summarizing {} older messages", old.0.len());
+                    summarize(provider, settings, None, &old.0).await?
+                }
+            };
+            // messages stored without an id can not be found again, their summary is made again next turn
+            if !matches!(cached, Some((_, []))) {
+                made = old.0.last().and_then(|msg| msg.id.clone()).map(|until| Summary { text: summary.clone(), until });
+            }
+            // the system prompt belongs to the command, so the ones stored by older versions are replaced
+            recent.clear_system_messages();
+            recent.0.insert(0, Message::new(Role::System, &format!("Summary of the earlier conversation:\n{summary}")));
+            let mut messages = context::drop_oldest(&recent, context.max_tokens);
+            context::drop_orphaned_tool_messages(&mut messages);
+            messages
+        }
+        _ => {
+            let mut messages = context::trim(history, context);
+            messages.clear_system_messages();
+            messages
+        }
+    };
+    if let Some(system) = system {
+        messages.0.insert(0, system);
+    }
+    log::debug!("Sending {} of {} messages, estimated {} tokens", messages.0.len(), history.0.len(), context::estimate_history_tokens(&messages.0));
+    for msg in &mut messages.0 {
+        msg.pinned = false;
+        msg.attachment = None;
+        msg.id = None;
+        msg.parent = None;
+        msg.metadata = None;
+    }
+    images::resolve(&mut messages, settings.images_dir())?;
+    Ok((messages, made))
+}
+
+async fn get_next_with_tools(provider: &dyn ChatProvider, settings: &Settings, mut history: Messages, system: &str, temperature: f32, tools: Vec<OpenaiFunction>) -> Result<Messages, Box<dyn Error>> {
+    check_budget(settings)?;
+    // attached files are sent as they are now, the history keeps the copy that was sent
+    for change in attachments::refresh(&mut history) {
+        if change.status != Status::Unchanged {
+            eprintln!("{change}");
+        }
+    }
+    // the input of this turn is timed when it is sent, not when the reply is saved
+    for msg in history.0.iter_mut().rev().take_while(|msg| msg.role != Role::Assistant) {
+        msg.metadata.get_or_insert_with(Metadata::now);
+    }
+    let (messages, summary) = prepare_messages(provider, settings, &history, system).await?;
+    let temperature = settings.temperature().unwrap_or(temperature);
+    let tool_names = tools.iter().map(|tool| tool.name.clone()).collect::<Vec<_>>();
+    let request = ChatRequest {
+        model: settings.model().to_string(),
+        messages,
+        temperature,
+        tools,
+        tool_choice: None,
+        response_format: None,
+    };
+    let sent = Instant::now();
+    let mut first_token = None;
+    let mut print_event = event_printer();
+    let response = provider.chat(request, &mut |event| {
+        if first_token.is_none() && !matches!(event, StreamEvent::Role(_)) {
+            first_token = Some(sent.elapsed());
+        }
+        print_event(event)
+    }).await?;
+    let latency = sent.elapsed();
+    record_usage(settings, settings.model(), response.usage.clone());
+    let mut message = response.message;
+    message.metadata = Some(Metadata {
+        model: Some(settings.model().to_string()),
+        provider: Some(provider.name().to_string()),
+        request_id: response.request_id,
+        finish_reason: response.finish_reason,
+        usage: response.usage,
+        time_to_first_token_ms: first_token.map(|elapsed| elapsed.as_millis() as u64),
+        latency_ms: Some(latency.as_millis() as u64),
+        system: Some(system.to_string()),
+        tools: (!tool_names.is_empty()).then_some(tool_names),
+        temperature: Some(temperature),
+        summary,
+        ..Metadata::now()
+    });
+    history.push(message);
+    Ok(history)
+}
+
+/// Continues the conversation, `system` is sent as the system prompt but not stored in the history
+pub async fn get_next(provider: &dyn ChatProvider, settings: &Settings, history: Messages, system: &str) -> Result<Messages, Box<dyn Error>> {
+    get_next_with_tools(provider, settings, history, system, CHAT_TEMPERATURE, vec![]).await
+}
+
+pub async fn get_next_powershell_command(provider: &dyn ChatProvider, settings: &Settings, history: Messages, system: &str) -> Result<Messages, Box<dyn Error>> {
+    get_next_with_tools(provider, settings, history, system, POWERSHELL_TEMPERATURE, powershell::functions()).await
+}
+
+/// Answers the conversation again the way `answer` was asked for, with its system prompt, functions and temperature.
+/// `system` replaces the stored system prompt, it is needed for answers stored without one.
+pub async fn get_next_again(provider: &dyn ChatProvider, settings: &Settings, history: Messages, answer: Option<&Metadata>, system: Option<&str>) -> Result<Messages, Box<dyn Error>> {
+    let system = system.or(answer.and_then(|answer| answer.system.as_deref()))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "The answer was stored without its system prompt, choose one with --persona"))?;
+    let names = answer.and_then(|answer| answer.tools.clone()).unwrap_or_default();
+    // only the functions this version still knows can be offered again
+    let tools = powershell::functions().into_iter().filter(|tool| names.contains(&tool.name)).collect();
+    let temperature = answer.and_then(|answer| answer.temperature).unwrap_or(CHAT_TEMPERATURE);
+    get_next_with_tools(provider, settings, history, system, temperature, tools).await
+}
+
+/// Asks for the information in `input` as json and checks the reply against the schema of the
+/// response format. Invalid replies are sent back with their problems, at most `retries` times.
+pub async fn extract(provider: &dyn ChatProvider, settings: &Settings, input: &str, response_format: ResponseFormat, retries: usize) -> Result<Value, Box<dyn Error>> {
+    let mut messages = Messages::new();
+    messages.set_system_message("Extract the requested information from the message of the user. Reply with JSON only.");
+    messages.add_user_message(input);
+    let mut attempt = 0;
+    loop {
+        check_budget(settings)?;
+        let request = ChatRequest {
+            model: settings.model().to_string(),
+            messages: messages.clone(),
+            temperature: EXTRACT_TEMPERATURE,
+            tools: vec![],
+            tool_choice: None,
+            response_format: Some(response_format.clone()),
+        };
+        let response = provider.complete(request).await?;
+        record_usage(settings, settings.model(), response.usage);
+        if let Some(refusal) = response.message.refusal {
+            return Err(Box::new(io::Error::other(format!("The model refused to answer: {refusal}"))));
+        }
+        let reply = response.message.text().into_owned();
+        let problems = match serde_json::from_str::<Value>(schema::extract_json(&reply)) {
+            Ok(value) => {
+                let problems = response_format.schema().map(|schema| schema::validate(schema, &value)).unwrap_or_default();
+                if problems.is_empty() {
+                    return Ok(value);
+                }
+                problems
+            }
+            Err(e) => vec![format!("the reply is not valid JSON: {e}")],
+        };
+        attempt += 1;
+        log::info!("Attempt {attempt} returned invalid JSON: {}", problems.join(", "));
+        if attempt > retries {
+            return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData,
+                format!("No valid JSON after {attempt} attempts:\n{}", problems.join("\n")))));
+        }
+        messages.add_message(Role::Assistant, &reply);
+        messages.add_user_message(&format!("That JSON is invalid:\n- {}\nReply with the corrected JSON only.", problems.join("\n- ")));
+    }
+}
@@ -0,0 +1,506 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::openai::{images, tree, FunctionCall, FunctionParameters, FunctionProperty, ImageUrl, Message, Model, PromptTokensDetails, ResponseFormat, Role, ToolCall, ToolChoice, Usage};
+use crate::provider::{self, ChatProvider, ChatRequest, ChatResponse, LineBuffer, ProviderSettings, StreamEvent};
+
+const BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+const API_KEY_ENV: &str = "GEMINI_API_KEY";
+const NAME: &str = "gemini";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GeminiRequest {
+    pub contents: Vec<Content>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<Content>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<Tool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_config: Option<ToolConfig>,
+    pub generation_config: GenerationConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolConfig {
+    pub function_calling_config: FunctionCallingConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FunctionCallingConfig {
+    // AUTO, ANY or NONE
+    pub mode: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_function_names: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationConfig {
+    pub temperature: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_mime_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_json_schema: Option<Value>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Content {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub parts: Vec<Part>,
+}
+
+// a part holds one of text, inline data, a function call or a function response
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Part {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inline_data: Option<Blob>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_call: Option<GeminiFunctionCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_response: Option<FunctionResponse>,
+    // summaries of the model's reasoning, not part of the reply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thought: Option<bool>,
+}
+
+impl Part {
+    pub fn text(text: String) -> Part {
+        Part { text: Some(text), ..Default::default() }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Blob {
+    pub mime_type: String,
+    // base64 encoded
+    pub data: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct GeminiFunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub args: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct FunctionResponse {
+    pub name: String,
+    pub response: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tool {
+    pub function_declarations: Vec<FunctionDeclaration>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub description: String,
+    // functions without parameters leave out the schema, an empty object schema is rejected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Schema>,
+}
+
+/// The openapi subset gemini uses for function parameters
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Schema {
+    pub r#type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub r#enum: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub properties: HashMap<String, Schema>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub required: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateContentResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    #[serde(default)]
+    usage_metadata: Option<UsageMetadata>,
+    #[serde(default)]
+    response_id: Option<String>,
+    #[serde(default)]
+    error: Option<ApiError>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    #[serde(default)]
+    content: Option<Content>,
+    // only set on the last event
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u64,
+    #[serde(default)]
+    candidates_token_count: u64,
+    #[serde(default)]
+    cached_content_token_count: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    #[serde(default)]
+    status: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiModel {
+    name: String,
+    #[serde(default)]
+    supported_generation_methods: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiModelList {
+    #[serde(default)]
+    models: Vec<GeminiModel>,
+    #[serde(default)]
+    next_page_token: Option<String>,
+}
+
+fn property_schema(property: FunctionProperty) -> Schema {
+    let format = if property.r#enum.is_empty() { None } else { Some("enum".to_string()) };
+    Schema {
+        r#type: property.r#type.to_uppercase(),
+        description: property.description,
+        format,
+        r#enum: property.r#enum,
+        properties: HashMap::new(),
+        required: vec![],
+    }
+}
+
+pub fn to_schema(parameters: FunctionParameters) -> Option<Schema> {
+    if parameters.properties.is_empty() {
+        return None;
+    }
+    Some(Schema {
+        r#type: parameters.r#type.to_uppercase(),
+        description: None,
+        format: None,
+        r#enum: vec![],
+        properties: parameters.properties.into_iter()
+            .map(|(name, property)| (name, property_schema(property)))
+            .collect(),
+        required: parameters.required,
+    })
+}
+
+// consecutive messages with the same role are merged into one content
+fn push_parts(contents: &mut Vec<Content>, role: &str, mut parts: Vec<Part>) {
+    if parts.is_empty() {
+        return;
+    }
+    match contents.last_mut() {
+        Some(last) if last.role.as_deref() == Some(role) => last.parts.append(&mut parts),
+        _ => contents.push(Content { role: Some(role.to_string()), parts }),
+    }
+}
+
+fn text_part(text: &str) -> Vec<Part> {
+    if text.is_empty() {
+        vec![]
+    } else {
+        vec![Part::text(text.to_string())]
+    }
+}
+
+// only inline image data is supported, other urls are passed on as text
+fn image_part(image: &ImageUrl) -> Part {
+    match images::parse_data_url(&image.url) {
+        Some((mime_type, data)) => Part {
+            inline_data: Some(Blob { mime_type: mime_type.to_string(), data: data.to_string() }),
+            ..Default::default()
+        },
+        None => Part::text(format!("Image: {}", image.url)),
+    }
+}
+
+fn to_tool_config(tool_choice: &ToolChoice) -> ToolConfig {
+    let (mode, allowed_function_names) = match tool_choice {
+        ToolChoice::None => ("NONE", vec![]),
+        ToolChoice::Auto => ("AUTO", vec![]),
+        ToolChoice::Required => ("ANY", vec![]),
+        ToolChoice::Function(name) => ("ANY", vec![name.clone()]),
+    };
+    ToolConfig {
+        function_calling_config: FunctionCallingConfig { mode: mode.to_string(), allowed_function_names },
+    }
+}
+
+/// Maps an openai shaped request to `generateContent`.
+/// Assistant messages get the `model` role, system messages become the `systemInstruction`,
+/// and tool results are sent by the user as `functionResponse` parts.
+/// Gemini has no tool call ids, results are matched to their call by function name.
+pub fn to_request(request: ChatRequest) -> GeminiRequest {
+    let mut system = vec![];
+    let mut contents = vec![];
+    // tool calls that did not get a result yet, as (tool call id, function name)
+    let mut open_calls: Vec<(String, String)> = vec![];
+    for msg in request.messages.0 {
+        let text = msg.text().into_owned();
+        match msg.role {
+            Role::System | Role::Developer => {
+                if !text.trim().is_empty() {
+                    system.push(Part::text(text));
+                }
+            }
+            Role::Assistant => {
+                let mut parts = text_part(&text);
+                for tool_call in msg.tool_calls.unwrap_or_default() {
+                    open_calls.push((tool_call.id, tool_call.function.name.clone()));
+                    parts.push(Part {
+                        function_call: Some(GeminiFunctionCall {
+                            args: serde_json::from_str(&tool_call.function.arguments).unwrap_or_else(|_| Value::Object(Default::default())),
+                            name: tool_call.function.name,
+                        }),
+                        ..Default::default()
+                    });
+                }
+                push_parts(&mut contents, "model", parts);
+            }
+            Role::Tool | Role::Function => {
+                let name = msg.tool_call_id.as_ref()
+                    .and_then(|id| open_calls.iter().rposition(|(call_id, _)| call_id == id))
+                    .map(|position| open_calls.remove(position).1);
+                let response = FunctionResponse {
+                    name: name.or(msg.name).unwrap_or_default(),
+                    response: serde_json::json!({ "content": text }),
+                };
+                push_parts(&mut contents, "user", vec![Part { function_response: Some(response), ..Default::default() }]);
+            }
+            Role::User => {
+                let mut parts = text_part(&text);
+                parts.extend(msg.images().into_iter().map(image_part));
+                push_parts(&mut contents, "user", parts);
+            }
+        }
+    }
+    let function_declarations = request.tools.into_iter()
+        .map(|function| FunctionDeclaration {
+            name: function.name,
+            description: function.description,
+            parameters: to_schema(function.parameters),
+        })
+        .collect::<Vec<_>>();
+    let tools = if function_declarations.is_empty() { vec![] } else { vec![Tool { function_declarations }] };
+    let tool_config = if tools.is_empty() { None } else { request.tool_choice.as_ref().map(to_tool_config) };
+    GeminiRequest {
+        contents,
+        system_instruction: if system.is_empty() { None } else { Some(Content { role: None, parts: system }) },
+        tools,
+        tool_config,
+        generation_config: GenerationConfig {
+            temperature: request.temperature,
+            response_mime_type: match request.response_format {
+                None | Some(ResponseFormat::Text) => None,
+                Some(_) => Some("application/json".to_string()),
+            },
+            response_json_schema: request.response_format.as_ref().and_then(ResponseFormat::schema).cloned(),
+        },
+    }
+}
+
+/// Decodes the server sent events of `streamGenerateContent?alt=sse`,
+/// every event is a complete `generateContent` response with the next part of the reply
+#[derive(Debug, Default)]
+pub struct StreamDecoder {
+    lines: LineBuffer,
+    started: bool,
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    usage: Option<Usage>,
+    response_id: Option<String>,
+    finish_reason: Option<String>,
+}
+
+impl StreamDecoder {
+    pub fn feed(&mut self, chunk: &[u8], on_event: &mut dyn FnMut(StreamEvent)) -> Result<(), Box<dyn Error>> {
+        for line in self.lines.push(chunk) {
+            if let Some(data) = line.strip_prefix("data:") {
+                self.decode(data.trim_start(), on_event)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self, on_event: &mut dyn FnMut(StreamEvent)) -> Result<ChatResponse, Box<dyn Error>> {
+        if let Some(line) = self.lines.finish() {
+            if let Some(data) = line.strip_prefix("data:") {
+                self.decode(data.trim_start(), on_event)?;
+            }
+        }
+        let message = Message::assistant(self.content, self.tool_calls);
+        Ok(ChatResponse { message, usage: self.usage, request_id: self.response_id, finish_reason: self.finish_reason })
+    }
+
+    fn decode(&mut self, data: &str, on_event: &mut dyn FnMut(StreamEvent)) -> Result<(), Box<dyn Error>> {
+        let response: GenerateContentResponse = serde_json::from_str(data)?;
+        if let Some(error) = response.error {
+            return Err(Box::new(io::Error::other(format!("Error from gemini api ({}): {}", error.status, error.message))));
+        }
+        if response.response_id.is_some() {
+            self.response_id = response.response_id;
+        }
+        let candidate = response.candidates.into_iter().next();
+        if let Some(finish_reason) = candidate.as_ref().and_then(|candidate| candidate.finish_reason.clone()) {
+            self.finish_reason = Some(finish_reason);
+        }
+        let parts = candidate
+            .and_then(|candidate| candidate.content)
+            .map(|content| content.parts)
+            .unwrap_or_default();
+        for part in parts.into_iter().filter(|part| part.thought != Some(true)) {
+            if !self.started {
+                on_event(StreamEvent::Role(Role::Assistant.as_str()));
+                self.started = true;
+            }
+            if let Some(text) = part.text {
+                on_event(StreamEvent::Content(&text));
+                self.content.push_str(&text);
+            }
+            // function calls are never split over several events, and come without an id
+            if let Some(call) = part.function_call {
+                let arguments = call.args.to_string();
+                on_event(StreamEvent::ToolCall(&call.name));
+                on_event(StreamEvent::ToolArguments(&arguments));
+                let id = format!("call_{}", tree::new_id());
+                self.tool_calls.push(ToolCall::new(&id, FunctionCall { name: call.name, arguments }));
+            }
+        }
+        // every event repeats the usage so far
+        if let Some(metadata) = response.usage_metadata {
+            self.usage = Some(Usage {
+                prompt_tokens: metadata.prompt_token_count,
+                completion_tokens: metadata.candidates_token_count,
+                total_tokens: metadata.prompt_token_count + metadata.candidates_token_count,
+                prompt_tokens_details: Some(PromptTokensDetails { cached_tokens: metadata.cached_content_token_count }),
+            });
+        }
+        Ok(())
+    }
+}
+
+pub struct GeminiProvider {
+    client: reqwest::Client,
+    api_key: String,
+    base_url: String,
+}
+
+impl GeminiProvider {
+    pub fn new(client: reqwest::Client, settings: &ProviderSettings) -> Result<GeminiProvider, Box<dyn Error>> {
+        Ok(GeminiProvider {
+            client,
+            api_key: settings.api_key(API_KEY_ENV)?,
+            base_url: settings.base_url(BASE_URL).to_string(),
+        })
+    }
+
+    async fn post(&self, path: &str, request: ChatRequest) -> Result<reqwest::Response, Box<dyn Error>> {
+        let url = format!("{}/models/{}:{path}", self.base_url, request.model);
+        let body_str = serde_json::to_string(&to_request(request))?;
+        log::debug!("POST {url} with message: {body_str}");
+        let response = self.client.post(url)
+            .header("Content-Type", "application/json")
+            .header("x-goog-api-key", &self.api_key)
+            .body(body_str)
+            .send().await?;
+        if response.status() != 200 {
+            let status = response.status();
+            return Err(provider::api_error(NAME, status, &response.text().await?));
+        }
+        Ok(response)
+    }
+}
+
+#[async_trait(?Send)]
+impl ChatProvider for GeminiProvider {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    async fn chat(&self, request: ChatRequest, on_event: &mut dyn FnMut(StreamEvent)) -> Result<ChatResponse, Box<dyn Error>> {
+        let mut response = self.post("streamGenerateContent?alt=sse", request).await?;
+        let mut decoder = StreamDecoder::default();
+        while let Some(chunk) = response.chunk().await? {
+            log::debug!("Parsing chunk:'{}'", String::from_utf8_lossy(chunk.as_ref()));
+            decoder.feed(&chunk, on_event)?;
+        }
+        decoder.finish(on_event)
+    }
+
+    async fn complete(&self, request: ChatRequest) -> Result<ChatResponse, Box<dyn Error>> {
+        let response = self.post("generateContent", request).await?;
+        let body = response.text().await?;
+        let mut decoder = StreamDecoder::default();
+        decoder.decode(&body, &mut |_| {})?;
+        decoder.finish(&mut |_| {})
+    }
+
+    async fn list_models(&self) -> Result<Vec<Model>, Box<dyn Error>> {
+        let mut models = vec![];
+        let mut page_token: Option<String> = None;
+        loop {
+            let url = format!("{}/models", self.base_url);
+            log::debug!("GET {url}");
+            let mut query = vec![("pageSize", "1000")];
+            if let Some(token) = &page_token {
+                query.push(("pageToken", token));
+            }
+            let response = self.client.get(url)
+                .query(&query)
+                .header("x-goog-api-key", &self.api_key)
+                .send().await?;
+            if response.status() != 200 {
+                let status = response.status();
+                return Err(provider::api_error(NAME, status, &response.text().await?));
+            }
+            let model_list: GeminiModelList = response.json().await?;
+            models.extend(model_list.models.into_iter()
+                // embedding and other models can not be used for chat
+                .filter(|model| model.supported_generation_methods.iter().any(|method| method == "generateContent"))
+                .map(|model| Model {
+                    id: model.name.trim_start_matches("models/").to_string(),
+                    object: "model".to_string(),
+                    created: 0,
+                    owned_by: "google".to_string(),
+                }));
+            page_token = model_list.next_page_token.filter(|token| !token.is_empty());
+            if page_token.is_none() {
+                return Ok(models);
+            }
+        }
+    }
+}
@@ -1,2 +1,7 @@
+pub mod anthropic;
+pub mod chat;
+pub mod gemini;
+pub mod ollama;
 pub mod openai;
 pub mod powershell;
+pub mod provider;
@@ -1,85 +1,1032 @@
 use std::env;
 use std::collections::HashMap;
 use std::error::Error;
-use std::io::{self, Read};
-use rustgpt::openai::{self, ChatHistory, config::Settings};
-use rustgpt::openai::config;
+use std::io::{self, IsTerminal, Read, Write};
+use rustgpt::chat;
+use rustgpt::ollama::OllamaProvider;
+use std::fs;
+use std::path::Path;
+use rustgpt::openai::{ChatHistory, ContentPart, JsonSchema, Message, Messages, Metadata, ResponseFormat, Role, config::Settings};
+use rustgpt::openai::{attachments, catalog, config, context, export, files, images, prompts, rag};
+use rustgpt::openai::export::Format;
+use rustgpt::openai::{import, search, sessions, tree};
+use rustgpt::openai::search::{Filter, SearchIndex, SessionFile};
+use rustgpt::openai::sessions::SessionInfo;
+use rustgpt::openai::store::{self, Backend};
+use rustgpt::openai::transcript::{self, Selection, Stats};
+use rustgpt::openai::embeddings::{EmbeddingOptions, EncodingFormat};
+use serde::Serialize;
+use rustgpt::openai::usage::{self, GroupBy};
 use rustgpt::powershell;
+use rustgpt::provider;
 
-async fn models() -> Result<(), Box<dyn Error>> {
-    let client = reqwest::Client::new();
-    let openai_api_key = env::var("OPENAI_API_KEY").unwrap();
-    let models_response = openai::print_models(&openai_api_key, &client);
-    println!("{:?}", models_response.await);
+fn invalid_input(msg: String) -> Box<dyn Error> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+// removes `name value` from the arguments and returns the value
+fn take_option<'a>(args: &mut Vec<&'a str>, name: &str) -> Result<Option<&'a str>, Box<dyn Error>> {
+    let Some(index) = args.iter().position(|arg| *arg == name) else {
+        return Ok(None);
+    };
+    if index + 1 >= args.len() {
+        return Err(invalid_input(format!("Missing value for {name}")));
+    }
+    let value = args.remove(index + 1);
+    args.remove(index);
+    Ok(Some(value))
+}
+
+// removes every `name value` from the arguments, for options that can be repeated
+fn take_options<'a>(args: &mut Vec<&'a str>, name: &str) -> Result<Vec<&'a str>, Box<dyn Error>> {
+    let mut values = vec![];
+    while let Some(value) = take_option(args, name)? {
+        values.push(value);
+    }
+    Ok(values)
+}
+
+// stores the images of `--image PATH`, scaled down to `--max-size PIXELS` and sent with `--detail low|high|auto`
+fn take_images<'a>(settings: &Settings, args: &mut Vec<&'a str>) -> Result<Vec<(&'a str, ContentPart)>, Box<dyn Error>> {
+    let paths = take_options(args, "--image")?;
+    let detail = take_option(args, "--detail")?;
+    let max_size = match take_option(args, "--max-size")? {
+        Some(value) => Some(value.parse().map_err(|_| invalid_input(format!("Invalid --max-size value '{value}'")))?),
+        None => None,
+    };
+    paths.into_iter()
+        .map(|path| Ok((path, images::store(settings.images_dir(), path, max_size, detail)?)))
+        .collect()
+}
+
+// renders the prompt of `--persona NAME`, or the default prompt of the command, with the `-v key=value` variables
+fn take_system_prompt(settings: &Settings, args: &mut Vec<&str>, default: &str) -> Result<String, Box<dyn Error>> {
+    let name = take_option(args, "--persona")?.unwrap_or(default);
+    let variables = take_options(args, "-v")?.into_iter()
+        .map(prompts::parse_variable)
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    let prompt = prompts::find(settings.prompts_dir(), name)?;
+    // template files usually end with a newline
+    Ok(prompts::render(&prompt.template, &variables)?.trim().to_string())
+}
+
+fn take_flag(args: &mut Vec<&str>, name: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| *arg != name);
+    args.len() != len
+}
+
+// applies `--model NAME` to a copy of the settings, after checking that the model exists
+async fn take_model_option(settings: &Settings, args: &mut Vec<&str>) -> Result<Settings, Box<dyn Error>> {
+    let mut settings = settings.clone();
+    if let Some(model) = take_option(args, "--model")? {
+        let provider = provider::from_settings(&settings)?;
+        catalog::validate_model(provider.as_ref(), &settings, model).await?;
+        settings.set_model(model);
+    }
+    Ok(settings)
+}
+
+// applies `--session NAME` to a copy of the settings
+fn take_session_option(settings: &Settings, args: &mut Vec<&str>) -> Result<Settings, Box<dyn Error>> {
+    let mut settings = settings.clone();
+    if let Some(session) = take_option(args, "--session")? {
+        settings.set_session(session)?;
+    }
+    Ok(settings)
+}
+
+fn format_flag(flag: Option<bool>) -> &'static str {
+    match flag {
+        Some(true) => "yes",
+        Some(false) => "no",
+        None => "?",
+    }
+}
+
+// passes model management on to the local ollama server
+async fn ollama_models(settings: &Settings, command: &str, model: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let model = model.ok_or_else(|| invalid_input(format!("Missing model name for models {command}")))?;
+    let ollama = OllamaProvider::from_settings(settings);
+    match command {
+        "pull" => {
+            let mut last_status = String::new();
+            ollama.pull(model, &mut |status| {
+                match (status.completed, status.total) {
+                    (Some(completed), Some(total)) if total > 0 => {
+                        print!("\r{} {:>3}%", status.status, completed * 100 / total);
+                        let _ = io::stdout().flush();
+                    }
+                    _ if status.status != last_status => println!("\r{}", status.status),
+                    _ => {}
+                }
+                last_status = status.status.clone();
+            }).await
+        }
+        "rm" => {
+            ollama.delete(model).await?;
+            println!("deleted {model}");
+            Ok(())
+        }
+        _ => {
+            println!("{}", serde_json::to_string_pretty(&ollama.show(model).await?)?);
+            Ok(())
+        }
+    }
+}
+
+async fn models(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    if let Some(command @ ("pull" | "rm" | "show")) = args.first().copied() {
+        return ollama_models(settings, command, args.get(1).copied()).await;
+    }
+    let mut args = args.to_vec();
+    let json = take_flag(&mut args, "--json");
+    let refresh = take_flag(&mut args, "--refresh");
+    let owner = take_option(&mut args, "--owner")?;
+    let filter = args.first().copied();
+
+    let provider = provider::from_settings(settings)?;
+    let mut models = catalog::get_models(provider.as_ref(), settings, refresh).await?
+        .into_iter()
+        .filter(|model| catalog::matches(model, filter, owner))
+        .map(|model| catalog::model_info(settings, model))
+        .collect::<Vec<_>>();
+    models.sort_by(|a, b| a.model.id.cmp(&b.model.id));
+    if json {
+        println!("{}", serde_json::to_string_pretty(&models)?);
+        return Ok(());
+    }
+    let id_width = models.iter().map(|info| info.model.id.len()).max().unwrap_or(0).max(5);
+    println!("{:<id_width$} {:<16} {:<10} {:>9} {:>5} {:>6} {:>16}", "model", "owner", "created", "context", "tools", "vision", "$/1M in/out");
+    for info in &models {
+        let created = chrono::DateTime::from_timestamp(info.model.created as i64, 0)
+            .map(|created| created.date_naive().to_string())
+            .unwrap_or_default();
+        let context = info.capabilities.context_length.map(|len| len.to_string()).unwrap_or("?".into());
+        let price = info.price.as_ref()
+            .map(|price| format!("{}/{}", price.prompt, price.completion))
+            .unwrap_or("?".into());
+        println!("{:<id_width$} {:<16} {:<10} {:>9} {:>5} {:>6} {:>16}", info.model.id, info.model.owned_by, created, context,
+                 format_flag(info.capabilities.tools), format_flag(info.capabilities.vision), price);
+    }
     Ok(())
 }
 
-async fn chat<'a>(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
-    let input = args.join(" ");
+async fn chat(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let settings = &take_session_option(settings, &mut args)?;
+    let settings = &take_model_option(settings, &mut args).await?;
+    let images = take_images(settings, &mut args)?;
+    let rag_root = take_option(&mut args, "--rag")?;
+    let top_k = match take_option(&mut args, "--top-k")? {
+        Some(value) => value.parse().map_err(|_| invalid_input(format!("Invalid --top-k value '{value}'")))?,
+        None => rag::DEFAULT_TOP_K,
+    };
+    let mut system = take_system_prompt(settings, &mut args, prompts::CHAT_PROMPT)?;
+    let mut input = args.join(" ");
 
-    let mut conversation = settings.get_history()?;
-    conversation.set_system_message("");
-    conversation.add_user_message(&input);
+    let (mut conversation, snapshot) = settings.get_branch()?;
+    let mut citations = vec![];
+    if let Some(root) = rag_root {
+        let (context, sources) = retrieve(settings, root, &input, top_k).await?;
+        system = if system.trim().is_empty() { rag::SYSTEM_PROMPT.to_string() } else { format!("{system}\n\n{}", rag::SYSTEM_PROMPT) };
+        input = context;
+        citations = sources;
+    }
+    if images.is_empty() {
+        conversation.add_user_message(&input);
+    } else {
+        let mut parts = vec![ContentPart::Text { text: input }];
+        parts.extend(images.into_iter().map(|(_, image)| image));
+        conversation.push(Message::with_parts(Role::User, parts));
+    }
     print!("{}", conversation);
 
-    let httpclient = reqwest::Client::new();
-    let openai_api_key = env::var("OPENAI_API_KEY").unwrap();
-    let completion = openai::get_next(&openai_api_key, &httpclient, conversation);
+    let provider = provider::from_settings(settings)?;
+    let completion = chat::get_next(provider.as_ref(), settings, conversation, &system);
     conversation = completion.await?;
-    settings.write_history(conversation)
+    if !citations.is_empty() {
+        println!();
+        for (number, citation) in citations.iter().enumerate() {
+            println!("[{}] {citation}", number + 1);
+        }
+    }
+    settings.save_branch(&snapshot, &conversation)
 }
 
-async fn pwsh<'a>(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+async fn pwsh(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let settings = &take_model_option(settings, &mut args).await?;
+    let system = take_system_prompt(settings, &mut args, prompts::POWERSHELL_PROMPT)?;
     let input = args.join(" ");
 
-    let mut conversation = settings.get_history()?;
-    conversation.set_system_message(" You are a machine translating human commands to powershell commands.\
-        These powershell commands can be returned as function calls.\
-        You can also ask the user for more information.\
-        If the function could do something dangerous always ask the user if the command should be run."
-    );
+    let (mut conversation, snapshot) = settings.get_branch()?;
     conversation.add_user_message(&input);
     println!("{}", conversation);
 
-    let httpclient = reqwest::Client::new();
-    let openai_api_key = env::var("OPENAI_API_KEY").unwrap();
-    let completion = openai::get_next_powershell_command(&openai_api_key, &httpclient, conversation);
+    let provider = provider::from_settings(settings)?;
+    let completion = chat::get_next_powershell_command(provider.as_ref(), settings, conversation, &system);
     conversation = completion.await?;
-    if let Some(msg) = conversation.last() {
-        if let Some(function_call) = &msg.function_call {
-            if let Some(cmd) = serde_json::from_str::<HashMap<String, String>>(&function_call.arguments)?.get("command") {
-                let output = powershell::run_command(cmd);
-                let function_name = function_call.name.clone();
-                conversation.add_powershell_message(&function_name, &output);
-                let pwsh_response = conversation.last().unwrap();
-                print!("{}", pwsh_response);
-            }
+    run_tool_calls(&mut conversation);
+    settings.save_branch(&snapshot, &conversation)
+}
+
+// runs the commands the last reply asks for, every tool call needs a result, otherwise the next request is rejected
+fn run_tool_calls(conversation: &mut Messages) {
+    let tool_calls = conversation.last().and_then(|msg| msg.tool_calls.clone()).unwrap_or_default();
+    for tool_call in tool_calls {
+        let arguments = serde_json::from_str::<HashMap<String, String>>(&tool_call.function.arguments).unwrap_or_default();
+        let output = match arguments.get("command") {
+            Some(cmd) => powershell::run_command(cmd),
+            None => format!("No command given to {}", tool_call.function.name),
+        };
+        conversation.add_tool_message(&tool_call.id, &output);
+        print!("{}", conversation.last().unwrap());
+    }
+}
+
+// the number of the branch ending at `leaf` in `branch list`, None when it ends before the last message
+fn branch_number(history: &Messages, leaf: &Option<String>) -> Option<usize> {
+    tree::branches(&history.0).iter()
+        .position(|branch| branch.last().is_some_and(|position| history.0[*position].id == *leaf))
+        .map(|index| index + 1)
+}
+
+// prints the numbered messages of the active branch, `--raw` only the text of the last reply
+async fn print_conversation(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let settings = &take_session_option(settings, &mut args)?;
+    let verbose = take_flag(&mut args, "--verbose");
+    let raw = take_flag(&mut args, "--raw");
+    let json = take_flag(&mut args, "--json");
+    let stats = take_flag(&mut args, "--stats");
+    let mut selection = Selection {
+        no_functions: take_flag(&mut args, "--no-functions"),
+        ..Default::default()
+    };
+    if take_flag(&mut args, "--system") {
+        selection.roles.push(Role::System);
+    }
+    for value in take_options(&mut args, "--role")? {
+        let role = transcript::parse_role(value)
+            .ok_or_else(|| invalid_input(format!("Invalid --role value '{value}', expected system, user, assistant or function")))?;
+        selection.roles.push(role);
+    }
+    if let Some(value) = take_option(&mut args, "--range")? {
+        selection.range = Some(transcript::parse_range(value)
+            .ok_or_else(|| invalid_input(format!("Invalid --range value '{value}', expected numbers like 3..7, 3..=7, 3.. or ..7")))?);
+    }
+    if let Some(value) = take_option(&mut args, "--last")? {
+        selection.last = Some(value.parse().map_err(|_| invalid_input(format!("Invalid --last value '{value}'")))?);
+    }
+    if let Some(arg) = args.first() {
+        return Err(invalid_input(format!("Unknown argument {arg}")));
+    }
+    let (conversation, _) = settings.get_branch()?;
+    let selected = selection.apply(&conversation.0);
+    if raw {
+        let reply = selected.iter().rev()
+            .find(|numbered| numbered.message.role == Role::Assistant)
+            .ok_or_else(|| invalid_input("No assistant message to print".to_string()))?;
+        println!("{}", reply.message.text());
+        return Ok(());
+    }
+    if stats {
+        let stats = Stats::new(&selected);
+        if json {
+            println!("{}", serde_json::to_string_pretty(&stats)?);
+        } else {
+            print!("{stats}");
         }
+        return Ok(());
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&selected)?);
+        return Ok(());
     }
-    settings.write_history(conversation)
+    let history = settings.get_history()?;
+    let branches = tree::branches(&history.0).len();
+    if branches > 1 {
+        match conversation.last().and_then(|leaf| branch_number(&history, &leaf.id)) {
+            Some(number) => println!("branch {number} of {branches}"),
+            None => println!("branch ending at message {}, {branches} branches in total", conversation.0.len()),
+        }
+    }
+    for numbered in &selected {
+        if let Some(metadata) = numbered.message.metadata.as_ref().filter(|_| verbose) {
+            println!("[{metadata}]");
+        }
+        println!("{numbered}");
+    }
+    Ok(())
 }
 
-async fn print_conversation<'a>(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
-    let conversation = settings.get_history()?;
-    if args.contains(&"--system") {
-        for msg in conversation.get_system_messages() {
-            println!("{}", msg);
+// writes the conversation as markdown, html, json or fine-tuning data to stdout or `--output FILE`
+fn export(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let settings = &take_session_option(settings, &mut args)?;
+    let format = match take_option(&mut args, "--format")? {
+        None => Format::Markdown,
+        Some(value) => Format::parse(value)
+            .ok_or_else(|| invalid_input(format!("Invalid --format value '{value}', expected md, html, json or jsonl-finetune")))?,
+    };
+    let output = take_option(&mut args, "--output")?;
+    if let Some(arg) = args.first() {
+        return Err(invalid_input(format!("Unknown argument {arg}")));
+    }
+    let (mut conversation, _) = settings.get_branch()?;
+    if conversation.0.is_empty() {
+        return Err(invalid_input(format!("Session {} has no messages", settings.session_name())));
+    }
+    let title = settings.session_name();
+    let exported = match format {
+        Format::Markdown => export::markdown(&conversation, &title, settings.images_dir()),
+        Format::Html => {
+            images::resolve(&mut conversation, settings.images_dir())?;
+            export::html(&conversation, &title)
+        }
+        Format::Json => serde_json::to_string_pretty(&conversation)? + "\n",
+        Format::FinetuneJsonl => {
+            images::resolve(&mut conversation, settings.images_dir())?;
+            export::finetune_jsonl(&conversation, &powershell::functions())? + "\n"
+        }
+    };
+    match output {
+        Some(path) => fs::write(path, exported)?,
+        None => print!("{exported}"),
+    }
+    Ok(())
+}
+
+// creates a session for every conversation of an export, importing again replaces the sessions
+fn import(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let names = || import::importers().iter().map(|importer| importer.name()).collect::<Vec<_>>().join(", ");
+    let (name, path) = match args {
+        [name, path] => (*name, Path::new(*path)),
+        _ => return Err(invalid_input(format!("Expected import FORMAT PATH, the formats are {}", names()))),
+    };
+    let importer = import::find(name).ok_or_else(|| invalid_input(format!("Unknown import format '{name}', expected {}", names())))?;
+    let conversations = importer.import(path)?;
+    let mut imported = 0;
+    for conversation in conversations {
+        if conversation.messages.0.is_empty() {
+            log::info!("Skipping empty conversation {}", conversation.id);
+            continue;
+        }
+        let session = sessions::session_name(name, &conversation.title, &conversation.id);
+        let mut session_settings = settings.clone();
+        session_settings.set_session(&session)?;
+        let _lock = session_settings.lock_history()?;
+        session_settings.write_history(conversation.messages)?;
+        session_settings.write_session_info(&SessionInfo {
+            title: Some(conversation.title),
+            created_at: conversation.created_at,
+            updated_at: conversation.updated_at,
+            source: Some(format!("{name}:{}", conversation.id)),
+            branch: None,
+        })?;
+        println!("{session}");
+        imported += 1;
+    }
+    println!("Imported {imported} conversations");
+    Ok(())
+}
+
+// finds the messages of all sessions containing every word of the query
+fn search(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let role = take_option(&mut args, "--role")?
+        .map(|value| Role::parse(value).ok_or_else(|| invalid_input(format!("Invalid --role value '{value}'"))))
+        .transpose()?;
+    let since = take_option(&mut args, "--since")?.map(usage::parse_since).transpose()?;
+    let session = take_option(&mut args, "--session")?;
+    let query = args.join(" ");
+    if search::tokenize(&query).is_empty() {
+        return Err(invalid_input("Expected a search query".to_string()));
+    }
+
+    let mut session_files = vec![];
+    for (name, path) in settings.sessions()? {
+        let mut session_settings = settings.clone();
+        session_settings.set_session(&name)?;
+        let timestamp = session_settings.session_info()?.updated_at;
+        session_files.push(SessionFile { name, path, timestamp });
+    }
+    let mut index = SearchIndex::load(settings.search_index_file())?;
+    if index.update(&session_files)? {
+        index.save(settings.search_index_file())?;
+    }
+    let filter = Filter { role, since, session };
+    let results = index.search(&query, &filter);
+    let (open, close) = if io::stdout().is_terminal() { ("\x1b[1;33m", "\x1b[0m") } else { ("**", "**") };
+    for result in &results {
+        let timestamp = result.timestamp.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M");
+        println!("{} {timestamp} #{} {}: {}", result.session, result.document.message + 1, result.document.role,
+                 search::snippet(&result.document.text, &query, open, close));
+    }
+    if results.is_empty() {
+        eprintln!("No messages found");
+    }
+    Ok(())
+}
+
+// moves the history of every session to another backend, new sessions use it too
+fn migrate(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let backend = match args {
+        [value] => Backend::parse(value).ok_or_else(|| invalid_input(format!("Invalid backend '{value}', expected json, jsonl or sqlite")))?,
+        _ => return Err(invalid_input("Expected migrate json|jsonl|sqlite".to_string())),
+    };
+    let mut migrated = 0;
+    for (name, path) in settings.sessions()? {
+        if Backend::from_path(&path) == backend {
+            continue;
+        }
+        let target = store::migrate(&path, backend)?;
+        println!("{name}: {} -> {}", path.display(), target.display());
+        migrated += 1;
+    }
+    let mut settings = settings.clone();
+    let history_file = Path::new(settings.history_file()).with_extension(backend.extension());
+    settings.set_history_file(&history_file.to_string_lossy());
+    settings.save()?;
+    println!("Migrated {migrated} sessions to {}", backend.extension());
+    Ok(())
+}
+
+async fn print_usage(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let since = take_option(&mut args, "--since")?.map(usage::parse_since).transpose()?;
+    let group_by = match take_option(&mut args, "--by")? {
+        None => GroupBy::Model,
+        Some(value) => GroupBy::parse(value)
+            .ok_or_else(|| invalid_input(format!("Invalid --by value '{value}', expected model, session or day")))?,
+    };
+    if let Some(arg) = args.first() {
+        return Err(invalid_input(format!("Unknown argument {arg}")));
+    }
+    let records = usage::read_records(settings.usage_file())?;
+    let totals = usage::totals(&records, settings.prices(), since, group_by);
+    println!("{:<30} {:>8} {:>12} {:>12} {:>12} {:>10}", "", "requests", "prompt", "cached", "completion", "cost");
+    let mut total_cost = 0.0;
+    let mut unpriced = false;
+    for (key, totals) in &totals {
+        let marker = if totals.unpriced > 0 { "*" } else { "" };
+        println!("{:<30} {:>8} {:>12} {:>12} {:>12} {:>9.4}{marker}", key, totals.requests, totals.prompt_tokens, totals.cached_tokens, totals.completion_tokens, totals.cost);
+        total_cost += totals.cost;
+        unpriced |= totals.unpriced > 0;
+    }
+    println!("total cost: ${total_cost:.4}");
+    if unpriced {
+        println!("* includes requests to models without a configured price");
+    }
+    Ok(())
+}
+
+// pinned messages are always sent to the api, regardless of the context strategy
+fn pin_message(settings: &Settings, args: &[&str], pinned: bool) -> Result<(), Box<dyn Error>> {
+    let number = match args.first().map(|arg| arg.parse::<usize>()) {
+        Some(Ok(number)) if number > 0 => number,
+        _ => Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, "Expected a message number starting at 1")))?,
+    };
+    let (mut conversation, snapshot) = settings.get_branch()?;
+    match conversation.0.get_mut(number - 1) {
+        Some(msg) => msg.pinned = pinned,
+        None => Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, format!("No message with number {number}"))))?,
+    }
+    settings.save_branch(&snapshot, &conversation)
+}
+
+fn take_embedding_options(args: &mut Vec<&str>, options: &mut EmbeddingOptions) -> Result<(), Box<dyn Error>> {
+    if let Some(model) = take_option(args, "--model")? {
+        options.model = model.to_string();
+    }
+    if let Some(value) = take_option(args, "--dimensions")? {
+        options.dimensions = Some(value.parse().map_err(|_| invalid_input(format!("Invalid --dimensions value '{value}'")))?);
+    }
+    if let Some(value) = take_option(args, "--batch")? {
+        options.batch_size = value.parse().map_err(|_| invalid_input(format!("Invalid --batch value '{value}'")))?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct EmbeddingLine<'a> {
+    source: &'a str,
+    // set when every line of a file is embedded on its own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    text: &'a str,
+    embedding: &'a [f32],
+}
+
+// embeds every file, every line of the files with `--lines`, or every line of stdin, and writes json lines
+async fn embed(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let mut options = EmbeddingOptions::default();
+    take_embedding_options(&mut args, &mut options)?;
+    options.encoding_format = match take_option(&mut args, "--encoding")? {
+        None | Some("float") => EncodingFormat::Float,
+        Some("base64") => EncodingFormat::Base64,
+        Some(value) => return Err(invalid_input(format!("Invalid --encoding value '{value}', expected float or base64"))),
+    };
+    let output = take_option(&mut args, "--output")?;
+    let lines = take_flag(&mut args, "--lines");
+
+    // (source, line, text)
+    let mut inputs: Vec<(String, Option<usize>, String)> = vec![];
+    if args.is_empty() {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        inputs.extend(contents.lines().enumerate().map(|(index, line)| ("stdin".to_string(), Some(index + 1), line.to_string())));
+    }
+    for path in args {
+        let contents = fs::read_to_string(path)?;
+        if lines {
+            inputs.extend(contents.lines().enumerate().map(|(index, line)| (path.to_string(), Some(index + 1), line.to_string())));
+        } else {
+            inputs.push((path.to_string(), None, contents));
         }
-    } else {
-        print!("{}", conversation);
     }
+    // the api rejects empty inputs
+    inputs.retain(|(_, _, text)| !text.trim().is_empty());
+
+    let provider = provider::from_settings(settings)?;
+    let texts = inputs.iter().map(|(_, _, text)| text.clone()).collect::<Vec<_>>();
+    let embeddings = provider.embed(&texts, &options).await?;
+    chat::record_usage(settings, &options.model, Some(embeddings.usage));
+
+    let mut writer: Box<dyn Write> = match output {
+        Some(path) => Box::new(io::BufWriter::new(fs::File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    for ((source, line, text), embedding) in inputs.iter().zip(&embeddings.vectors) {
+        let record = EmbeddingLine { source, line: *line, text, embedding };
+        writeln!(writer, "{}", serde_json::to_string(&record)?)?;
+    }
+    Ok(())
+}
+
+// chunks and embeds the text files of a directory, unchanged files keep their embeddings
+async fn index(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let keep_options = !args.iter().any(|arg| matches!(*arg, "--model" | "--dimensions"));
+    let mut options = EmbeddingOptions::default();
+    take_embedding_options(&mut args, &mut options)?;
+    let chunk_size = take_option(&mut args, "--chunk-size")?
+        .map(|value| value.parse::<usize>().map_err(|_| invalid_input(format!("Invalid --chunk-size value '{value}'"))))
+        .transpose()?;
+    let root = match args.as_slice() {
+        [root] => Path::new(*root),
+        _ => return Err(invalid_input("Expected the directory to index".to_string())),
+    };
+    let path = rag::index_path(settings.index_dir(), root)?;
+    let existing = rag::Index::load(&path)?;
+    // the embedding options of an earlier run are kept unless they are given again
+    if let Some(index) = existing.as_ref().filter(|_| keep_options) {
+        options.model = index.model.clone();
+        options.dimensions = index.dimensions;
+    }
+    let chunk_size = chunk_size.or(existing.as_ref().map(|index| index.chunk_size)).unwrap_or(rag::DEFAULT_CHUNK_SIZE);
+    let mut index = match existing {
+        Some(index) if index.model == options.model && index.dimensions == options.dimensions && index.chunk_size == chunk_size => index,
+        Some(_) => {
+            eprintln!("The embedding options changed, indexing all files again");
+            rag::Index::new(root, &options, chunk_size)?
+        }
+        None => rag::Index::new(root, &options, chunk_size)?,
+    };
+
+    let provider = provider::from_settings(settings)?;
+    let stats = index.update(provider.as_ref(), &options).await?;
+    if stats.chunks > 0 {
+        chat::record_usage(settings, &options.model, Some(stats.usage));
+    }
+    index.save(&path)?;
+    println!("{} added, {} changed, {} unchanged, {} removed, {} chunks embedded",
+             stats.added, stats.changed, stats.unchanged, stats.removed, stats.chunks);
+    Ok(())
+}
+
+// embeds the question and returns it with the closest chunks of the index of root as numbered sources before it
+async fn retrieve(settings: &Settings, root: &str, question: &str, top_k: usize) -> Result<(String, Vec<String>), Box<dyn Error>> {
+    let path = rag::index_path(settings.index_dir(), Path::new(root))?;
+    let index = rag::Index::load(&path)?
+        .ok_or_else(|| invalid_input(format!("{root} is not indexed, run `rustgpt index {root}` first")))?;
+    let options = index.embedding_options();
+    let provider = provider::from_settings(settings)?;
+    let embedded = provider.embed(&[question.to_string()], &options).await?;
+    chat::record_usage(settings, &options.model, Some(embedded.usage));
+    let query = embedded.vectors.into_iter().next().unwrap_or_default();
+    let hits = index.search(&query, top_k);
+    for hit in &hits {
+        log::debug!("Retrieved {} with similarity {:.3}", hit.citation(), hit.score);
+    }
+    Ok((rag::format_context(&hits, question), hits.iter().map(rag::Hit::citation).collect()))
+}
+
+// the schema name may only contain letters, digits, underscores and dashes
+fn schema_name(path: &str) -> String {
+    let stem = Path::new(path).file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+    let name = stem.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .take(64)
+        .collect::<String>();
+    if name.is_empty() { "extract".to_string() } else { name }
+}
+
+// prints the information in the arguments or stdin as json, without a schema any json object is accepted
+async fn extract(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let settings = &take_model_option(settings, &mut args).await?;
+    let schema_file = take_option(&mut args, "--schema")?;
+    let retries = match take_option(&mut args, "--retries")? {
+        Some(value) => value.parse().map_err(|_| invalid_input(format!("Invalid --retries value '{value}'")))?,
+        None => 2,
+    };
+    let strict = !take_flag(&mut args, "--no-strict");
+    let response_format = match schema_file {
+        Some(path) => ResponseFormat::JsonSchema {
+            json_schema: JsonSchema {
+                name: schema_name(path),
+                schema: serde_json::from_str(&fs::read_to_string(path)?)?,
+                strict,
+            },
+        },
+        None => ResponseFormat::JsonObject,
+    };
+    let input = if args.is_empty() {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        input
+    } else {
+        args.join(" ")
+    };
+
+    let provider = provider::from_settings(settings)?;
+    let value = chat::extract(provider.as_ref(), settings, &input, response_format, retries).await?;
+    println!("{}", serde_json::to_string_pretty(&value)?);
     Ok(())
 }
 
+fn add_image_file(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let images = take_images(settings, &mut args)?;
+    if let Some(arg) = args.first() {
+        return Err(invalid_input(format!("Unknown argument {arg}")));
+    }
+    // like text files, every image is introduced by its file name
+    let mut parts = vec![];
+    for (path, image) in images {
+        parts.push(ContentPart::Text { text: format!("{path}:") });
+        parts.push(image);
+    }
+    let (mut conversation, snapshot) = settings.get_branch()?;
+    conversation.push(Message::with_parts(Role::User, parts));
+    settings.save_branch(&snapshot, &conversation)
+}
+
 async fn add_file_from_stdin(file_name: &str, settings: &Settings) -> Result<(), Box<dyn Error>> {
     let mut contents = String::new();
     io::stdin().read_to_string(&mut contents)?;
     let mut file_name_line = ":\n".to_string();
     file_name_line.insert_str(0, file_name);
     contents.insert_str(0, &file_name_line);
-    let mut conversation = settings.get_history()?;
+    let (mut conversation, snapshot) = settings.get_branch()?;
     conversation.add_user_message(&contents);
-    Ok(settings.write_history(conversation)?)
+    settings.save_branch(&snapshot, &conversation)
+}
+
+// attaches every file as a user message with its path and contents in a code fence,
+// the files are read again before every request
+async fn add_files(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    if args.contains(&"--image") {
+        return add_image_file(settings, args);
+    }
+    let mut args = args.to_vec();
+    if let Some(name) = take_option(&mut args, "--stdin")? {
+        return add_file_from_stdin(name, settings).await;
+    }
+    let max_bytes = match take_option(&mut args, "--max-bytes")? {
+        Some(value) => value.parse().map_err(|_| invalid_input(format!("Invalid --max-bytes value '{value}'")))?,
+        None => files::DEFAULT_MAX_FILE_SIZE,
+    };
+    if args.is_empty() {
+        return Err(invalid_input("Expected files, directories or globs, or --stdin NAME".to_string()));
+    }
+    let mut attached = vec![];
+    for path in files::expand(&args)? {
+        let path = path.to_string_lossy().replace('\\', "/");
+        match attachments::attach(&path, max_bytes)? {
+            Some(msg) => attached.push(msg),
+            None => eprintln!("Skipping {path}, it is binary or larger than {max_bytes} bytes"),
+        }
+    }
+    if attached.is_empty() {
+        return Err(invalid_input("No text files to attach".to_string()));
+    }
+    let mut total = 0;
+    for msg in &attached {
+        let tokens = context::estimate_tokens(msg);
+        total += tokens;
+        println!("{:>8} {}", tokens, msg.attachment.as_ref().map_or("", |attachment| attachment.path.as_str()));
+    }
+    println!("{total:>8} tokens estimated for {} files", attached.len());
+    let (mut conversation, snapshot) = settings.get_branch()?;
+    conversation.0.extend(attached);
+    settings.save_branch(&snapshot, &conversation)
+}
+
+// lists the attached files or removes them, by path or message number, from the conversation
+fn attach(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    match args {
+        [] | ["list"] => {
+            let (mut current, _) = settings.get_branch()?;
+            for change in attachments::refresh(&mut current) {
+                println!("{:>4} {change}", change.index + 1);
+            }
+            Ok(())
+        }
+        ["rm", targets @ ..] if !targets.is_empty() => {
+            let _lock = settings.lock_history()?;
+            // numbers are positions in the active branch, paths are removed from every branch
+            let (branch, _) = settings.get_branch()?;
+            let ids = targets.iter()
+                .filter_map(|target| target.parse::<usize>().ok())
+                .filter_map(|number| branch.0.get(number.checked_sub(1)?))
+                .filter(|msg| msg.attachment.is_some())
+                .filter_map(|msg| msg.id.clone())
+                .collect::<Vec<_>>();
+            let mut conversation = settings.get_history()?;
+            let removed = tree::remove(&mut conversation, |msg| {
+                msg.attachment.as_ref().is_some_and(|attachment| targets.contains(&attachment.path.as_str()))
+                    || msg.id.as_ref().is_some_and(|id| ids.contains(id))
+            });
+            if removed == 0 {
+                return Err(invalid_input(format!("No attachment matches {}", targets.join(" "))));
+            }
+            println!("Removed {removed} attachments");
+            settings.write_history(conversation)
+        }
+        _ => Err(invalid_input("Expected attach list or attach rm PATH|NUMBER".to_string())),
+    }
+}
+
+fn editor() -> String {
+    env::var("VISUAL").or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad".to_string() } else { "vi".to_string() })
+}
+
+fn edit_file(path: &Path) -> Result<(), Box<dyn Error>> {
+    let editor = editor();
+    let mut command = editor.split_whitespace();
+    let program = command.next().ok_or_else(|| invalid_input("No editor configured".to_string()))?;
+    let status = std::process::Command::new(program).args(command).arg(path).status()?;
+    if !status.success() {
+        return Err(Box::new(io::Error::other(format!("{editor} exited with {status}"))));
+    }
+    Ok(())
+}
+
+// forks the active branch at message N with the edited text, an edited user message is answered right away
+async fn edit(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let settings = &take_session_option(settings, &mut args)?;
+    let settings = &take_model_option(settings, &mut args).await?;
+    let system = take_system_prompt(settings, &mut args, prompts::CHAT_PROMPT)?;
+    let no_reply = take_flag(&mut args, "--no-reply");
+    let number = match args.as_slice() {
+        [number] => number.parse::<usize>().ok().filter(|number| *number > 0)
+            .ok_or_else(|| invalid_input(format!("Invalid message number '{number}'")))?,
+        _ => return Err(invalid_input("Expected edit NUMBER".to_string())),
+    };
+    let (mut conversation, mut snapshot) = settings.get_branch()?;
+    let original = conversation.0.get(number - 1)
+        .ok_or_else(|| invalid_input(format!("No message with number {number}")))?;
+    let path = env::temp_dir().join(format!("rustgpt-edit-{}.md", std::process::id()));
+    fs::write(&path, original.text().as_ref())?;
+    let edited = edit_file(&path).and_then(|_| Ok(fs::read_to_string(&path)?));
+    let _ = fs::remove_file(&path);
+    // editors usually add a newline at the end
+    let edited = edited?.trim_end_matches(['\r', '\n']).to_string();
+    if edited == original.text() {
+        return Err(invalid_input(format!("Message {number} was not changed")));
+    }
+    let mut msg = Message { id: None, parent: None, attachment: None, metadata: None, ..original.clone() };
+    msg.set_text(&edited);
+    let reply = msg.role == Role::User && !no_reply;
+    snapshot.fork(number - 1);
+    conversation.0.truncate(number - 1);
+    conversation.push(msg);
+    // the fork is saved first, so the edit is kept when the request fails
+    settings.save_branch(&snapshot, &conversation)?;
+    println!("Forked at message {number}");
+    if !reply {
+        return Ok(());
+    }
+    let (conversation, snapshot) = settings.get_branch()?;
+    print!("{}", conversation);
+    let provider = provider::from_settings(settings)?;
+    let conversation = chat::get_next(provider.as_ref(), settings, conversation, &system).await?;
+    settings.save_branch(&snapshot, &conversation)
+}
+
+// applies `--temperature VALUE` to a copy of the settings
+fn take_temperature_option(settings: &Settings, args: &mut Vec<&str>) -> Result<Settings, Box<dyn Error>> {
+    let mut settings = settings.clone();
+    if let Some(value) = take_option(args, "--temperature")? {
+        let temperature = value.parse().ok().filter(|temperature| (0.0..=2.0).contains(temperature))
+            .ok_or_else(|| invalid_input(format!("Invalid --temperature value '{value}', expected 0 to 2")))?;
+        settings.set_temperature(temperature);
+    }
+    Ok(settings)
+}
+
+// the position of the last user message in the branch, the question `retry` and `regenerate` answer again
+fn last_question(conversation: &Messages) -> Result<usize, Box<dyn Error>> {
+    conversation.0.iter().rposition(|msg| msg.role == Role::User)
+        .ok_or_else(|| invalid_input("There is no user message to answer".to_string()))
+}
+
+// drops the last exchange, from the user message it starts with, from the active branch
+fn undo(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let settings = &take_session_option(settings, &mut args)?;
+    if let Some(arg) = args.first() {
+        return Err(invalid_input(format!("Unknown argument {arg}")));
+    }
+    let _lock = settings.lock_history()?;
+    let mut history = settings.get_history()?;
+    let mut info = settings.session_info()?;
+    let branch = tree::branch(&history.0, info.branch.as_deref());
+    let start = tree::last_exchange(branch.iter().map(|position| &history.0[*position]))
+        .ok_or_else(|| invalid_input("There is no exchange to undo".to_string()))?;
+    let previous = start.checked_sub(1).and_then(|index| history.0[branch[index]].id.clone());
+    // messages that other branches continue from are kept for them
+    let removed = tree::prune(&mut history, &branch, start);
+    settings.write_history(history)?;
+    if previous.is_some() || info.branch.is_some() {
+        info.branch = previous;
+        settings.write_session_info(&info)?;
+    }
+    println!("Removed {removed} messages");
+    Ok(())
+}
+
+// the system prompt of `retry` and `regenerate`, None to ask the way the last exchange was answered.
+// Without an answer, or with `--persona` or `-v`, the chat prompt or the chosen one is used.
+fn take_retry_system_prompt(settings: &Settings, args: &mut Vec<&str>, answer: Option<&Message>) -> Result<Option<String>, Box<dyn Error>> {
+    let persona = args.iter().any(|arg| matches!(*arg, "--persona" | "-v"));
+    let system = take_system_prompt(settings, args, prompts::CHAT_PROMPT)?;
+    Ok((persona || answer.is_none()).then_some(system))
+}
+
+// the first answer to the user message at `start`, its metadata tells how it was asked for
+fn first_answer(conversation: &Messages, start: usize) -> Option<&Message> {
+    conversation.0[start + 1..].iter().find(|msg| msg.role == Role::Assistant)
+}
+
+// answers the message `leaf` again in a new branch the way `answer` was asked for, returns the id of the last new message
+async fn answer_again(settings: &Settings, leaf: Option<&str>, answer: Option<&Metadata>, system: Option<&str>) -> Result<Option<String>, Box<dyn Error>> {
+    let (conversation, snapshot) = settings.get_branch_at(leaf)?;
+    let provider = provider::from_settings(settings)?;
+    let mut conversation = chat::get_next_again(provider.as_ref(), settings, conversation, answer, system).await?;
+    println!();
+    run_tool_calls(&mut conversation);
+    let id = conversation.0.last_mut().map(|answer| answer.id.get_or_insert_with(tree::new_id).clone());
+    settings.save_branch(&snapshot, &conversation)?;
+    Ok(id)
+}
+
+// sends the last user message again and replaces the answer, with `--model` and `--temperature` for this request only
+async fn retry(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let settings = &take_session_option(settings, &mut args)?;
+    let settings = &take_model_option(settings, &mut args).await?;
+    let settings = &take_temperature_option(settings, &mut args)?;
+    let (conversation, _) = settings.get_branch()?;
+    let start = last_question(&conversation)?;
+    let answer = first_answer(&conversation, start);
+    let system = take_retry_system_prompt(settings, &mut args, answer)?;
+    if let Some(arg) = args.first() {
+        return Err(invalid_input(format!("Unknown argument {arg}")));
+    }
+    let question = conversation.0[start].id.clone();
+    let old_answer = conversation.0.last().filter(|_| start + 1 < conversation.0.len()).and_then(|msg| msg.id.clone());
+    print!("{}", conversation.0[start]);
+    answer_again(settings, question.as_deref(), answer.and_then(|answer| answer.metadata.as_ref()), system.as_deref()).await?;
+    let Some(old_answer) = old_answer else {
+        return Ok(());
+    };
+    let _lock = settings.lock_history()?;
+    let mut history = settings.get_history()?;
+    let branch = tree::branch(&history.0, Some(&old_answer));
+    tree::prune(&mut history, &branch, start + 1);
+    settings.write_history(history)
+}
+
+// answers the last user message `-n` more times, every answer is kept in its own branch
+async fn regenerate(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let settings = &take_session_option(settings, &mut args)?;
+    let settings = &take_model_option(settings, &mut args).await?;
+    let settings = &take_temperature_option(settings, &mut args)?;
+    let count = match take_option(&mut args, "-n")? {
+        Some(value) => value.parse::<usize>().ok().filter(|count| *count > 0)
+            .ok_or_else(|| invalid_input(format!("Invalid -n value '{value}'")))?,
+        None => 1,
+    };
+    let (conversation, _) = settings.get_branch()?;
+    let start = last_question(&conversation)?;
+    let answer = first_answer(&conversation, start);
+    let system = take_retry_system_prompt(settings, &mut args, answer)?;
+    if let Some(arg) = args.first() {
+        return Err(invalid_input(format!("Unknown argument {arg}")));
+    }
+    let metadata = answer.and_then(|answer| answer.metadata.as_ref());
+    let question = conversation.0[start].id.clone();
+    print!("{}", conversation.0[start]);
+    let mut answers = vec![];
+    for number in 1..=count {
+        println!("--- answer {number} of {count}");
+        answers.push(answer_again(settings, question.as_deref(), metadata, system.as_deref()).await?);
+    }
+    let history = settings.get_history()?;
+    let numbers = answers.iter()
+        .filter_map(|answer| branch_number(&history, answer))
+        .map(|number| number.to_string())
+        .collect::<Vec<_>>();
+    println!("The answers are branches {}, the last one is active. Pick another with `rustgpt branch switch NUMBER`.", numbers.join(", "));
+    Ok(())
+}
+
+// lists the branches of the conversation or makes another one active, by number or message id
+fn branch(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let mut args = args.to_vec();
+    let settings = &take_session_option(settings, &mut args)?;
+    let history = settings.get_history()?;
+    let branches = tree::branches(&history.0);
+    match args.as_slice() {
+        [] | ["list"] => {
+            let (active, _) = settings.get_branch()?;
+            for (number, path) in branches.iter().enumerate() {
+                let leaf = path.last().map(|position| &history.0[*position]);
+                let is_active = active.last().is_some_and(|msg| Some(&msg.id) == leaf.map(|leaf| &leaf.id));
+                // where the branch leaves the active one
+                let fork = path.iter().zip(&active.0)
+                    .position(|(position, msg)| history.0[*position].id != msg.id)
+                    .map_or(String::new(), |index| format!(", from message {}", index + 1));
+                let preview = path.iter().rev()
+                    .map(|position| &history.0[*position])
+                    .find(|msg| msg.role == Role::User)
+                    .map(|msg| msg.text().split_whitespace().collect::<Vec<_>>().join(" ").chars().take(60).collect::<String>())
+                    .unwrap_or_default();
+                println!("{} {:>3} {:>4} messages{fork}  {preview}", if is_active { "*" } else { " " }, number + 1, path.len());
+            }
+            Ok(())
+        }
+        ["switch", target] => {
+            let leaf = match target.parse::<usize>() {
+                Ok(number) => number.checked_sub(1).and_then(|index| branches.get(index)?.last()).map(|position| &history.0[*position]),
+                Err(_) => history.0.iter().find(|msg| msg.id.as_deref() == Some(*target)),
+            };
+            let leaf = leaf.ok_or_else(|| invalid_input(format!("No branch {target}")))?;
+            let mut info = settings.session_info()?;
+            info.branch = leaf.id.clone();
+            settings.write_session_info(&info)?;
+            let path = tree::branch(&history.0, leaf.id.as_deref());
+            println!("Switched to the branch with {} messages", path.len());
+            Ok(())
+        }
+        _ => Err(invalid_input("Expected branch list or branch switch NUMBER|ID".to_string())),
+    }
+}
+
+// lists, shows or edits the system prompt templates, editing a built in prompt copies it to the prompts directory
+fn prompt(settings: &Settings, args: &[&str]) -> Result<(), Box<dyn Error>> {
+    let dir = settings.prompts_dir();
+    match args {
+        [] | ["list"] => {
+            for prompt in prompts::list(dir)? {
+                let source = prompt.path.map_or("built in".to_string(), |path| path.display().to_string());
+                println!("{:<20} {source}", prompt.name);
+            }
+            Ok(())
+        }
+        ["show", name] => {
+            println!("{}", prompts::find(dir, name)?.template);
+            Ok(())
+        }
+        ["edit", name] => {
+            let path = prompts::path(dir, name)?;
+            if !path.exists() {
+                let template = prompts::find(dir, name).map(|prompt| prompt.template).unwrap_or_default();
+                fs::create_dir_all(dir)?;
+                fs::write(&path, template)?;
+            }
+            edit_file(&path)
+        }
+        _ => Err(invalid_input("Expected prompt list, prompt show NAME or prompt edit NAME".to_string())),
+    }
 }
 
 #[tokio::main]
@@ -87,15 +1034,19 @@ async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
     let args = env::args().collect::<Vec<String>>();
     let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
-    let settings = Settings::from_file(config::DEFAULT_CONFIG_FILE)
-        .or(Ok(Settings::default()) as Result<_, Box<dyn Error>>)?;
-    let result = match args.as_slice() {
-        &[] => { panic!("can not call program without any args!") }
-        &[_] => { Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, "Invalid number of arguments")))? }
-        &[_, command, ..] => {
+    // the default settings are written to the config file, so only create them when there is none
+    let settings = if Path::new(config::DEFAULT_CONFIG_FILE).exists() {
+        Settings::from_file(config::DEFAULT_CONFIG_FILE)?
+    } else {
+        Settings::default()
+    };
+    let result = match *args.as_slice() {
+        [] => { panic!("can not call program without any args!") }
+        [_] => { Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, "Invalid number of arguments")))? }
+        [_, command, ..] => {
             return match command {
                 "models" => {
-                    models().await
+                    models(&settings, &args[2..]).await
                 }
                 "pwsh" => {
                     pwsh(&settings, &args[2..]).await
@@ -103,18 +1054,65 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 "chat" => {
                     chat(&settings, &args[2..]).await
                 }
+                "embed" => {
+                    embed(&settings, &args[2..]).await
+                }
+                "index" => {
+                    index(&settings, &args[2..]).await
+                }
+                "extract" => {
+                    extract(&settings, &args[2..]).await
+                }
                 "print" => {
                     print_conversation(&settings, &args[2..]).await
                 }
+                "import" => {
+                    import(&settings, &args[2..])
+                }
+                "search" => {
+                    search(&settings, &args[2..])
+                }
+                "export" => {
+                    export(&settings, &args[2..])
+                }
+                "migrate" => {
+                    migrate(&settings, &args[2..])
+                }
+                "usage" => {
+                    print_usage(&settings, &args[2..]).await
+                }
+                "pin" => {
+                    pin_message(&settings, &args[2..], true)
+                }
+                "unpin" => {
+                    pin_message(&settings, &args[2..], false)
+                }
                 "clear" => {
                     settings.clear_history()
                 }
+                "prompt" => {
+                    prompt(&settings, &args[2..])
+                }
+                "edit" => {
+                    edit(&settings, &args[2..]).await
+                }
+                "branch" => {
+                    branch(&settings, &args[2..])
+                }
+                "undo" => {
+                    undo(&settings, &args[2..])
+                }
+                "retry" => {
+                    retry(&settings, &args[2..]).await
+                }
+                "regenerate" => {
+                    regenerate(&settings, &args[2..]).await
+                }
+                "attach" => {
+                    attach(&settings, &args[2..])
+                }
                 "file" => {
-                    let filename = match args.get(2) {
-                        Some(filename) => filename,
-                        None => "file"
-                    };
-                    add_file_from_stdin(filename, &settings).await
+                    add_files(&settings, &args[2..]).await
                 }
                 _ => {
                     Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown command {}", args[1]))))?
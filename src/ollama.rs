@@ -0,0 +1,403 @@
+use std::env;
+use std::error::Error;
+use std::io;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::openai::{images, tree, FunctionCall, Message, Model, OpenaiFunction, ResponseFormat, Role, ToolCall, Usage};
+use crate::openai::config::Settings;
+use crate::openai::embeddings::{self, EmbeddingOptions, Embeddings};
+use crate::provider::{self, ChatProvider, ChatRequest, ChatResponse, LineBuffer, ProviderSettings, StreamEvent};
+
+const BASE_URL: &str = "http://localhost:11434";
+const NAME: &str = "ollama";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaRequest {
+    pub model: String,
+    pub messages: Vec<OllamaMessage>,
+    pub stream: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tools: Vec<OllamaTool>,
+    pub options: OllamaOptions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<String>,
+    // "json" or a json schema the reply has to follow
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaOptions {
+    pub temperature: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<OllamaToolCall>,
+    // name of the function a tool message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_name: Option<String>,
+    // base64 encoded images
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaToolCall {
+    pub function: OllamaFunctionCall,
+}
+
+// unlike openai the arguments are a json object instead of a string
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaFunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OllamaTool {
+    pub r#type: String,
+    pub function: OpenaiFunction,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChunk {
+    #[serde(default)]
+    message: Option<OllamaMessage>,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    done_reason: Option<String>,
+    #[serde(default)]
+    prompt_eval_count: Option<u64>,
+    #[serde(default)]
+    eval_count: Option<u64>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LocalModel {
+    name: String,
+    #[serde(default)]
+    modified_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LocalModelList {
+    models: Vec<LocalModel>,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbedRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dimensions: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+    #[serde(default)]
+    prompt_eval_count: Option<u64>,
+}
+
+/// Progress of `pull`, `total` and `completed` are only set while downloading layers
+#[derive(Debug, Deserialize)]
+pub struct PullStatus {
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub digest: Option<String>,
+    #[serde(default)]
+    pub total: Option<u64>,
+    #[serde(default)]
+    pub completed: Option<u64>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+// ollama only knows the system, user, assistant and tool roles
+fn ollama_role(role: Role) -> &'static str {
+    match role {
+        Role::System | Role::Developer => "system",
+        Role::User => "user",
+        Role::Assistant => "assistant",
+        // legacy function results are tool results named after their function
+        Role::Tool | Role::Function => "tool",
+    }
+}
+
+/// Ollama has no tool call ids, tool results are matched to their call by function name.
+/// Ollama does not support `tool_choice` either, so it is ignored.
+pub fn to_request(request: ChatRequest, keep_alive: Option<String>) -> OllamaRequest {
+    // tool calls that did not get a result yet, as (tool call id, function name)
+    let mut open_calls: Vec<(String, String)> = vec![];
+    let messages = request.messages.0.into_iter()
+        .map(|msg| {
+            let content = msg.text().into_owned();
+            // ollama only takes image data, urls can not be passed on
+            let images = msg.images().into_iter()
+                .filter_map(|image| match images::parse_data_url(&image.url) {
+                    Some((_, data)) => Some(data.to_string()),
+                    None => {
+                        log::warn!("Skipping image {}, ollama only supports local images", image.url);
+                        None
+                    }
+                })
+                .collect();
+            let tool_calls = msg.tool_calls.unwrap_or_default().into_iter()
+                .map(|tool_call| {
+                    open_calls.push((tool_call.id, tool_call.function.name.clone()));
+                    OllamaToolCall {
+                        function: OllamaFunctionCall {
+                            arguments: serde_json::from_str(&tool_call.function.arguments).unwrap_or_else(|_| Value::Object(Default::default())),
+                            name: tool_call.function.name,
+                        },
+                    }
+                })
+                .collect();
+            let tool_name = msg.tool_call_id.as_ref()
+                .and_then(|id| open_calls.iter().rposition(|(call_id, _)| call_id == id))
+                .map(|position| open_calls.remove(position).1)
+                .or_else(|| msg.name.clone().filter(|_| msg.role == Role::Function));
+            OllamaMessage { role: ollama_role(msg.role).to_string(), content, tool_calls, tool_name, images }
+        })
+        .collect();
+    let tools = request.tools.into_iter()
+        .map(|function| OllamaTool { r#type: "function".to_string(), function })
+        .collect();
+    let format = match request.response_format {
+        None | Some(ResponseFormat::Text) => None,
+        Some(ResponseFormat::JsonObject) => Some(Value::String("json".to_string())),
+        Some(ResponseFormat::JsonSchema { json_schema }) => Some(json_schema.schema),
+    };
+    OllamaRequest {
+        model: request.model,
+        messages,
+        stream: true,
+        tools,
+        options: OllamaOptions { temperature: request.temperature },
+        keep_alive,
+        format,
+    }
+}
+
+/// Decodes the newline delimited json of a streamed `/api/chat` reply
+#[derive(Debug, Default)]
+pub struct StreamDecoder {
+    lines: LineBuffer,
+    started: bool,
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    usage: Option<Usage>,
+    done_reason: Option<String>,
+}
+
+impl StreamDecoder {
+    pub fn feed(&mut self, chunk: &[u8], on_event: &mut dyn FnMut(StreamEvent)) -> Result<(), Box<dyn Error>> {
+        for line in self.lines.push(chunk) {
+            self.decode_line(&line, on_event)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self, on_event: &mut dyn FnMut(StreamEvent)) -> Result<ChatResponse, Box<dyn Error>> {
+        if let Some(line) = self.lines.finish() {
+            self.decode_line(&line, on_event)?;
+        }
+        let message = Message::assistant(self.content, self.tool_calls);
+        // ollama has no ids for its replies
+        Ok(ChatResponse { message, usage: self.usage, request_id: None, finish_reason: self.done_reason })
+    }
+
+    fn decode_line(&mut self, line: &str, on_event: &mut dyn FnMut(StreamEvent)) -> Result<(), Box<dyn Error>> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        let chunk: ChatChunk = serde_json::from_str(line)?;
+        if let Some(error) = chunk.error {
+            return Err(Box::new(io::Error::other(format!("Error from ollama api: {error}"))));
+        }
+        if let Some(message) = chunk.message {
+            if !self.started {
+                on_event(StreamEvent::Role(&message.role));
+                self.started = true;
+            }
+            if !message.content.is_empty() {
+                on_event(StreamEvent::Content(&message.content));
+                self.content.push_str(&message.content);
+            }
+            // tool calls are not streamed in parts, every call arrives complete and without an id
+            for tool_call in message.tool_calls {
+                let arguments = tool_call.function.arguments.to_string();
+                on_event(StreamEvent::ToolCall(&tool_call.function.name));
+                on_event(StreamEvent::ToolArguments(&arguments));
+                let id = format!("call_{}", tree::new_id());
+                self.tool_calls.push(ToolCall::new(&id, FunctionCall { name: tool_call.function.name, arguments }));
+            }
+        }
+        if chunk.done {
+            self.done_reason = chunk.done_reason;
+            let prompt_tokens = chunk.prompt_eval_count.unwrap_or(0);
+            let completion_tokens = chunk.eval_count.unwrap_or(0);
+            self.usage = Some(Usage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+                prompt_tokens_details: None,
+            });
+        }
+        Ok(())
+    }
+}
+
+pub struct OllamaProvider {
+    client: reqwest::Client,
+    base_url: String,
+    // ollama runs without authentication, unless it is behind a proxy
+    api_key: Option<String>,
+    keep_alive: Option<String>,
+}
+
+impl OllamaProvider {
+    pub fn new(client: reqwest::Client, settings: &ProviderSettings) -> OllamaProvider {
+        OllamaProvider {
+            client,
+            base_url: settings.base_url(BASE_URL).to_string(),
+            api_key: settings.api_key_env.as_ref().and_then(|name| env::var(name).ok()),
+            keep_alive: settings.keep_alive.clone(),
+        }
+    }
+
+    /// Uses the `ollama` provider settings, also when another provider is selected
+    pub fn from_settings(settings: &Settings) -> OllamaProvider {
+        let provider_settings = settings.providers().get(NAME).cloned().unwrap_or_default();
+        Self::new(reqwest::Client::new(), &provider_settings)
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        log::debug!("{method} {}{path}", self.base_url);
+        let builder = self.client.request(method, format!("{}{path}", self.base_url));
+        match &self.api_key {
+            Some(api_key) => builder.header("Authorization", format!("Bearer {api_key}")),
+            None => builder,
+        }
+    }
+
+    async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, Box<dyn Error>> {
+        if !response.status().is_success() {
+            let status = response.status();
+            return Err(provider::api_error(NAME, status, &response.text().await?));
+        }
+        Ok(response)
+    }
+
+    /// Downloads a model, `on_status` receives every progress update
+    pub async fn pull(&self, model: &str, on_status: &mut dyn FnMut(&PullStatus)) -> Result<(), Box<dyn Error>> {
+        let body = serde_json::json!({ "model": model, "stream": true });
+        let mut response = Self::check_status(self.request(reqwest::Method::POST, "/api/pull").json(&body).send().await?).await?;
+        let mut lines = LineBuffer::default();
+        let mut handle_line = |line: &str| -> Result<(), Box<dyn Error>> {
+            if line.trim().is_empty() {
+                return Ok(());
+            }
+            let status: PullStatus = serde_json::from_str(line)?;
+            if let Some(error) = &status.error {
+                return Err(Box::new(io::Error::other(format!("Could not pull {model}: {error}"))));
+            }
+            on_status(&status);
+            Ok(())
+        };
+        while let Some(chunk) = response.chunk().await? {
+            for line in lines.push(&chunk) {
+                handle_line(&line)?;
+            }
+        }
+        if let Some(line) = lines.finish() {
+            handle_line(&line)?;
+        }
+        Ok(())
+    }
+
+    pub async fn delete(&self, model: &str) -> Result<(), Box<dyn Error>> {
+        let body = serde_json::json!({ "model": model });
+        Self::check_status(self.request(reqwest::Method::DELETE, "/api/delete").json(&body).send().await?).await?;
+        Ok(())
+    }
+
+    /// Returns the details, parameters and template of a local model as reported by ollama
+    pub async fn show(&self, model: &str) -> Result<Value, Box<dyn Error>> {
+        let body = serde_json::json!({ "model": model });
+        let response = Self::check_status(self.request(reqwest::Method::POST, "/api/show").json(&body).send().await?).await?;
+        Ok(response.json().await?)
+    }
+}
+
+#[async_trait(?Send)]
+impl ChatProvider for OllamaProvider {
+    fn name(&self) -> &str {
+        NAME
+    }
+
+    async fn chat(&self, request: ChatRequest, on_event: &mut dyn FnMut(StreamEvent)) -> Result<ChatResponse, Box<dyn Error>> {
+        let body_str = serde_json::to_string(&to_request(request, self.keep_alive.clone()))?;
+        log::debug!("Chat request: {body_str}");
+        let response = self.request(reqwest::Method::POST, "/api/chat")
+            .header("Content-Type", "application/json")
+            .body(body_str)
+            .send().await?;
+        let mut response = Self::check_status(response).await?;
+        let mut decoder = StreamDecoder::default();
+        while let Some(chunk) = response.chunk().await? {
+            log::debug!("Parsing chunk:'{}'", String::from_utf8_lossy(chunk.as_ref()));
+            decoder.feed(&chunk, on_event)?;
+        }
+        decoder.finish(on_event)
+    }
+
+    async fn list_models(&self) -> Result<Vec<Model>, Box<dyn Error>> {
+        let response = Self::check_status(self.request(reqwest::Method::GET, "/api/tags").send().await?).await?;
+        let model_list: LocalModelList = response.json().await?;
+        Ok(model_list.models.into_iter()
+            .map(|model| Model {
+                created: model.modified_at
+                    .and_then(|modified| chrono::DateTime::parse_from_rfc3339(&modified).ok())
+                    .map_or(0, |modified| modified.timestamp() as u64),
+                id: model.name,
+                object: "model".to_string(),
+                owned_by: NAME.to_string(),
+            })
+            .collect())
+    }
+
+    // ollama always returns floats, the encoding format is ignored
+    async fn embed(&self, inputs: &[String], options: &EmbeddingOptions) -> Result<Embeddings, Box<dyn Error>> {
+        let mut embeddings = Embeddings::default();
+        for batch in inputs.chunks(options.batch_size.max(1)) {
+            let request = EmbedRequest {
+                model: &options.model,
+                input: batch,
+                dimensions: options.dimensions,
+                keep_alive: self.keep_alive.as_deref(),
+            };
+            let response = Self::check_status(self.request(reqwest::Method::POST, "/api/embed").json(&request).send().await?).await?;
+            let response: EmbedResponse = response.json().await?;
+            embeddings::check_count(batch.len(), response.embeddings.len())?;
+            embeddings.vectors.extend(response.embeddings);
+            let tokens = response.prompt_eval_count.unwrap_or_default();
+            embeddings.usage.prompt_tokens += tokens;
+            embeddings.usage.total_tokens += tokens;
+        }
+        Ok(embeddings)
+    }
+}
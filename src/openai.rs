@@ -1,191 +1,211 @@
 mod models;
 
+pub mod attachments;
+pub mod catalog;
 pub mod config;
+pub mod context;
+pub mod embeddings;
+pub mod export;
+pub mod files;
+pub mod images;
+pub mod import;
+pub mod prompts;
+pub mod rag;
+pub mod schema;
+pub mod search;
+pub mod sessions;
+pub mod store;
+pub mod transcript;
+pub mod tree;
+pub mod usage;
 
-use std::collections::HashMap;
 pub use models::*;
 
 use std::error::Error;
-use std::io;
+use async_trait::async_trait;
 use log;
+use crate::openai::embeddings::{EmbeddingOptions, Embeddings};
+use crate::provider::{self, ChatProvider, ChatRequest, ChatResponse, LineBuffer, ProviderSettings, StreamEvent};
 
-const MODEL: &str = "gpt-3.5-turbo";
+const BASE_URL: &str = "https://api.openai.com/v1";
+const API_KEY_ENV: &str = "OPENAI_API_KEY";
 
-struct OpenAiResponseIter {
-    buffer: Vec<u8>,
+/// Decodes the server sent events of a streamed chat completion
+#[derive(Debug, Default)]
+pub struct StreamDecoder {
+    lines: LineBuffer,
+    role: Option<Role>,
+    content: String,
+    refusal: String,
+    tool_calls: Vec<ToolCall>,
+    usage: Option<Usage>,
+    request_id: Option<String>,
+    finish_reason: Option<String>,
 }
 
-impl Iterator for OpenAiResponseIter {
-    type Item = OpenAiResponse;
+impl StreamDecoder {
+    pub fn feed(&mut self, chunk: &[u8], on_event: &mut dyn FnMut(StreamEvent)) -> Result<(), Box<dyn Error>> {
+        for line in self.lines.push(chunk) {
+            self.decode_line(&line, on_event);
+        }
+        Ok(())
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        log::debug!("Getting next part from buffer:'{:?}'", String::from_utf8_lossy(self.buffer.as_ref()));
-        if self.buffer.is_empty() {
-            log::debug!("buffer is empty returning None");
-            return None;
+    pub fn finish(mut self, on_event: &mut dyn FnMut(StreamEvent)) -> Result<ChatResponse, Box<dyn Error>> {
+        if let Some(line) = self.lines.finish() {
+            self.decode_line(&line, on_event);
         }
-        let index = if let Some(newline_index) = self.buffer.iter().position(|&x| x == b'\n') {
-            newline_index
-        } else {
-            log::debug!("No more newlines in buffer, returning using full length of buffer");
-            self.buffer.len()-1
+        let message = Message {
+            role: self.role.unwrap_or(Role::Assistant),
+            refusal: if self.refusal.is_empty() { None } else { Some(self.refusal) },
+            ..Message::assistant(self.content, self.tool_calls)
         };
-        let line = self.buffer.drain(..=index).collect::<Vec<u8>>();
-        log::debug!("Clearing buffer '{:?}' as it's stored in line: {:?}", String::from_utf8_lossy(self.buffer.as_ref()), String::from_utf8_lossy(line.as_ref()));
-        if !self.buffer.is_empty(){
-            self.buffer.remove(0);
-        }
-        if line.is_empty() {
-            log::debug!("No data in line, returning None");
-            return None;
-        }
-        if line.starts_with(b"data: [DONE]") {
+        Ok(ChatResponse { message, usage: self.usage, request_id: self.request_id, finish_reason: self.finish_reason })
+    }
+
+    fn decode_line(&mut self, line: &str, on_event: &mut dyn FnMut(StreamEvent)) {
+        let Some(data) = line.strip_prefix("data:").map(str::trim_start) else {
+            return;
+        };
+        if data == "[DONE]" {
             log::debug!("End of data message");
-            return None;
+            return;
         }
-        log::debug!("serde_json parsing '{}'", String::from_utf8_lossy(line.as_ref()));
-        if let Ok(openai_resp) = serde_json::from_slice(&line[6..]) {
-            Some(openai_resp)
-        } else {
-            log::error!("could not parse: {:?}", String::from_utf8(line.to_vec()));
-            None
+        let partial_response: OpenAiResponse = match serde_json::from_str(data) {
+            Ok(partial_response) => partial_response,
+            Err(e) => {
+                log::error!("could not parse '{data}': {e}");
+                return;
+            }
+        };
+        // only the last event has the usage, when `stream_options.include_usage` is requested
+        if partial_response.usage.is_some() {
+            self.usage = partial_response.usage;
+        }
+        // every event repeats the id of the completion
+        self.request_id.get_or_insert(partial_response.id);
+        for choice in partial_response.choices {
+            if choice.finish_reason.is_some() {
+                self.finish_reason = choice.finish_reason;
+            }
+            let Some(delta) = choice.delta else {
+                continue;
+            };
+            if let Some(role) = delta.role {
+                on_event(StreamEvent::Role(role.as_str()));
+                self.role = Some(role);
+            }
+            if let Some(content) = delta.content {
+                on_event(StreamEvent::Content(&content));
+                self.content.push_str(&content);
+            }
+            if let Some(refusal) = delta.refusal {
+                on_event(StreamEvent::Refusal(&refusal));
+                self.refusal.push_str(&refusal);
+            }
+            for tool_call_delta in delta.tool_calls.into_iter().flatten() {
+                if tool_call_delta.index >= self.tool_calls.len() {
+                    self.tool_calls.resize_with(tool_call_delta.index + 1, || ToolCall::new("", FunctionCall::default()));
+                }
+                let tool_call = &mut self.tool_calls[tool_call_delta.index];
+                if let Some(id) = tool_call_delta.id {
+                    tool_call.id = id;
+                }
+                let Some(function_call) = tool_call_delta.function else {
+                    continue;
+                };
+                if let Some(name) = function_call.name {
+                    on_event(StreamEvent::ToolCall(&name));
+                    tool_call.function.name.push_str(&name);
+                }
+                if let Some(arguments) = function_call.arguments {
+                    on_event(StreamEvent::ToolArguments(&arguments));
+                    tool_call.function.arguments.push_str(&arguments);
+                }
+            }
         }
     }
 }
 
-fn get_chat_request(messages: Messages) -> OpenAiRequest {
+fn get_request(request: ChatRequest) -> OpenAiRequest {
     OpenAiRequest {
-        model: MODEL.to_string(),
-        messages,
-        temperature: 0.5,
+        model: request.model,
+        messages: request.messages,
+        temperature: request.temperature,
         stream: Some(true),
-        functions: None,
+        tools: if request.tools.is_empty() { None } else { Some(request.tools.into_iter().map(Tool::function).collect()) },
+        tool_choice: request.tool_choice,
+        response_format: request.response_format,
+        stream_options: Some(StreamOptions { include_usage: true }),
     }
 }
 
-fn get_request_with_powershell_functions(messages: Messages) -> OpenAiRequest {
-    OpenAiRequest {
-        model: MODEL.to_string(),
-        messages,
-        temperature: 0.1,
-        stream: Some(true),
-        functions: Some(vec![
-            OpenaiFunction {
-                name: "powershell".to_string(),
-                description: "Call a powershell command".to_string(),
-                parameters: FunctionParameters {
-                    r#type: "object".to_string(),
-                    properties: HashMap::from([("command".into(),
-                                                FunctionProperty {
-                                                    r#type: "string".into(),
-                                                    description: Some("the powershell command".into()),
-                                                    r#enum: vec![],
-                                                })]),
-                    required: vec!["command".into()],
-                },
-            },
-            OpenaiFunction {
-                name: "theme".to_string(),
-                description: "Call a powershell command to change the windows theme".to_string(),
-                parameters: FunctionParameters {
-                    r#type: "object".to_string(),
-                    properties: HashMap::from([("command".into(),
-                                                FunctionProperty {
-                                                    r#type: "string".into(),
-                                                    description: Some("themeA for dark mode and C for light mode".into()),
-                                                    r#enum: vec!["& \"C:\\Windows\\Resources\\Themes\\themeA.theme\"".into(),
-                                                                 "& \"C:\\Windows\\Resources\\Themes\\themeC.theme\"".into()],
-                                                })]),
-                    required: vec!["command".into()],
-                },
-            },
-        ]),
+pub struct OpenAiProvider {
+    client: reqwest::Client,
+    api_key: String,
+    base_url: String,
+}
+
+impl OpenAiProvider {
+    pub fn new(client: reqwest::Client, settings: &ProviderSettings) -> Result<OpenAiProvider, Box<dyn Error>> {
+        Ok(OpenAiProvider {
+            client,
+            api_key: settings.api_key(API_KEY_ENV)?,
+            base_url: settings.base_url(BASE_URL).to_string(),
+        })
     }
 }
 
-pub async fn print_models(openai_api_key: &str, client: &reqwest::Client) -> Result<String, Box<dyn Error>> {
-    let res = client.get("https://api.openai.com/v1/models")
+#[async_trait(?Send)]
+impl ChatProvider for OpenAiProvider {
+    fn name(&self) -> &str {
+        "openai"
+    }
+
+    async fn chat(&self, request: ChatRequest, on_event: &mut dyn FnMut(StreamEvent)) -> Result<ChatResponse, Box<dyn Error>> {
+        let request = get_request(request);
+        get_next_from_request(&self.api_key, &self.base_url, &self.client, request, on_event).await
+    }
+
+    async fn list_models(&self) -> Result<Vec<Model>, Box<dyn Error>> {
+        list_models(&self.api_key, &self.base_url, &self.client).await
+    }
+
+    async fn embed(&self, inputs: &[String], options: &EmbeddingOptions) -> Result<Embeddings, Box<dyn Error>> {
+        embeddings::embed(&self.api_key, &self.base_url, &self.client, inputs, options).await
+    }
+}
+
+async fn list_models(openai_api_key: &str, base_url: &str, client: &reqwest::Client) -> Result<Vec<Model>, Box<dyn Error>> {
+    log::debug!("GET {base_url}/models");
+    let res = client.get(format!("{base_url}/models"))
         .header("Authorization", format!("Bearer {openai_api_key}"))
         .send().await?;
-    Ok(res.text().await?)
+    if res.status() != 200 {
+        let status = res.status();
+        return Err(provider::api_error("openai", status, &res.text().await?));
+    }
+    let model_list: ModelList = res.json().await?;
+    Ok(model_list.data)
 }
 
-async fn get_next_from_request(openai_api_key: &str, client: &reqwest::Client, request: OpenAiRequest) -> Result<Message, Box<dyn Error>> {
+async fn get_next_from_request(openai_api_key: &str, base_url: &str, client: &reqwest::Client, request: OpenAiRequest, on_event: &mut dyn FnMut(StreamEvent)) -> Result<ChatResponse, Box<dyn Error>> {
     let body_str = serde_json::to_string(&request)?;
-    log::debug!("GET https://api.openai.com/v1/chat/completions response with message: {body_str}");
-    let mut response = client.post("https://api.openai.com/v1/chat/completions")
+    log::debug!("POST {base_url}/chat/completions with message: {body_str}");
+    let mut response = client.post(format!("{base_url}/chat/completions"))
         .header("Content-Type", "application/json")
         .header("Authorization", format!("Bearer {openai_api_key}"))
         .body(body_str)
         .send().await?;
 
-    let mut new_msg = Message {
-        content: String::new(),
-        role: String::new(),
-        function_call: None,
-        name: None,
-    };
-    let mut rec_role = String::new();
-    let mut rec_content = String::new();
-    let mut function_name = String::new();
-    let mut function_arguments = String::new();
-
     if response.status() != 200 {
-        log::error!("Received Error '{}' from openai api: {:?}", response.status(), response.text().await?);
-        return Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, "Error from api")))?;
+        let status = response.status();
+        return Err(provider::api_error("openai", status, &response.text().await?));
     }
-    while let Some(chunk) = response.chunk().await.expect("Did not get new chunk after awaiting") {
+    let mut decoder = StreamDecoder::default();
+    while let Some(chunk) = response.chunk().await? {
         log::debug!("Parsing chunk:'{}'", String::from_utf8_lossy(chunk.as_ref()));
-        let openai_response_iter = OpenAiResponseIter {
-            buffer: chunk.to_vec(),
-        };
-        for partial_response in openai_response_iter {
-            for message in partial_response.choices {
-                if let Some(delta) = message.delta {
-                    if let Some(role) = delta.role {
-                        rec_role = role.into();
-                        print!("{}: ", rec_role);
-                    }
-                    if let Some(content) = delta.content {
-                        rec_content.push_str(&content);
-                        print!("{}", content);
-                    }
-                    if let Some(function_call) = delta.function_call {
-                        if let Some(name) = function_call.name {
-                            print!("{}", name);
-                            function_name.push_str(&name);
-                        }
-                        if let Some(arguments) = function_call.arguments {
-                            print!("{arguments}");
-                            function_arguments.push_str(&arguments);
-                        }
-                    }
-                }
-            }
-        }
+        decoder.feed(&chunk, on_event)?;
     }
-    new_msg.role = rec_role;
-    new_msg.content = rec_content;
-    if !function_name.is_empty() {
-        let f_call = FunctionCall {
-            name: function_name,
-            arguments: function_arguments,
-        };
-        new_msg.function_call = Some(f_call);
-    }
-    Ok(new_msg)
-}
-
-pub async fn get_next(openai_api_key: &str, client: &reqwest::Client, mut history: Messages) -> Result<Messages, Box<dyn Error>> {
-    let request = get_chat_request(history.clone());
-    let new_msg = get_next_from_request(openai_api_key, client, request).await?;
-    history.push(new_msg);
-    Ok(history)
-}
-
-pub async fn get_next_powershell_command(openai_api_key: &str, client: &reqwest::Client, mut history: Messages) -> Result<Messages, Box<dyn Error>> {
-    let request = get_request_with_powershell_functions(history.clone());
-    let new_msg = get_next_from_request(openai_api_key, client, request).await?;
-    history.push(new_msg);
-    Ok(history)
+    decoder.finish(on_event)
 }
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use sha2::{Digest, Sha256};
+use crate::openai::{Attachment, Content, Message, Messages, Role};
+use crate::openai::files;
+
+pub fn hash(text: &str) -> String {
+    Sha256::digest(text.as_bytes()).iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn fenced(path: &str, text: &str) -> String {
+    files::fence(path, files::language(Path::new(path)), text)
+}
+
+/// A user message with the contents of a text file that refers to the file, `None` for binary
+/// files and files larger than `max_size` bytes
+pub fn attach(path: &str, max_size: u64) -> Result<Option<Message>, Box<dyn Error>> {
+    let Some(text) = files::read_text(Path::new(path), max_size)? else {
+        return Ok(None);
+    };
+    Ok(Some(Message {
+        attachment: Some(Attachment { path: path.to_string(), hash: hash(&text), max_size: Some(max_size) }),
+        ..Message::new(Role::User, &fenced(path, &text))
+    }))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Status {
+    Unchanged,
+    Changed { added: usize, removed: usize },
+    // the last copy is sent instead
+    Unreadable(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    /// index of the message in the history
+    pub index: usize,
+    pub path: String,
+    pub status: Status,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.status {
+            Status::Unchanged => write!(f, "{} is unchanged", self.path),
+            Status::Changed { added, removed } => write!(f, "{} changed since the last turn: +{added} -{removed} lines", self.path),
+            Status::Unreadable(reason) => write!(f, "{} can not be read ({reason}), the last copy is sent", self.path),
+        }
+    }
+}
+
+// the number of added and removed lines, ignoring the order of the lines
+fn line_changes(old: &str, new: &str) -> (usize, usize) {
+    let mut counts: HashMap<&str, isize> = HashMap::new();
+    for line in old.lines() {
+        *counts.entry(line).or_default() -= 1;
+    }
+    for line in new.lines() {
+        *counts.entry(line).or_default() += 1;
+    }
+    let added = counts.values().filter(|count| **count > 0).sum::<isize>();
+    let removed = -counts.values().filter(|count| **count < 0).sum::<isize>();
+    (added as usize, removed as usize)
+}
+
+/// Reads every attached file again, with the limit it was attached with, and replaces the copies of the files that changed.
+/// Returns the status of every attachment, in the order of the messages.
+pub fn refresh(messages: &mut Messages) -> Vec<Change> {
+    let mut changes = vec![];
+    for (index, msg) in messages.0.iter_mut().enumerate() {
+        let Some(attachment) = msg.attachment.clone() else {
+            continue;
+        };
+        let max_size = attachment.max_size.unwrap_or(files::DEFAULT_MAX_FILE_SIZE);
+        let status = match files::read_text(Path::new(&attachment.path), max_size) {
+            Ok(Some(text)) if hash(&text) == attachment.hash => Status::Unchanged,
+            Ok(Some(text)) => {
+                let contents = fenced(&attachment.path, &text);
+                let (added, removed) = line_changes(&msg.text(), &contents);
+                msg.attachment = Some(Attachment { hash: hash(&text), ..attachment.clone() });
+                msg.content = Some(Content::Text(contents));
+                Status::Changed { added, removed }
+            }
+            Ok(None) => Status::Unreadable("binary or too large".to_string()),
+            Err(e) => Status::Unreadable(e.to_string()),
+        };
+        changes.push(Change { index, path: attachment.path, status });
+    }
+    changes
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::openai::Model;
+use crate::openai::config::Settings;
+use crate::openai::usage::{self, Price};
+use crate::provider::ChatProvider;
+
+pub const DEFAULT_MODELS_CACHE_FILE: &str = "rustgpt/models.json";
+pub const DEFAULT_MODELS_CACHE_TTL: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Capabilities {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vision: Option<bool>,
+}
+
+impl Capabilities {
+    const fn new(context_length: usize, tools: bool, vision: bool) -> Capabilities {
+        Capabilities { context_length: Some(context_length), tools: Some(tools), vision: Some(vision) }
+    }
+
+    // fields set in `other` take precedence
+    fn merge(&self, other: &Capabilities) -> Capabilities {
+        Capabilities {
+            context_length: other.context_length.or(self.context_length),
+            tools: other.tools.or(self.tools),
+            vision: other.vision.or(self.vision),
+        }
+    }
+}
+
+// known capabilities of the hosted models, configured capabilities take precedence over these
+const DEFAULT_CAPABILITIES: &[(&str, Capabilities)] = &[
+    ("gpt-3.5-turbo", Capabilities::new(16_385, true, false)),
+    ("gpt-4", Capabilities::new(8_192, true, false)),
+    ("gpt-4-turbo", Capabilities::new(128_000, true, true)),
+    ("gpt-4o", Capabilities::new(128_000, true, true)),
+    ("gpt-4.1", Capabilities::new(1_047_576, true, true)),
+    ("o1", Capabilities::new(200_000, true, true)),
+    ("o3-mini", Capabilities::new(200_000, true, false)),
+    ("text-embedding-", Capabilities::new(8_191, false, false)),
+    ("claude-", Capabilities::new(200_000, true, true)),
+    ("gemini-", Capabilities::new(1_048_576, true, true)),
+];
+
+/// A model as listed by the api, merged with the local capability registry
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelInfo {
+    #[serde(flatten)]
+    pub model: Model,
+    pub capabilities: Capabilities,
+    pub price: Option<Price>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ModelCache {
+    fetched_at: DateTime<Utc>,
+    #[serde(default)]
+    provider: String,
+    models: Vec<Model>,
+}
+
+fn longest_prefix<'a, T>(entries: impl Iterator<Item=(&'a str, &'a T)>, model: &str) -> Option<&'a T> {
+    entries
+        .filter(|(name, _)| model.starts_with(name))
+        .max_by_key(|(name, _)| name.len())
+        .map(|(_, value)| value)
+}
+
+/// Looks up the capabilities of a model by the longest matching prefix,
+/// configured capabilities are merged over the built in ones.
+pub fn find_capabilities(configured: &HashMap<String, Capabilities>, model: &str) -> Capabilities {
+    let defaults = DEFAULT_CAPABILITIES.iter().map(|(name, capabilities)| (*name, capabilities));
+    let configured_entries = configured.iter().map(|(name, capabilities)| (name.as_str(), capabilities));
+    let default = longest_prefix(defaults, model).cloned().unwrap_or_default();
+    match longest_prefix(configured_entries, model) {
+        Some(overrides) => default.merge(overrides),
+        None => default,
+    }
+}
+
+pub fn model_info(settings: &Settings, model: Model) -> ModelInfo {
+    let capabilities = find_capabilities(settings.capabilities(), &model.id);
+    let price = usage::find_price(settings.prices(), &model.id).cloned();
+    ModelInfo { model, capabilities, price }
+}
+
+fn read_cache(path: &str) -> Option<ModelCache> {
+    let contents = fs::read_to_string(path).ok()?;
+    match serde_json::from_str(&contents) {
+        Ok(cache) => Some(cache),
+        Err(e) => {
+            log::warn!("Ignoring unreadable models cache {path}: {e}");
+            None
+        }
+    }
+}
+
+fn write_cache(path: &str, provider: &str, models: &[Model]) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let cache = ModelCache { fetched_at: Utc::now(), provider: provider.to_string(), models: models.to_vec() };
+    Ok(fs::write(path, serde_json::to_string(&cache)?)?)
+}
+
+/// Returns the models from the local cache, or from the api when the cache is older than the configured ttl.
+/// A stale cache is still used when the api can not be reached.
+pub async fn get_models(provider: &dyn ChatProvider, settings: &Settings, refresh: bool) -> Result<Vec<Model>, Box<dyn Error>> {
+    // a cache written for another provider is of no use
+    let cache = read_cache(settings.models_cache_file())
+        .filter(|cache| cache.provider.is_empty() || cache.provider == provider.name());
+    if let Some(cache) = &cache {
+        let age = Utc::now().signed_duration_since(cache.fetched_at);
+        if !refresh && age.num_seconds() >= 0 && (age.num_seconds() as u64) < settings.models_cache_ttl() {
+            log::debug!("Using models cache from {}", cache.fetched_at);
+            return Ok(cache.models.clone());
+        }
+    }
+    match provider.list_models().await {
+        Ok(models) => {
+            if let Err(e) = write_cache(settings.models_cache_file(), provider.name(), &models) {
+                log::warn!("Could not write models cache: {e}");
+            }
+            Ok(models)
+        }
+        Err(e) => match cache {
+            Some(cache) => {
+                log::warn!("Could not list models ({e}), using cache from {}", cache.fetched_at);
+                Ok(cache.models)
+            }
+            None => Err(e),
+        }
+    }
+}
+
+/// Case insensitive substring match on the id, and exact match on the owner
+pub fn matches(model: &Model, filter: Option<&str>, owner: Option<&str>) -> bool {
+    let id_matches = filter.is_none_or(|filter| model.id.to_lowercase().contains(&filter.to_lowercase()));
+    let owner_matches = owner.is_none_or(|owner| model.owned_by.eq_ignore_ascii_case(owner));
+    id_matches && owner_matches
+}
+
+/// Checks that a model exists before it is used for a request.
+/// When the model list is not available the model is accepted with a warning.
+pub async fn validate_model(provider: &dyn ChatProvider, settings: &Settings, model: &str) -> Result<(), Box<dyn Error>> {
+    let models = match get_models(provider, settings, false).await {
+        Ok(models) => models,
+        Err(e) => {
+            log::warn!("Could not validate model {model}: {e}");
+            return Ok(());
+        }
+    };
+    if models.iter().any(|known| known.id == model) {
+        return Ok(());
+    }
+    let mut suggestions = models.iter()
+        .filter(|known| known.id.contains(model) || model.contains(&known.id))
+        .map(|known| known.id.as_str())
+        .collect::<Vec<&str>>();
+    suggestions.sort();
+    let hint = if suggestions.is_empty() {
+        "see `rustgpt models`".to_string()
+    } else {
+        format!("did you mean {}?", suggestions.join(", "))
+    };
+    Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown model '{model}', {hint}"))))
+}
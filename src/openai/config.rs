@@ -1,18 +1,96 @@
+use std::collections::HashMap;
 use std::fs;
 use std::error::Error;
-use std::path::Path;
+use std::io;
+use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
 use crate::openai::{ChatHistory, Messages};
+use crate::openai::catalog::{self, Capabilities};
+use crate::openai::context::ContextSettings;
+use crate::openai::{images, prompts, rag, search};
+use crate::openai::sessions::SessionInfo;
+use crate::openai::store::{self, Backend, HistoryStore, Lock, Snapshot};
+use crate::openai::tree;
+use crate::openai::usage::{self, Budget, Price};
+use crate::provider::{self, ProviderSettings};
 
 pub const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
 pub const DEFAULT_FILE_NAME: &str = "rustgpt/conversation.json";
 pub const DEFAULT_CONFIG_FILE: &str = "rustgpt/config.json";
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Settings {
     model: String,
     history_file: String,
     config_file: String,
+    #[serde(default)]
+    context: ContextSettings,
+    #[serde(default = "default_usage_file")]
+    usage_file: String,
+    // USD per million tokens, extends and overrides the built in prices
+    #[serde(default)]
+    prices: HashMap<String, Price>,
+    #[serde(default)]
+    budget: Budget,
+    #[serde(default = "default_models_cache_file")]
+    models_cache_file: String,
+    // seconds before the cached model list is fetched again
+    #[serde(default = "default_models_cache_ttl")]
+    models_cache_ttl: u64,
+    // extends and overrides the built in model capabilities, keyed by model name prefix
+    #[serde(default)]
+    capabilities: HashMap<String, Capabilities>,
+    #[serde(default = "default_provider")]
+    provider: String,
+    // api url and key per provider, keyed by provider name
+    #[serde(default)]
+    providers: HashMap<String, ProviderSettings>,
+    // images sent in the conversation, the history only refers to them by hash
+    #[serde(default = "default_images_dir")]
+    images_dir: String,
+    // one file per directory indexed for retrieval
+    #[serde(default = "default_index_dir")]
+    index_dir: String,
+    // system prompt templates, one `<name>.md` file per prompt
+    #[serde(default = "default_prompts_dir")]
+    prompts_dir: String,
+    #[serde(default = "default_search_index_file")]
+    search_index_file: String,
+    // set with `--temperature` for a single run, the commands have their own defaults
+    #[serde(skip)]
+    temperature: Option<f32>,
+}
+
+fn default_provider() -> String {
+    provider::DEFAULT_PROVIDER.to_string()
+}
+
+fn default_models_cache_file() -> String {
+    catalog::DEFAULT_MODELS_CACHE_FILE.to_string()
+}
+
+fn default_models_cache_ttl() -> u64 {
+    catalog::DEFAULT_MODELS_CACHE_TTL
+}
+
+fn default_images_dir() -> String {
+    images::DEFAULT_IMAGES_DIR.to_string()
+}
+
+fn default_index_dir() -> String {
+    rag::DEFAULT_INDEX_DIR.to_string()
+}
+
+fn default_prompts_dir() -> String {
+    prompts::DEFAULT_PROMPTS_DIR.to_string()
+}
+
+fn default_search_index_file() -> String {
+    search::DEFAULT_SEARCH_INDEX_FILE.to_string()
+}
+
+fn default_usage_file() -> String {
+    usage::DEFAULT_USAGE_FILE.to_string()
 }
 
 impl Settings {
@@ -21,13 +99,182 @@ impl Settings {
         if !path.exists() {
             return Ok(());
         }
-        let empty = Messages::new();
-        let text = serde_json::to_string(&empty)?;
-        Ok(fs::write(path, text)?)
+        let _lock = self.lock_history()?;
+        self.history_store().replace(&Messages::new())?;
+        // the active branch ended in a message that is gone now
+        let mut info = self.session_info()?;
+        if info.branch.take().is_some() {
+            self.write_session_info(&info)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Self::create(DEFAULT_FILE_NAME, DEFAULT_CONFIG_FILE, DEFAULT_MODEL)
     }
 }
 
 impl Settings {
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    // only changes the model for this run, the config file is not updated
+    pub fn set_model(&mut self, model: &str) {
+        self.model = model.to_string();
+    }
+
+    pub fn temperature(&self) -> Option<f32> {
+        self.temperature
+    }
+
+    // like the model, only for this run
+    pub fn set_temperature(&mut self, temperature: f32) {
+        self.temperature = Some(temperature);
+    }
+
+    pub fn context(&self) -> &ContextSettings {
+        &self.context
+    }
+
+    pub fn usage_file(&self) -> &str {
+        &self.usage_file
+    }
+
+    pub fn prices(&self) -> &HashMap<String, Price> {
+        &self.prices
+    }
+
+    pub fn budget(&self) -> &Budget {
+        &self.budget
+    }
+
+    pub fn models_cache_file(&self) -> &str {
+        &self.models_cache_file
+    }
+
+    pub fn models_cache_ttl(&self) -> u64 {
+        self.models_cache_ttl
+    }
+
+    pub fn capabilities(&self) -> &HashMap<String, Capabilities> {
+        &self.capabilities
+    }
+
+    pub fn images_dir(&self) -> &str {
+        &self.images_dir
+    }
+
+    pub fn index_dir(&self) -> &str {
+        &self.index_dir
+    }
+
+    pub fn prompts_dir(&self) -> &str {
+        &self.prompts_dir
+    }
+
+    pub fn search_index_file(&self) -> &str {
+        &self.search_index_file
+    }
+
+    pub fn provider(&self) -> &str {
+        &self.provider
+    }
+
+    pub fn providers(&self) -> &HashMap<String, ProviderSettings> {
+        &self.providers
+    }
+
+    // the session is named after the history file, `rustgpt/conversation.json` is session `conversation`
+    pub fn session_name(&self) -> String {
+        Path::new(&self.history_file)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| self.history_file.clone())
+    }
+
+    // switches to the history of another session, stored next to the current history file.
+    // New sessions use the backend of the current history, existing ones keep theirs.
+    pub fn set_session(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid session name '{name}'"))));
+        }
+        let dir = Path::new(&self.history_file).parent().unwrap_or(Path::new(""));
+        let path = |backend: Backend| dir.join(format!("{name}.{}", backend.extension()));
+        let path = store::BACKENDS.into_iter().map(path).find(|path| path.exists())
+            .unwrap_or_else(|| path(self.history_backend()));
+        self.history_file = path.to_string_lossy().into_owned();
+        Ok(())
+    }
+
+    pub fn history_file(&self) -> &str {
+        &self.history_file
+    }
+
+    // only used by `migrate`, which also saves the settings
+    pub fn set_history_file(&mut self, history_file: &str) {
+        self.history_file = history_file.to_string();
+    }
+
+    pub fn history_backend(&self) -> Backend {
+        Backend::from_path(Path::new(&self.history_file))
+    }
+
+    pub fn history_store(&self) -> Box<dyn HistoryStore> {
+        store::open(Path::new(&self.history_file))
+    }
+
+    // held by commands while they read and write the history, so concurrent runs do not lose changes
+    pub fn lock_history(&self) -> Result<Lock, Box<dyn Error>> {
+        Lock::exclusive(Path::new(&self.history_file))
+    }
+
+    // the names and history files of all sessions, the history files of every backend next to the current history
+    pub fn sessions(&self) -> Result<Vec<(String, PathBuf)>, Box<dyn Error>> {
+        let dir = Path::new(&self.history_file).parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        if !dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let other_files = [&self.config_file, &self.models_cache_file, &self.search_index_file, &self.usage_file].map(Path::new);
+        let mut sessions = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+            let Some(session) = store::BACKENDS.iter().find_map(|backend| name.strip_suffix(&format!(".{}", backend.extension()))) else {
+                continue;
+            };
+            if session.ends_with(".meta") || other_files.iter().any(|other| other.file_name() == path.file_name() && other.parent() == path.parent()) {
+                continue;
+            }
+            sessions.push((session.to_string(), path));
+        }
+        sessions.sort();
+        Ok(sessions)
+    }
+
+    fn session_info_file(&self) -> PathBuf {
+        Path::new(&self.history_file).with_extension("meta.json")
+    }
+
+    // the title and timestamps of the session, empty when they were never stored
+    pub fn session_info(&self) -> Result<SessionInfo, Box<dyn Error>> {
+        let path = self.session_info_file();
+        if !path.exists() {
+            return Ok(SessionInfo::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn write_session_info(&self, info: &SessionInfo) -> Result<(), Box<dyn Error>> {
+        let path = self.session_info_file();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(fs::write(path, serde_json::to_string(info)?)?)
+    }
+
     pub fn from_file(path: &str) -> Result<Settings, Box<dyn Error>> {
         let config_content = fs::read_to_string(path)?;
         Ok(serde_json::from_str(&config_content)?)
@@ -47,6 +294,20 @@ impl Settings {
             model: model.to_string(),
             history_file: history_file.to_string(),
             config_file: settings_file.to_string(),
+            context: ContextSettings::default(),
+            usage_file: default_usage_file(),
+            prices: HashMap::new(),
+            budget: Budget::default(),
+            models_cache_file: default_models_cache_file(),
+            models_cache_ttl: default_models_cache_ttl(),
+            capabilities: HashMap::new(),
+            provider: default_provider(),
+            providers: HashMap::new(),
+            images_dir: default_images_dir(),
+            index_dir: default_index_dir(),
+            prompts_dir: default_prompts_dir(),
+            search_index_file: default_search_index_file(),
+            temperature: None,
         };
         if let Err(e) = settings.save() {
             log::warn!("Could not save settings: {}", e);
@@ -54,29 +315,40 @@ impl Settings {
         settings
     }
 
-    pub fn default() -> Settings {
-        Self::create(DEFAULT_FILE_NAME, DEFAULT_CONFIG_FILE, DEFAULT_MODEL)
+    // every stored message of every branch, returns error if the file exists but could not be parsed
+    pub fn get_history(&self) -> Result<Messages, Box<dyn Error>> {
+        let mut messages = self.history_store().load()?;
+        tree::link(&mut messages, 0);
+        Ok(messages)
     }
 
-    // read the history file if it exists, or create a new one if it doesn't
-    // returns error if the file exists but could not be parsed
-    pub fn get_history(&self) -> Result<Messages, Box<dyn Error>> {
-        let path = Path::new(&self.history_file);
-        if path.exists() {
-            let contents = fs::read_to_string(path)?;
-            Ok(serde_json::from_str::<Messages>(&contents)?)
-        } else {
+    // the active branch, from the last `context.load_limit` stored messages or all of them
+    pub fn get_branch(&self) -> Result<(Messages, Snapshot), Box<dyn Error>> {
+        let info = self.session_info()?;
+        self.get_branch_at(info.branch.as_deref())
+    }
+
+    // the branch ending at the message `leaf`, or at the last stored message
+    pub fn get_branch_at(&self, leaf: Option<&str>) -> Result<(Messages, Snapshot), Box<dyn Error>> {
+        if !Path::new(&self.history_file).exists() {
             eprintln!("Creating new history");
-            Ok(Messages(vec![]))
         }
+        store::load_branch(self.history_store().as_ref(), self.context.load_limit, leaf)
     }
 
-    pub fn write_history(&self, history: Messages) -> Result<(), Box<dyn Error>> {
-        let path = Path::new(&self.history_file);
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)?;
+    // writes the changes to the branch, a branch that was switched to stays active
+    pub fn save_branch(&self, snapshot: &Snapshot, branch: &Messages) -> Result<(), Box<dyn Error>> {
+        let leaf = snapshot.save(self.history_store().as_ref(), branch)?;
+        let mut info = self.session_info()?;
+        if info.branch.is_some() && leaf.is_some() {
+            info.branch = leaf;
+            self.write_session_info(&info)?;
         }
-        let ser = serde_json::to_string(&history)?;
-        Ok(fs::write(path, ser)?)
+        Ok(())
+    }
+
+    // rewrites the whole history, commands that change a branch save it with `save_branch` instead
+    pub fn write_history(&self, history: Messages) -> Result<(), Box<dyn Error>> {
+        self.history_store().replace(&history)
     }
 }
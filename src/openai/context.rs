@@ -0,0 +1,173 @@
+use serde::{Deserialize, Serialize};
+use crate::openai::{Content, ContentPart, Message, Messages, Role, Summary};
+
+pub const DEFAULT_MAX_TOKENS: usize = 12_000;
+pub const DEFAULT_WINDOW: usize = 20;
+pub const DEFAULT_KEEP_RECENT: usize = 6;
+pub const DEFAULT_LOAD_LIMIT: usize = 1000;
+
+// rough number of tokens added by the api for every message (role, separators)
+const MESSAGE_OVERHEAD: usize = 4;
+// images and audio are counted as a 512x512 image at high detail
+const MEDIA_TOKENS: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextStrategy {
+    // send the full history
+    None,
+    // drop the oldest turns until the history fits in `max_tokens`
+    DropOldest,
+    // keep system and pinned messages plus the last `window` messages
+    SlidingWindow,
+    // replace everything but the last `keep_recent` messages by a summary
+    Summarize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ContextSettings {
+    pub strategy: ContextStrategy,
+    pub max_tokens: usize,
+    pub window: usize,
+    pub keep_recent: usize,
+    // only the last messages are read from the history, older messages are not sent even when pinned.
+    // Set to null to read every stored message before each request.
+    pub load_limit: Option<usize>,
+}
+
+impl Default for ContextSettings {
+    fn default() -> Self {
+        ContextSettings {
+            strategy: ContextStrategy::DropOldest,
+            max_tokens: DEFAULT_MAX_TOKENS,
+            window: DEFAULT_WINDOW,
+            keep_recent: DEFAULT_KEEP_RECENT,
+            load_limit: Some(DEFAULT_LOAD_LIMIT),
+        }
+    }
+}
+
+// there is no tokenizer available, roughly 4 characters make up a token for english text
+pub fn estimate_text_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+pub fn estimate_tokens(msg: &Message) -> usize {
+    let mut tokens = MESSAGE_OVERHEAD + estimate_text_tokens(&msg.text());
+    if let Some(Content::Parts(parts)) = &msg.content {
+        tokens += parts.iter().filter(|part| !matches!(part, ContentPart::Text { .. })).count() * MEDIA_TOKENS;
+    }
+    if let Some(refusal) = &msg.refusal {
+        tokens += estimate_text_tokens(refusal);
+    }
+    for tool_call in msg.tool_calls.iter().flatten() {
+        tokens += estimate_text_tokens(&tool_call.function.name) + estimate_text_tokens(&tool_call.function.arguments);
+    }
+    if let Some(name) = &msg.name {
+        tokens += estimate_text_tokens(name);
+    }
+    tokens
+}
+
+pub fn estimate_history_tokens(messages: &[Message]) -> usize {
+    messages.iter().map(estimate_tokens).sum()
+}
+
+fn is_kept(msg: &Message) -> bool {
+    msg.role == Role::System || msg.pinned
+}
+
+// removes tool results whose call is no longer part of the messages, the api rejects those
+pub fn drop_orphaned_tool_messages(messages: &mut Messages) {
+    let mut call_ids = vec![];
+    messages.0.retain(|msg| {
+        call_ids.extend(msg.tool_calls.iter().flatten().map(|tool_call| tool_call.id.clone()));
+        msg.role != Role::Tool || msg.tool_call_id.as_ref().is_some_and(|id| call_ids.contains(id))
+    });
+}
+
+// drops the oldest messages until the history fits in `max_tokens`, system and pinned messages are
+// always kept. The last message is always kept, and the remaining history never starts with
+// an assistant or tool message that has lost the user message it answered.
+pub fn drop_oldest(history: &Messages, max_tokens: usize) -> Messages {
+    let mut messages = history.0.clone();
+    let mut total = estimate_history_tokens(&messages);
+    let mut index = 0;
+    while total > max_tokens && index < messages.len().saturating_sub(1) {
+        if is_kept(&messages[index]) {
+            index += 1;
+            continue;
+        }
+        total -= estimate_tokens(&messages.remove(index));
+    }
+    while let Some(pos) = messages.iter().position(|msg| !is_kept(msg)) {
+        if messages[pos].role == Role::User || pos == messages.len() - 1 {
+            break;
+        }
+        messages.remove(pos);
+    }
+    Messages(messages)
+}
+
+// keeps the last `window` non system messages, system and pinned messages are always kept.
+pub fn sliding_window(history: &Messages, window: usize) -> Messages {
+    let conversation_len = history.0.iter().filter(|msg| msg.role != Role::System).count();
+    let first_in_window = conversation_len.saturating_sub(window);
+    let mut conversation_index = 0;
+    let mut messages = vec![];
+    for msg in &history.0 {
+        if msg.role == Role::System {
+            messages.push(msg.clone());
+            continue;
+        }
+        if conversation_index >= first_in_window || msg.pinned {
+            messages.push(msg.clone());
+        }
+        conversation_index += 1;
+    }
+    Messages(messages)
+}
+
+// splits the history in the messages that should be summarized and the ones that are sent as is.
+// Returns None when the history already fits in `max_tokens` or there is nothing to summarize.
+pub fn split_for_summary(history: &Messages, settings: &ContextSettings) -> Option<(Messages, Messages)> {
+    if estimate_history_tokens(&history.0) <= settings.max_tokens {
+        return None;
+    }
+    let conversation_len = history.0.iter().filter(|msg| !is_kept(msg)).count();
+    let mut to_summarize = conversation_len.saturating_sub(settings.keep_recent);
+    if to_summarize == 0 {
+        return None;
+    }
+    let mut old = vec![];
+    let mut recent = vec![];
+    for msg in &history.0 {
+        if is_kept(msg) || to_summarize == 0 {
+            recent.push(msg.clone());
+        } else {
+            old.push(msg.clone());
+            to_summarize -= 1;
+        }
+    }
+    Some((Messages(old), Messages(recent)))
+}
+
+// the newest summary stored in the history and the messages of `old` after the last one it covers,
+// None when it covers none of them
+pub fn cached_summary<'a>(history: &'a Messages, old: &'a Messages) -> Option<(&'a Summary, &'a [Message])> {
+    let summary = history.0.iter().rev().find_map(|msg| msg.metadata.as_ref()?.summary.as_ref())?;
+    let covered = old.0.iter().position(|msg| msg.id.as_deref() == Some(summary.until.as_str()))?;
+    Some((summary, &old.0[covered + 1..]))
+}
+
+// applies the strategies that do not need the api, `Summarize` falls back to `DropOldest`.
+pub fn trim(history: &Messages, settings: &ContextSettings) -> Messages {
+    let mut messages = match settings.strategy {
+        ContextStrategy::None => history.clone(),
+        ContextStrategy::DropOldest | ContextStrategy::Summarize => drop_oldest(history, settings.max_tokens),
+        ContextStrategy::SlidingWindow => sliding_window(history, settings.window),
+    };
+    drop_orphaned_tool_messages(&mut messages);
+    messages
+}
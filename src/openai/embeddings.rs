@@ -0,0 +1,170 @@
+use std::error::Error;
+use std::io;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+use crate::openai::Usage;
+use crate::provider;
+
+pub const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+// the api takes at most 2048 inputs per request
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodingFormat {
+    #[default]
+    Float,
+    // little endian f32 values, a quarter of the size of the json numbers
+    Base64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EmbeddingRequest {
+    pub model: String,
+    pub input: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
+    pub encoding_format: EncodingFormat,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum EmbeddingVector {
+    Float(Vec<f32>),
+    Base64(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: EmbeddingVector,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+    #[serde(default)]
+    usage: Option<EmbeddingUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingUsage {
+    prompt_tokens: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct EmbeddingOptions {
+    pub model: String,
+    /// shortens the vectors, only supported by the `text-embedding-3` models
+    pub dimensions: Option<u32>,
+    pub encoding_format: EncodingFormat,
+    pub batch_size: usize,
+}
+
+impl Default for EmbeddingOptions {
+    fn default() -> Self {
+        EmbeddingOptions {
+            model: DEFAULT_EMBEDDING_MODEL.to_string(),
+            dimensions: None,
+            encoding_format: EncodingFormat::default(),
+            batch_size: DEFAULT_BATCH_SIZE,
+        }
+    }
+}
+
+/// The vectors in the order of the inputs, and the tokens used for all batches
+#[derive(Debug, Default)]
+pub struct Embeddings {
+    pub vectors: Vec<Vec<f32>>,
+    pub usage: Usage,
+}
+
+fn decode_base64(data: &str) -> Result<Vec<f32>, Box<dyn Error>> {
+    let bytes = STANDARD.decode(data)?;
+    if bytes.len() % 4 != 0 {
+        return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, "Base64 embedding is not a list of f32 values")));
+    }
+    Ok(bytes.chunks_exact(4).map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]])).collect())
+}
+
+async fn request(api_key: &str, base_url: &str, client: &reqwest::Client, request: &EmbeddingRequest) -> Result<EmbeddingResponse, Box<dyn Error>> {
+    log::debug!("POST {base_url}/embeddings with {} inputs", request.input.len());
+    let response = client.post(format!("{base_url}/embeddings"))
+        .header("Authorization", format!("Bearer {api_key}"))
+        .json(request)
+        .send().await?;
+    if response.status() != 200 {
+        let status = response.status();
+        return Err(provider::api_error("openai", status, &response.text().await?));
+    }
+    Ok(response.json().await?)
+}
+
+/// Embeds the inputs with the openai api in batches of `options.batch_size`
+pub(crate) async fn embed(api_key: &str, base_url: &str, client: &reqwest::Client, inputs: &[String], options: &EmbeddingOptions) -> Result<Embeddings, Box<dyn Error>> {
+    let mut embeddings = Embeddings::default();
+    for batch in inputs.chunks(options.batch_size.max(1)) {
+        let request = EmbeddingRequest {
+            model: options.model.clone(),
+            input: batch.to_vec(),
+            dimensions: options.dimensions,
+            encoding_format: options.encoding_format,
+        };
+        let mut response = self::request(api_key, base_url, client, &request).await?;
+        check_count(batch.len(), response.data.len())?;
+        response.data.sort_by_key(|data| data.index);
+        for data in response.data {
+            embeddings.vectors.push(match data.embedding {
+                EmbeddingVector::Float(vector) => vector,
+                EmbeddingVector::Base64(encoded) => decode_base64(&encoded)?,
+            });
+        }
+        if let Some(usage) = response.usage {
+            embeddings.usage.prompt_tokens += usage.prompt_tokens;
+            embeddings.usage.total_tokens += usage.prompt_tokens;
+        }
+    }
+    Ok(embeddings)
+}
+
+/// Fails when the api returned another number of vectors than it was sent inputs
+pub(crate) fn check_count(inputs: usize, vectors: usize) -> Result<(), Box<dyn Error>> {
+    if inputs != vectors {
+        return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, format!("Expected {inputs} embeddings, got {vectors}"))));
+    }
+    Ok(())
+}
+
+pub fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+pub fn norm(vector: &[f32]) -> f32 {
+    dot(vector, vector).sqrt()
+}
+
+/// Between -1 and 1, 0 when one of the vectors is all zeros
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 { 0.0 } else { dot(a, b) / norms }
+}
+
+/// Scales the vector to length 1, after which the dot product equals the cosine similarity
+pub fn normalize(vector: &mut [f32]) {
+    let norm = norm(vector);
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|value| *value /= norm);
+    }
+}
+
+/// The indices and similarities of the `k` candidates most similar to `query`, most similar first
+pub fn top_k<'a>(query: &[f32], candidates: impl IntoIterator<Item = &'a [f32]>, k: usize) -> Vec<(usize, f32)> {
+    let mut scores = candidates.into_iter()
+        .enumerate()
+        .map(|(index, candidate)| (index, cosine_similarity(query, candidate)))
+        .collect::<Vec<_>>();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+    scores.truncate(k);
+    scores
+}
@@ -0,0 +1,235 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::io;
+use std::path::Path;
+use serde::Serialize;
+use crate::openai::{Content, Message, Messages, OpenaiFunction, Role, Tool};
+use crate::openai::images::IMAGE_REF_SCHEME;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Markdown,
+    Html,
+    Json,
+    // one line of OpenAI chat fine-tuning training data
+    FinetuneJsonl,
+}
+
+impl Format {
+    pub fn parse(value: &str) -> Option<Format> {
+        match value {
+            "md" | "markdown" => Some(Format::Markdown),
+            "html" => Some(Format::Html),
+            "json" => Some(Format::Json),
+            "jsonl-finetune" => Some(Format::FinetuneJsonl),
+            _ => None,
+        }
+    }
+}
+
+fn heading(role: Role) -> &'static str {
+    match role {
+        Role::System => "System",
+        Role::Developer => "Developer",
+        Role::User => "User",
+        Role::Assistant => "Assistant",
+        Role::Tool => "Tool",
+        Role::Function => "Function",
+    }
+}
+
+// a fence longer than any backtick run in the text
+fn fence_for(text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+fn fenced(language: &str, text: &str) -> String {
+    let fence = fence_for(text);
+    format!("{fence}{language}\n{}\n{fence}", text.trim_end())
+}
+
+// stored images are linked by their path, everything else by their url
+fn image_source(url: &str, images_dir: &str) -> String {
+    match url.strip_prefix(IMAGE_REF_SCHEME) {
+        Some(file_name) => Path::new(images_dir).join(file_name).to_string_lossy().replace('\\', "/"),
+        None => url.to_string(),
+    }
+}
+
+// pretty printed arguments, or the raw ones when they are not json
+fn arguments(arguments: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(arguments)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or(arguments.to_string())
+}
+
+/// A heading per message, tool calls and tool results in fences
+pub fn markdown(messages: &Messages, title: &str, images_dir: &str) -> String {
+    let mut blocks = vec![format!("# {title}")];
+    for msg in &messages.0 {
+        blocks.push(format!("## {}", heading(msg.role)));
+        let text = msg.text();
+        if !text.is_empty() {
+            blocks.push(if msg.role == Role::Tool { fenced("", &text) } else { text.into_owned() });
+        }
+        for image in msg.images() {
+            blocks.push(format!("![image]({})", image_source(&image.url, images_dir)));
+        }
+        if let Some(refusal) = &msg.refusal {
+            blocks.push(format!("> {refusal}"));
+        }
+        for tool_call in msg.tool_calls.iter().flatten() {
+            blocks.push(format!("Call to `{}`:\n\n{}", tool_call.function.name, fenced("json", &arguments(&tool_call.function.arguments))));
+        }
+    }
+    blocks.join("\n\n") + "\n"
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn html_code(language: &str, code: &str) -> String {
+    let class = if language.is_empty() { "language-plaintext".to_string() } else { format!("language-{}", escape(language)) };
+    format!("<pre><code class=\"{class}\">{}</code></pre>", escape(code))
+}
+
+// paragraphs and inline code, fenced code blocks become highlighted code
+fn html_text(text: &str) -> String {
+    let mut html = String::new();
+    let mut paragraph: Vec<&str> = vec![];
+    let mut code: Option<(String, &str, Vec<&str>)> = None;
+    let flush = |paragraph: &mut Vec<&str>, html: &mut String| {
+        if !paragraph.is_empty() {
+            let escaped = paragraph.iter().map(|line| inline_code(&escape(line))).collect::<Vec<_>>().join("<br>\n");
+            html.push_str(&format!("<p>{escaped}</p>\n"));
+            paragraph.clear();
+        }
+    };
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        match &mut code {
+            Some((fence, language, lines)) => {
+                if trimmed.starts_with(fence.as_str()) && trimmed.trim_start_matches('`').trim().is_empty() {
+                    html.push_str(&html_code(language, &lines.join("\n")));
+                    html.push('\n');
+                    code = None;
+                } else {
+                    lines.push(line);
+                }
+            }
+            None if trimmed.starts_with("```") => {
+                flush(&mut paragraph, &mut html);
+                let fence = trimmed.chars().take_while(|c| *c == '`').collect::<String>();
+                let language = trimmed[fence.len()..].trim();
+                code = Some((fence, language, vec![]));
+            }
+            None if trimmed.is_empty() => flush(&mut paragraph, &mut html),
+            None => paragraph.push(line),
+        }
+    }
+    // an unclosed fence runs to the end of the text
+    if let Some((_, language, lines)) = code {
+        html.push_str(&html_code(language, &lines.join("\n")));
+        html.push('\n');
+    }
+    flush(&mut paragraph, &mut html);
+    html
+}
+
+fn inline_code(escaped: &str) -> String {
+    let mut html = String::new();
+    for (index, part) in escaped.split('`').enumerate() {
+        if index % 2 == 1 {
+            html.push_str(&format!("<code>{part}</code>"));
+        } else {
+            html.push_str(part);
+        }
+    }
+    html
+}
+
+const HTML_STYLE: &str = "body{font-family:system-ui,sans-serif;max-width:50rem;margin:2rem auto;padding:0 1rem;line-height:1.5}\
+    .message{border-left:4px solid #ccc;padding:.25rem 1rem;margin:1rem 0}\
+    .user{border-color:#4a90d9}.assistant{border-color:#50a060}.tool{border-color:#d9a04a}.system{border-color:#999}\
+    .role{font-weight:bold;text-transform:capitalize}.refusal{color:#b03030}\
+    pre{background:#f6f8fa;padding:.75rem;overflow-x:auto}img{max-width:100%}";
+const HIGHLIGHT_URL: &str = "https://cdnjs.cloudflare.com/ajax/libs/highlight.js/11.9.0";
+
+/// A single html file, images should be resolved to `data:` urls first. The code is highlighted with
+/// highlight.js when it can be loaded, and stays readable without it.
+pub fn html(messages: &Messages, title: &str) -> String {
+    let mut body = String::new();
+    for msg in &messages.0 {
+        body.push_str(&format!("<div class=\"message {}\">\n<div class=\"role\">{}</div>\n", msg.role, heading(msg.role)));
+        let text = msg.text();
+        if msg.role == Role::Tool {
+            body.push_str(&html_code("", &text));
+        } else {
+            body.push_str(&html_text(&text));
+        }
+        for image in msg.images() {
+            body.push_str(&format!("<img src=\"{}\" alt=\"image\">\n", escape(&image.url)));
+        }
+        if let Some(refusal) = &msg.refusal {
+            body.push_str(&format!("<p class=\"refusal\">{}</p>\n", escape(refusal)));
+        }
+        for tool_call in msg.tool_calls.iter().flatten() {
+            body.push_str(&format!("<p>Call to <code>{}</code>:</p>\n", escape(&tool_call.function.name)));
+            body.push_str(&html_code("json", &arguments(&tool_call.function.arguments)));
+        }
+        body.push_str("</div>\n");
+    }
+    format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n\
+        <style>{HTML_STYLE}</style>\n\
+        <link rel=\"stylesheet\" href=\"{HIGHLIGHT_URL}/styles/github.min.css\">\n\
+        <script src=\"{HIGHLIGHT_URL}/highlight.min.js\"></script>\n\
+        </head>\n<body>\n<h1>{title}</h1>\n{body}\
+        <script>if (window.hljs) {{ hljs.highlightAll(); }}</script>\n</body>\n</html>\n", title = escape(title))
+}
+
+#[derive(Serialize)]
+struct TrainingExample {
+    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<Tool>,
+}
+
+/// The conversation as one line of chat fine-tuning data: it ends with the last assistant reply,
+/// refusals and local fields are left out, and the definitions of the known `functions` that are
+/// called are added. Images should be resolved to `data:` urls first.
+pub fn finetune_jsonl(messages: &Messages, functions: &[OpenaiFunction]) -> Result<String, Box<dyn Error>> {
+    let last_reply = messages.0.iter()
+        .rposition(|msg| msg.role == Role::Assistant && msg.refusal.is_none())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "A training example needs an assistant reply"))?;
+    let mut called = HashSet::new();
+    let mut training_messages = vec![];
+    for msg in &messages.0[..=last_reply] {
+        if msg.refusal.is_some() {
+            continue;
+        }
+        for tool_call in msg.tool_calls.iter().flatten() {
+            called.insert(tool_call.function.name.as_str());
+        }
+        let content = match &msg.content {
+            // only user messages may contain images
+            Some(Content::Parts(_)) if msg.role != Role::User => Some(Content::Text(msg.text().into_owned())),
+            content => content.clone(),
+        };
+        training_messages.push(Message {
+            content,
+            role: msg.role,
+            tool_calls: msg.tool_calls.clone(),
+            tool_call_id: msg.tool_call_id.clone(),
+            name: msg.name.clone(),
+            ..Default::default()
+        });
+    }
+    let tools = functions.iter()
+        .filter(|function| called.contains(function.name.as_str()))
+        .cloned()
+        .map(Tool::function)
+        .collect();
+    Ok(serde_json::to_string(&TrainingExample { messages: training_messages, tools })?)
+}
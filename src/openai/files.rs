@@ -0,0 +1,133 @@
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use globset::GlobBuilder;
+
+// larger files are most likely generated or data
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 256 * 1024;
+
+fn invalid_input(msg: String) -> Box<dyn Error> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// The files below `root` that are not hidden or ignored by `.gitignore`, sorted by path
+pub fn walk(root: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    for entry in ignore::WalkBuilder::new(root).build() {
+        match entry {
+            Ok(entry) if entry.file_type().is_some_and(|file_type| file_type.is_file()) => files.push(entry.into_path()),
+            Ok(_) => {}
+            Err(e) => log::warn!("Skipping {e}"),
+        }
+    }
+    files.sort();
+    files
+}
+
+// the files matching a pattern like `src/**/*.rs`, searched from the directory before the first wildcard
+fn glob(pattern: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let matcher = GlobBuilder::new(pattern).literal_separator(true).build()?.compile_matcher();
+    let base = Path::new(pattern).components()
+        .take_while(|part| !is_glob(&part.as_os_str().to_string_lossy()))
+        .collect::<PathBuf>();
+    let root = if base.as_os_str().is_empty() { Path::new(".") } else { base.as_path() };
+    Ok(walk(root).into_iter()
+        .filter(|path| matcher.is_match(path.strip_prefix("./").unwrap_or(path)))
+        .collect())
+}
+
+/// Expands globs and directories into the files they contain, skipping ignored files.
+/// Files that are named explicitly are always included.
+pub fn expand(patterns: &[&str]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = vec![];
+    for pattern in patterns {
+        let path = Path::new(pattern);
+        let found = if path.is_dir() {
+            walk(path)
+        } else if path.is_file() {
+            vec![path.to_path_buf()]
+        } else if is_glob(pattern) {
+            glob(pattern)?
+        } else {
+            return Err(invalid_input(format!("{pattern} does not exist")));
+        };
+        if found.is_empty() {
+            log::warn!("No files found for {pattern}");
+        }
+        for file in found {
+            if !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Reads a text file, `None` for binary files and files larger than `max_size` bytes
+pub fn read_text(path: &Path, max_size: u64) -> Result<Option<String>, Box<dyn Error>> {
+    if fs::metadata(path)?.len() > max_size {
+        log::info!("Skipping {}, it is larger than {max_size} bytes", path.display());
+        return Ok(None);
+    }
+    let bytes = fs::read(path)?;
+    if bytes.contains(&0) {
+        log::info!("Skipping binary file {}", path.display());
+        return Ok(None);
+    }
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok(Some(text)),
+        Err(_) => {
+            log::info!("Skipping {}, it is not utf-8 text", path.display());
+            Ok(None)
+        }
+    }
+}
+
+/// The markdown name of the language of a file, used to tag its code fence
+pub fn language(path: &Path) -> &'static str {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "rs" => "rust",
+        "md" | "markdown" => "markdown",
+        "py" => "python",
+        "js" | "mjs" | "cjs" => "javascript",
+        "ts" => "typescript",
+        "tsx" => "tsx",
+        "jsx" => "jsx",
+        "json" => "json",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "xml" => "xml",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "sh" | "bash" => "bash",
+        "ps1" | "psm1" => "powershell",
+        "c" | "h" => "c",
+        "cpp" | "cc" | "hpp" => "cpp",
+        "cs" => "csharp",
+        "go" => "go",
+        "java" => "java",
+        "kt" => "kotlin",
+        "rb" => "ruby",
+        "sql" => "sql",
+        _ => "",
+    }
+}
+
+/// The path followed by the contents in a code fence, the fence is longer than any backtick run in the contents
+pub fn fence(path: &str, language: &str, contents: &str) -> String {
+    let mut longest = 0;
+    let mut run = 0;
+    for c in contents.chars() {
+        run = if c == '`' { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    let fence = "`".repeat(longest.max(2) + 1);
+    let newline = if contents.ends_with('\n') { "" } else { "\n" };
+    format!("{path}:\n{fence}{language}\n{contents}{newline}{fence}")
+}
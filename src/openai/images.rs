@@ -0,0 +1,104 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::Path;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use image::ImageFormat;
+use image::imageops::FilterType;
+use sha2::{Digest, Sha256};
+use crate::openai::{Content, ContentPart, ImageUrl, Messages};
+
+pub const DEFAULT_IMAGES_DIR: &str = "rustgpt/images";
+// the history refers to stored images as `rustgpt-image:<sha256>.<extension>`
+pub const IMAGE_REF_SCHEME: &str = "rustgpt-image:";
+
+const DETAILS: [&str; 3] = ["low", "high", "auto"];
+
+fn invalid_input(msg: String) -> Box<dyn Error> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+fn media_type(format: ImageFormat) -> Option<&'static str> {
+    match format {
+        ImageFormat::Png => Some("image/png"),
+        ImageFormat::Jpeg => Some("image/jpeg"),
+        ImageFormat::WebP => Some("image/webp"),
+        ImageFormat::Gif => Some("image/gif"),
+        _ => None,
+    }
+}
+
+/// Reads a png, jpeg, webp or gif file. When a side is longer than `max_size` pixels the image
+/// is scaled down, keeping the aspect ratio, and stored as jpeg or otherwise png.
+pub fn load(path: &str, max_size: Option<u32>) -> Result<(Vec<u8>, ImageFormat), Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    let format = image::guess_format(&bytes).ok()
+        .filter(|format| media_type(*format).is_some())
+        .ok_or_else(|| invalid_input(format!("{path} is not a png, jpeg, webp or gif image")))?;
+    let Some(max_size) = max_size else {
+        return Ok((bytes, format));
+    };
+    let image = image::load_from_memory_with_format(&bytes, format)?;
+    if image.width() <= max_size && image.height() <= max_size {
+        return Ok((bytes, format));
+    }
+    let resized = image.resize(max_size, max_size, FilterType::Lanczos3);
+    log::info!("Downscaled {path} from {}x{} to {}x{}", image.width(), image.height(), resized.width(), resized.height());
+    let format = if format == ImageFormat::Jpeg { ImageFormat::Jpeg } else { ImageFormat::Png };
+    let resized = if format == ImageFormat::Jpeg { image::DynamicImage::ImageRgb8(resized.to_rgb8()) } else { resized };
+    let mut encoded = Cursor::new(vec![]);
+    resized.write_to(&mut encoded, format)?;
+    Ok((encoded.into_inner(), format))
+}
+
+/// Copies an image into `dir`, named after its hash, and returns the content part referring to it
+pub fn store(dir: &str, path: &str, max_size: Option<u32>, detail: Option<&str>) -> Result<ContentPart, Box<dyn Error>> {
+    if let Some(detail) = detail.filter(|detail| !DETAILS.contains(detail)) {
+        return Err(invalid_input(format!("Invalid detail '{detail}', expected low, high or auto")));
+    }
+    let (bytes, format) = load(path, max_size)?;
+    let hash = Sha256::digest(&bytes).iter().map(|byte| format!("{byte:02x}")).collect::<String>();
+    let file_name = format!("{hash}.{}", format.extensions_str()[0]);
+    let target = Path::new(dir).join(&file_name);
+    if !target.exists() {
+        fs::create_dir_all(dir)?;
+        fs::write(&target, &bytes)?;
+    }
+    Ok(ContentPart::ImageUrl {
+        image_url: ImageUrl {
+            url: format!("{IMAGE_REF_SCHEME}{file_name}"),
+            detail: detail.map(str::to_string),
+        },
+    })
+}
+
+/// Replaces the references to stored images by `data:` urls, right before the messages are sent
+pub fn resolve(messages: &mut Messages, dir: &str) -> Result<(), Box<dyn Error>> {
+    for msg in &mut messages.0 {
+        let Some(Content::Parts(parts)) = &mut msg.content else {
+            continue;
+        };
+        for part in parts {
+            let ContentPart::ImageUrl { image_url } = part else {
+                continue;
+            };
+            let Some(file_name) = image_url.url.strip_prefix(IMAGE_REF_SCHEME) else {
+                continue;
+            };
+            let path = Path::new(dir).join(file_name);
+            let bytes = fs::read(&path)
+                .map_err(|e| invalid_input(format!("Stored image {} can not be read: {e}", path.display())))?;
+            let media_type = ImageFormat::from_path(&path).ok()
+                .and_then(media_type)
+                .unwrap_or("application/octet-stream");
+            image_url.url = format!("data:{media_type};base64,{}", STANDARD.encode(bytes));
+        }
+    }
+    Ok(())
+}
+
+/// Splits a `data:` url into the media type and the base64 data, for apis that take raw image data
+pub fn parse_data_url(url: &str) -> Option<(&str, &str)> {
+    url.strip_prefix("data:")?.split_once(";base64,")
+}
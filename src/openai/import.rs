@@ -0,0 +1,33 @@
+mod chatgpt;
+
+use std::error::Error;
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use crate::openai::Messages;
+
+pub use chatgpt::ChatGptImporter;
+
+#[derive(Debug, Clone)]
+pub struct ImportedConversation {
+    /// unique within the export, used to name the session
+    pub id: String,
+    pub title: String,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    pub messages: Messages,
+}
+
+/// Reads the conversations of another tool, every conversation becomes a session
+pub trait Importer {
+    /// the name used on the command line, `rustgpt import <name> <path>`
+    fn name(&self) -> &'static str;
+    fn import(&self, path: &Path) -> Result<Vec<ImportedConversation>, Box<dyn Error>>;
+}
+
+pub fn importers() -> Vec<Box<dyn Importer>> {
+    vec![Box::new(ChatGptImporter)]
+}
+
+pub fn find(name: &str) -> Option<Box<dyn Importer>> {
+    importers().into_iter().find(|importer| importer.name() == name)
+}
@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use crate::openai::{ChatHistory, Messages, Role};
+use super::{ImportedConversation, Importer};
+
+const CONVERSATIONS_FILE: &str = "conversations.json";
+
+/// Reads `conversations.json` from a ChatGPT data export, or the zip file it came in.
+/// Only the branch that was shown last is imported, images are replaced by `[image]`.
+pub struct ChatGptImporter;
+
+#[derive(Debug, Deserialize)]
+struct Conversation {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    create_time: Option<f64>,
+    #[serde(default)]
+    update_time: Option<f64>,
+    mapping: HashMap<String, Node>,
+    #[serde(default)]
+    current_node: Option<String>,
+    #[serde(default, alias = "conversation_id")]
+    id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Node {
+    #[serde(default)]
+    message: Option<ExportedMessage>,
+    #[serde(default)]
+    parent: Option<String>,
+    #[serde(default)]
+    children: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportedMessage {
+    author: Author,
+    #[serde(default)]
+    content: Option<Value>,
+    #[serde(default)]
+    metadata: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    role: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+fn timestamp(seconds: Option<f64>) -> Option<DateTime<Utc>> {
+    let seconds = seconds?;
+    DateTime::from_timestamp(seconds.trunc() as i64, (seconds.fract() * 1e9) as u32)
+}
+
+fn read_conversations(path: &Path) -> Result<String, Box<dyn Error>> {
+    if path.extension().is_none_or(|extension| extension != "zip") {
+        return Ok(fs::read_to_string(path)?);
+    }
+    let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+    let name = archive.file_names()
+        .find(|name| Path::new(name).file_name().is_some_and(|file_name| file_name == CONVERSATIONS_FILE))
+        .map(str::to_string)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("No {CONVERSATIONS_FILE} in {}", path.display())))?;
+    let mut contents = String::new();
+    archive.by_name(&name)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+// the text of the content types that are shown in the conversation
+fn text(content: &Value) -> String {
+    let string = |key: &str| content.get(key).and_then(Value::as_str).unwrap_or_default().to_string();
+    match content.get("content_type").and_then(Value::as_str).unwrap_or("text") {
+        "text" | "multimodal_text" => content.get("parts").and_then(Value::as_array).into_iter().flatten()
+            .filter_map(|part| match part {
+                Value::String(text) => Some(text.clone()),
+                Value::Object(object) if object.get("content_type").and_then(Value::as_str) == Some("image_asset_pointer") => Some("[image]".to_string()),
+                Value::Object(object) => object.get("text").and_then(Value::as_str).map(str::to_string),
+                _ => None,
+            })
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        "code" => {
+            let language = string("language");
+            let language = if language == "unknown" { String::new() } else { language };
+            format!("```{language}\n{}\n```", string("text").trim_end())
+        }
+        "execution_output" | "system_error" | "tether_quote" | "tether_browsing_display" => {
+            let text = string("text");
+            if text.is_empty() { string("result") } else { text }
+        }
+        // custom instructions and other settings are not part of the conversation
+        _ => String::new(),
+    }
+}
+
+fn hidden(message: &ExportedMessage) -> bool {
+    message.metadata.as_ref()
+        .and_then(|metadata| metadata.get("is_visually_hidden_from_conversation"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false)
+}
+
+// without a current node the latest reply to every message is followed
+fn last_leaf(conversation: &Conversation) -> Option<String> {
+    let (mut id, mut node) = conversation.mapping.iter().find(|(_, node)| node.parent.is_none())?;
+    for _ in 0..conversation.mapping.len() {
+        let Some((child_id, child)) = node.children.last().and_then(|child| conversation.mapping.get_key_value(child)) else {
+            break;
+        };
+        (id, node) = (child_id, child);
+    }
+    Some(id.clone())
+}
+
+// the messages from the root to `current_node`, which is the branch that was shown last
+fn current_branch(conversation: &Conversation) -> Messages {
+    let mut branch = vec![];
+    let mut node_id = conversation.current_node.clone().or_else(|| last_leaf(conversation));
+    while let Some(node) = node_id.as_ref().and_then(|id| conversation.mapping.get(id)) {
+        // a broken export could contain a cycle
+        if branch.len() > conversation.mapping.len() {
+            break;
+        }
+        branch.push(node);
+        node_id = node.parent.clone();
+    }
+    let mut messages = Messages::new();
+    for message in branch.into_iter().rev().filter_map(|node| node.message.as_ref()) {
+        let text = message.content.as_ref().map(text).unwrap_or_default();
+        if text.trim().is_empty() || hidden(message) {
+            continue;
+        }
+        match message.author.role.as_str() {
+            "user" => messages.add_message(Role::User, &text),
+            "assistant" => messages.add_message(Role::Assistant, &text),
+            "system" => messages.add_message(Role::System, &text),
+            // there are no tool calls to answer, so tool output becomes part of the reply
+            "tool" => {
+                let name = message.author.name.as_deref().unwrap_or("tool");
+                messages.add_message(Role::Assistant, &format!("Result of {name}:\n{text}"));
+            }
+            role => log::debug!("Skipping message of {role}"),
+        }
+    }
+    messages
+}
+
+impl Importer for ChatGptImporter {
+    fn name(&self) -> &'static str {
+        "chatgpt"
+    }
+
+    fn import(&self, path: &Path) -> Result<Vec<ImportedConversation>, Box<dyn Error>> {
+        let conversations: Vec<Conversation> = serde_json::from_str(&read_conversations(path)?)?;
+        Ok(conversations.into_iter().enumerate().map(|(index, conversation)| {
+            let messages = current_branch(&conversation);
+            ImportedConversation {
+                id: conversation.id.clone().unwrap_or_else(|| index.to_string()),
+                title: conversation.title.clone().unwrap_or_default(),
+                created_at: timestamp(conversation.create_time),
+                updated_at: timestamp(conversation.update_time),
+                messages,
+            }
+        }).collect())
+    }
+}
@@ -1,23 +1,64 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "Vec<Message>")]
 pub struct Messages(pub Vec<Message>);
 
+// histories written before tool calls used the single `function_call` protocol,
+// those calls become tool calls answered by `tool` messages when they are loaded
+impl From<Vec<Message>> for Messages {
+    fn from(messages: Vec<Message>) -> Messages {
+        Messages::from_stored(messages, 0)
+    }
+}
+
+impl Messages {
+    /// Loads the stored messages from the position `offset` on. Legacy function calls are named after
+    /// their position in the store, so they get the same id whichever part of the history is loaded.
+    pub fn from_stored(mut messages: Vec<Message>, offset: usize) -> Messages {
+        // calls without a result yet, as (function name, tool call id)
+        let mut open_calls: Vec<(String, String)> = vec![];
+        for (index, msg) in messages.iter_mut().enumerate() {
+            if let Some(call) = msg.function_call.take() {
+                msg.tool_calls.get_or_insert_with(Vec::new).push(ToolCall::new(&format!("call_{}", offset + index), call));
+            }
+            // a migrated call that was saved again has tool calls, its result may still be a function message
+            for tool_call in msg.tool_calls.iter().flatten() {
+                open_calls.push((tool_call.function.name.clone(), tool_call.id.clone()));
+            }
+            if msg.role == Role::Tool {
+                open_calls.retain(|(_, id)| msg.tool_call_id.as_ref() != Some(id));
+            }
+            if msg.role == Role::Function {
+                msg.role = Role::Tool;
+                if let Some(position) = open_calls.iter().position(|(name, _)| msg.name.as_ref() == Some(name)) {
+                    msg.tool_call_id = Some(open_calls.remove(position).1);
+                    msg.name = None;
+                }
+            }
+        }
+        Messages(messages)
+    }
+}
+
 pub trait ChatHistory {
     fn last(&self) -> Option<&Message>;
     fn push(&mut self, msg: Message);
     fn add_user_message(&mut self, msg: &str);
     fn set_system_message(&mut self, msg: &str);
-    fn add_message(&mut self, role: &str, msg: &str);
+    fn add_message(&mut self, role: Role, msg: &str);
 
-    fn add_powershell_message(&mut self, name: &str, msg: &str);
+    fn add_tool_message(&mut self, tool_call_id: &str, output: &str);
 
     fn from(openai_message: Message) -> Messages {
         Messages(vec![openai_message])
     }
 
+    #[allow(clippy::new_ret_no_self)]
     fn new() -> Messages {
         Messages(vec![])
     }
@@ -29,10 +70,10 @@ pub trait ChatHistory {
 impl Display for Messages {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         for msg in &self.0 {
-            if msg.role == "system" {
+            if msg.role == Role::System {
                 continue;
             }
-            write!(f, "{}\n", *msg)?;
+            writeln!(f, "{}", *msg)?;
         }
         Ok(())
     }
@@ -48,111 +89,463 @@ impl ChatHistory for Messages {
         self.0.push(msg)
     }
     fn add_user_message(&mut self, msg: &str) {
-        self.add_message("user", msg)
+        self.add_message(Role::User, msg)
     }
 
     fn set_system_message(&mut self, msg: &str) {
         self.clear_system_messages();
-        let openai_msg = Message {
-            role: "system".to_string(),
-            content: msg.to_string(),
-            function_call: None,
-            name: None,
-        };
-        self.0.insert(0, openai_msg)
+        self.0.insert(0, Message::new(Role::System, msg))
     }
 
-    fn add_message(&mut self, role: &str, msg: &str) {
-        let openai_msg = Message {
-            role: role.to_string(),
-            content: msg.to_string(),
-            function_call: None,
-            name: None,
-        };
-        self.0.push(openai_msg);
+    fn add_message(&mut self, role: Role, msg: &str) {
+        self.0.push(Message::new(role, msg));
     }
 
-    fn add_powershell_message(&mut self, name: &str, output: &str) {
+    fn add_tool_message(&mut self, tool_call_id: &str, output: &str) {
         let openai_msg = Message {
-            role: "function".to_string(),
-            content: output.to_string(),
-            function_call: None,
-            name: Some(name.into()),
+            tool_call_id: Some(tool_call_id.to_string()),
+            ..Message::new(Role::Tool, output)
         };
         self.0.push(openai_msg);
     }
 
     fn clear_system_messages(&mut self) {
-        self.0.retain(|msg| msg.role != "system");
+        self.0.retain(|msg| msg.role != Role::System);
     }
 
     fn get_system_messages(&self) -> Vec<&Message> {
-        self.0.iter().filter(|msg| msg.role == "system").collect()
+        self.0.iter().filter(|msg| msg.role == Role::System).collect()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    System,
+    // replaces the system role for reasoning models
+    Developer,
+    #[default]
+    User,
+    Assistant,
+    Tool,
+    // legacy function results, only found in old histories and replaced by `Tool` when loaded
+    Function,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::Developer => "developer",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+            Role::Function => "function",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Role> {
+        [Role::System, Role::Developer, Role::User, Role::Assistant, Role::Tool, Role::Function].into_iter()
+            .find(|role| role.as_str() == value)
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
+/// Either plain text, as in all older histories, or a list of parts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Content {
+    Text(String),
+    Parts(Vec<ContentPart>),
+}
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl Content {
+    /// The text of the content, the text parts are joined by newlines
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            Content::Text(text) => Cow::Borrowed(text),
+            Content::Parts(parts) => Cow::Owned(parts.iter()
+                .filter_map(|part| match part {
+                    ContentPart::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join("\n")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ImageUrl },
+    InputAudio { input_audio: InputAudio },
+    // only in assistant messages
+    Refusal { refusal: String },
+}
+
+/// An http(s) url or a `data:` url with the base64 encoded image
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageUrl {
+    pub url: String,
+    // low, high or auto
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputAudio {
+    // base64 encoded audio
+    pub data: String,
+    // wav or mp3
+    pub format: String,
+}
+
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Message {
-    pub content: String,
-    pub role: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    // null for assistant messages that only call tools
+    #[serde(default)]
+    pub content: Option<Content>,
+    pub role: Role,
+    // set instead of the content when the model declines to answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refusal: Option<String>,
+    // legacy single function call, only found in old histories and replaced by `tool_calls` when loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub function_call: Option<FunctionCall>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+    // the call a `tool` message answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    // pinned messages survive context trimming, only stored locally and never sent to the api
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    // the file the content was read from, only stored locally and never sent to the api
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<Attachment>,
+    // the message and the one it follows in its branch, only stored locally and never sent to the api.
+    // Messages stored without an id follow the message stored before them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    // when and how the message was created, only stored locally and never sent to the api
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+}
+
+/// A file attached with `rustgpt file`, read again before every request
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub path: String,
+    // sha256 of the contents that were last sent
+    pub hash: String,
+    // the --max-bytes limit the file was attached with, the default limit when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+}
+
+/// Stored with a message, only the creation time is known for messages that are not replies
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Metadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    // the id the provider gave the reply, to find it in its logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    // milliseconds from sending the request to the first streamed part and to the end of the reply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_to_first_token_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    // how the reply was asked for, so `retry` and `regenerate` can ask again the same way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    // the names of the functions the model could call
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    // the summary of the older messages sent with the request, only stored when it was made or extended
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<Summary>,
+}
+
+/// A summary of the older messages of a branch, later turns reuse it and only summarize what it does not cover
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub text: String,
+    // the id of the last message it covers
+    pub until: String,
+}
+
+impl Metadata {
+    pub fn now() -> Metadata {
+        Metadata { created_at: Some(Utc::now()), ..Default::default() }
+    }
+}
+
+impl Display for Metadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut fields = vec![];
+        if let Some(created_at) = self.created_at {
+            fields.push(created_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string());
+        }
+        match (&self.provider, &self.model) {
+            (Some(provider), Some(model)) => fields.push(format!("{provider}/{model}")),
+            (provider, model) => fields.extend(provider.iter().chain(model).cloned()),
+        }
+        if let Some(finish_reason) = &self.finish_reason {
+            fields.push(format!("finish {finish_reason}"));
+        }
+        if let Some(usage) = &self.usage {
+            fields.push(format!("{} in {} out tokens", usage.prompt_tokens, usage.completion_tokens));
+        }
+        if let Some(ttft) = self.time_to_first_token_ms {
+            fields.push(format!("first token {ttft}ms"));
+        }
+        if let Some(latency) = self.latency_ms {
+            fields.push(format!("total {latency}ms"));
+        }
+        if let Some(request_id) = &self.request_id {
+            fields.push(format!("id {request_id}"));
+        }
+        write!(f, "{}", fields.join(", "))
+    }
+}
+
+impl Message {
+    pub fn new(role: Role, text: &str) -> Message {
+        Message {
+            content: Some(Content::Text(text.to_string())),
+            role,
+            ..Default::default()
+        }
+    }
+
+    /// A reply as decoded from a stream, empty text and tool calls are left out like the api does
+    pub fn assistant(text: String, tool_calls: Vec<ToolCall>) -> Message {
+        Message {
+            content: if text.is_empty() { None } else { Some(Content::Text(text)) },
+            role: Role::Assistant,
+            tool_calls: if tool_calls.is_empty() { None } else { Some(tool_calls) },
+            ..Default::default()
+        }
+    }
+
+    pub fn with_parts(role: Role, parts: Vec<ContentPart>) -> Message {
+        Message {
+            content: Some(Content::Parts(parts)),
+            role,
+            ..Default::default()
+        }
+    }
+
+    pub fn images(&self) -> Vec<&ImageUrl> {
+        match &self.content {
+            Some(Content::Parts(parts)) => parts.iter()
+                .filter_map(|part| match part {
+                    ContentPart::ImageUrl { image_url } => Some(image_url),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    /// The text of the message, empty when there is no content
+    pub fn text(&self) -> Cow<'_, str> {
+        self.content.as_ref().map_or(Cow::Borrowed(""), Content::text)
+    }
+
+    /// Replaces the text of the message, images are kept
+    pub fn set_text(&mut self, text: &str) {
+        match &mut self.content {
+            Some(Content::Parts(parts)) => {
+                parts.retain(|part| !matches!(part, ContentPart::Text { .. }));
+                parts.insert(0, ContentPart::Text { text: text.to_string() });
+            }
+            content => *content = Some(Content::Text(text.to_string())),
+        }
+    }
 }
 
 impl Display for Message {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}:{}", self.role, self.content)?;
-        if let Some(call) = &self.function_call {
-            write!(f, "{}(", call.name)?;
-            write!(f, "{}", call.arguments)?;
+        write!(f, "{}:{}", self.role, self.text())?;
+        for image in self.images() {
+            // data urls are far too long to print
+            let url = if image.url.starts_with("data:") { "data" } else { &image.url };
+            write!(f, "[image {url}]")?;
+        }
+        if let Some(refusal) = &self.refusal {
+            write!(f, "{refusal}")?;
+        }
+        for tool_call in self.tool_calls.iter().flatten() {
+            write!(f, "{}(", tool_call.function.name)?;
+            write!(f, "{}", tool_call.function.arguments)?;
             write!(f, ")")?;
         }
-        write!(f, "\n")?;
+        writeln!(f)?;
         Ok(())
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OpenaiFunction {
     pub name: String,
     pub description: String,
     pub parameters: FunctionParameters,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FunctionParameters {
     pub r#type: String,
     pub properties: HashMap<String, FunctionProperty>,
     pub required: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FunctionProperty {
     pub r#type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub r#enum: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tool {
+    pub r#type: String,
+    pub function: OpenaiFunction,
+}
+
+impl Tool {
+    pub fn function(function: OpenaiFunction) -> Tool {
+        Tool { r#type: "function".to_string(), function }
+    }
+}
+
+/// Whether the model may, must or must not call tools
+#[derive(Debug, Clone, PartialEq)]
+pub enum ToolChoice {
+    None,
+    Auto,
+    Required,
+    Function(String),
+}
+
+impl Serialize for ToolChoice {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            ToolChoice::None => serializer.serialize_str("none"),
+            ToolChoice::Auto => serializer.serialize_str("auto"),
+            ToolChoice::Required => serializer.serialize_str("required"),
+            ToolChoice::Function(name) => serde_json::json!({"type": "function", "function": {"name": name}}).serialize(serializer),
+        }
+    }
+}
+
+/// Asks for a reply in json, optionally following a json schema
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchema },
+}
+
+impl ResponseFormat {
+    /// The schema the reply has to follow, if any
+    pub fn schema(&self) -> Option<&serde_json::Value> {
+        match self {
+            ResponseFormat::JsonSchema { json_schema } => Some(&json_schema.schema),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonSchema {
+    // only letters, digits, underscores and dashes
+    pub name: String,
+    pub schema: serde_json::Value,
+    // with strict the api guarantees the schema is followed, but only supports a subset of json schema
+    #[serde(default)]
+    pub strict: bool,
+}
+
+#[derive(Debug, Serialize)]
 pub struct OpenAiRequest {
     pub model: String,
     pub messages: Messages,
     pub temperature: f32,
     pub stream: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub functions: Option<Vec<OpenaiFunction>>,
+    pub tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamOptions {
+    pub include_usage: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OpenAiResponse {
-    id: String,
+    pub(crate) id: String,
     object: String,
     created: u64,
     model: String,
     pub choices: Vec<Choice>,
+    // only set on the last chunk of a stream when `stream_options.include_usage` is requested
+    #[serde(default)]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub total_tokens: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<PromptTokensDetails>,
+}
+
+impl Usage {
+    pub fn cached_tokens(&self) -> u64 {
+        self.prompt_tokens_details.as_ref().map_or(0, |details| details.cached_tokens)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PromptTokensDetails {
+    #[serde(default)]
+    pub cached_tokens: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -166,18 +559,59 @@ pub struct Choice {
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Delta {
     pub content: Option<String>,
-    pub role: Option<String>,
-    pub function_call: Option<StreamingFunctionCall>,
+    pub role: Option<Role>,
+    #[serde(default)]
+    pub refusal: Option<String>,
+    #[serde(default)]
+    pub tool_calls: Option<Vec<StreamingToolCall>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct FunctionCall {
     pub name: String,
     pub arguments: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub r#type: String,
+    pub function: FunctionCall,
+}
+
+impl ToolCall {
+    pub fn new(id: &str, function: FunctionCall) -> ToolCall {
+        ToolCall { id: id.to_string(), r#type: "function".to_string(), function }
+    }
+}
+
+// the parts of parallel calls are told apart by their index, only the first part has the id and name
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamingToolCall {
+    pub index: usize,
+    pub id: Option<String>,
+    pub function: Option<StreamingFunctionCall>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct StreamingFunctionCall {
     pub name: Option<String>,
     pub arguments: Option<String>,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Model {
+    pub id: String,
+    #[serde(default)]
+    pub object: String,
+    #[serde(default)]
+    pub created: u64,
+    #[serde(default)]
+    pub owned_by: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModelList {
+    pub object: String,
+    pub data: Vec<Model>,
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use chrono::Local;
+
+pub const DEFAULT_PROMPTS_DIR: &str = "rustgpt/prompts";
+pub const CHAT_PROMPT: &str = "chat";
+pub const POWERSHELL_PROMPT: &str = "pwsh";
+const EXTENSION: &str = "md";
+
+// a template file in the prompts directory with the same name replaces these
+const BUILTIN_PROMPTS: &[(&str, &str)] = &[
+    (CHAT_PROMPT, ""),
+    (POWERSHELL_PROMPT, " You are a machine translating human commands to powershell commands.\
+        These powershell commands can be returned as function calls.\
+        You can also ask the user for more information.\
+        If the function could do something dangerous always ask the user if the command should be run."),
+];
+
+fn invalid_input(msg: String) -> Box<dyn Error> {
+    Box::new(io::Error::new(io::ErrorKind::InvalidInput, msg))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prompt {
+    pub name: String,
+    pub template: String,
+    /// the template file, `None` for built in prompts
+    pub path: Option<PathBuf>,
+}
+
+// names become file names, so they may only contain letters, digits, dashes and underscores
+fn check_name(name: &str) -> Result<(), Box<dyn Error>> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(invalid_input(format!("Invalid prompt name '{name}', use letters, digits, - and _")));
+    }
+    Ok(())
+}
+
+pub fn path(dir: &str, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    check_name(name)?;
+    Ok(Path::new(dir).join(format!("{name}.{EXTENSION}")))
+}
+
+fn builtin(name: &str) -> Option<Prompt> {
+    BUILTIN_PROMPTS.iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(name, template)| Prompt { name: name.to_string(), template: template.to_string(), path: None })
+}
+
+/// The built in prompts and the templates in `dir`, sorted by name
+pub fn list(dir: &str) -> Result<Vec<Prompt>, Box<dyn Error>> {
+    let mut prompts = BUILTIN_PROMPTS.iter()
+        .map(|(name, _)| (name.to_string(), builtin(name).expect("listed prompts are built in")))
+        .collect::<HashMap<_, _>>();
+    if Path::new(dir).is_dir() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|extension| extension != EXTENSION) {
+                continue;
+            }
+            let Some(name) = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()) else {
+                continue;
+            };
+            let template = fs::read_to_string(&path)?;
+            prompts.insert(name.clone(), Prompt { name, template, path: Some(path) });
+        }
+    }
+    let mut prompts = prompts.into_values().collect::<Vec<_>>();
+    prompts.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(prompts)
+}
+
+/// The template file in `dir`, or else the built in prompt
+pub fn find(dir: &str, name: &str) -> Result<Prompt, Box<dyn Error>> {
+    let path = path(dir, name)?;
+    if path.exists() {
+        let template = fs::read_to_string(&path)?;
+        return Ok(Prompt { name: name.to_string(), template, path: Some(path) });
+    }
+    builtin(name).ok_or_else(|| {
+        let names = list(dir).map(|prompts| prompts.into_iter().map(|prompt| prompt.name).collect::<Vec<_>>().join(", "));
+        invalid_input(format!("Unknown prompt '{name}', available are {}", names.unwrap_or_default()))
+    })
+}
+
+/// Parses a `-v key=value` variable
+pub fn parse_variable(variable: &str) -> Result<(String, String), Box<dyn Error>> {
+    match variable.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => Ok((key.trim().to_string(), value.to_string())),
+        _ => Err(invalid_input(format!("Invalid variable '{variable}', expected key=value"))),
+    }
+}
+
+fn shell() -> String {
+    let shell = env::var("SHELL").or_else(|_| env::var("ComSpec")).unwrap_or_default();
+    match Path::new(&shell).file_stem() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None if cfg!(windows) => "powershell".to_string(),
+        None => "sh".to_string(),
+    }
+}
+
+fn variable(name: &str, variables: &HashMap<String, String>) -> Result<String, Box<dyn Error>> {
+    if let Some(value) = variables.get(name) {
+        return Ok(value.clone());
+    }
+    if let Some(path) = name.strip_prefix("file:") {
+        return fs::read_to_string(path.trim())
+            .map_err(|e| invalid_input(format!("Can not read {} for {{{{{name}}}}}: {e}", path.trim())));
+    }
+    match name {
+        "cwd" => Ok(env::current_dir()?.to_string_lossy().into_owned()),
+        "os" => Ok(env::consts::OS.to_string()),
+        "shell" => Ok(shell()),
+        "date" => Ok(Local::now().date_naive().to_string()),
+        _ => Err(invalid_input(format!("Unknown template variable {{{{{name}}}}}, pass it with -v {name}=VALUE"))),
+    }
+}
+
+/// Replaces `{{cwd}}`, `{{os}}`, `{{shell}}`, `{{date}}`, `{{file:path}}` and the given variables,
+/// which take precedence over the built in ones
+pub fn render(template: &str, variables: &HashMap<String, String>) -> Result<String, Box<dyn Error>> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        rendered.push_str(&rest[..start]);
+        rendered.push_str(&variable(rest[start + 2..start + end].trim(), variables)?);
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
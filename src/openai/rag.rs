@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::openai::{Message, Role, Usage};
+use crate::openai::embeddings::{self, EmbeddingOptions};
+use crate::openai::files;
+use crate::provider::ChatProvider;
+
+pub const DEFAULT_INDEX_DIR: &str = "rustgpt/index";
+pub const DEFAULT_CHUNK_SIZE: usize = 1500;
+pub const DEFAULT_TOP_K: usize = 5;
+// larger files are most likely generated or data, not documentation
+pub const MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+pub const SYSTEM_PROMPT: &str = "Answer with the help of the numbered sources the user provides. \
+    Cite the sources you use as [n]. Say so when the sources do not contain the answer.";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    Rust,
+    Markdown,
+    Text,
+}
+
+impl Language {
+    pub fn from_path(path: &Path) -> Language {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("rs") => Language::Rust,
+            Some("md" | "markdown") => Language::Markdown,
+            _ => Language::Text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Chunk {
+    /// first and last line of the chunk, starting at 1
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub embedding: Vec<f32>,
+}
+
+fn hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn size(lines: &[&str], range: &Range<usize>) -> usize {
+    lines[range.clone()].iter().map(|line| line.len() + 1).sum()
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+// a rust item starts at the given indentation, its doc comments and attributes are part of it
+fn starts_rust_item(lines: &[&str], index: usize, level: usize) -> bool {
+    let line = lines[index];
+    if line.trim().is_empty() || indent(line) != level || line.trim_start().starts_with(['}', ')', ']']) {
+        return false;
+    }
+    let Some(previous) = index.checked_sub(1).map(|previous| lines[previous]) else {
+        return true;
+    };
+    let previous_trimmed = previous.trim();
+    previous_trimmed.is_empty()
+        || (indent(previous) <= level && (previous_trimmed.ends_with('}') || previous_trimmed.ends_with(';')))
+}
+
+fn fences(lines: &[&str]) -> Vec<bool> {
+    let mut in_fence = false;
+    lines.iter().map(|line| {
+        let is_fence = line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~");
+        if is_fence {
+            in_fence = !in_fence;
+        }
+        in_fence || is_fence
+    }).collect()
+}
+
+fn split_at(range: Range<usize>, is_boundary: &dyn Fn(usize) -> bool) -> Vec<Range<usize>> {
+    let mut pieces = vec![];
+    let mut start = range.start;
+    for index in range.start + 1..range.end {
+        if is_boundary(index) {
+            pieces.push(start..index);
+            start = index;
+        }
+    }
+    pieces.push(start..range.end);
+    pieces
+}
+
+// joins neighbouring pieces as long as they fit
+fn merge(lines: &[&str], pieces: Vec<Range<usize>>, max_chars: usize) -> Vec<Range<usize>> {
+    let mut merged: Vec<Range<usize>> = vec![];
+    for piece in pieces {
+        match merged.last_mut() {
+            Some(last) if size(lines, &(last.start..piece.end)) <= max_chars => last.end = piece.end,
+            _ => merged.push(piece),
+        }
+    }
+    merged
+}
+
+// splits with the coarsest splitter first and only uses the finer ones for pieces that are too large,
+// single lines are the last resort
+fn fit(lines: &[&str], range: Range<usize>, max_chars: usize, splitters: &[&dyn Fn(usize) -> bool]) -> Vec<Range<usize>> {
+    if size(lines, &range) <= max_chars {
+        return vec![range];
+    }
+    let pieces = match splitters.split_first() {
+        Some((splitter, finer)) => split_at(range, *splitter).into_iter()
+            .flat_map(|piece| fit(lines, piece, max_chars, finer))
+            .collect(),
+        None => range.map(|index| index..index + 1).collect(),
+    };
+    merge(lines, pieces, max_chars)
+}
+
+/// Splits a file into chunks of about `max_chars`. Rust files are split between items and then
+/// between the items of impls and modules, markdown files between sections and then paragraphs,
+/// and other files between paragraphs.
+pub fn chunk(language: Language, text: &str, max_chars: usize) -> Vec<Chunk> {
+    let lines = text.lines().collect::<Vec<_>>();
+    let blank = |index: usize| lines[index - 1].trim().is_empty() && !lines[index].trim().is_empty();
+    let ranges = match language {
+        Language::Rust => {
+            let items = |index: usize| starts_rust_item(&lines, index, 0);
+            let nested_items = |index: usize| starts_rust_item(&lines, index, 4);
+            fit(&lines, 0..lines.len(), max_chars, &[&items, &nested_items, &blank])
+        }
+        Language::Markdown => {
+            let in_fence = fences(&lines);
+            let headings = |index: usize| !in_fence[index] && lines[index].starts_with('#')
+                && lines[index].trim_start_matches('#').starts_with(' ');
+            let paragraphs = |index: usize| !in_fence[index] && blank(index);
+            fit(&lines, 0..lines.len(), max_chars, &[&headings, &paragraphs])
+        }
+        Language::Text => fit(&lines, 0..lines.len(), max_chars, &[&blank]),
+    };
+    ranges.into_iter()
+        .filter(|range| lines[range.clone()].iter().any(|line| !line.trim().is_empty()))
+        .map(|range| Chunk {
+            start_line: range.start + 1,
+            end_line: range.end,
+            text: lines[range].join("\n"),
+            embedding: vec![],
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedFile {
+    pub hash: String,
+    pub chunks: Vec<Chunk>,
+}
+
+#[derive(Debug, Default)]
+pub struct UpdateStats {
+    pub added: usize,
+    pub changed: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+    pub chunks: usize,
+    pub usage: Usage,
+}
+
+#[derive(Debug)]
+pub struct Hit<'a> {
+    pub path: &'a str,
+    pub chunk: &'a Chunk,
+    pub score: f32,
+}
+
+impl Hit<'_> {
+    pub fn citation(&self) -> String {
+        format!("{}:{}-{}", self.path, self.chunk.start_line, self.chunk.end_line)
+    }
+}
+
+/// The chunks and embeddings of the text files in a directory, keyed by their path relative to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Index {
+    pub root: PathBuf,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<u32>,
+    pub chunk_size: usize,
+    pub files: BTreeMap<String, IndexedFile>,
+}
+
+/// Every indexed directory gets its own file in `index_dir`, named after the directory and a hash of its path
+pub fn index_path(index_dir: &str, root: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let root = root.canonicalize()?;
+    let name = root.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or("root".into());
+    let path_hash = hash(root.to_string_lossy().as_bytes());
+    Ok(Path::new(index_dir).join(format!("{name}-{}.json", &path_hash[..16])))
+}
+
+// the text files below root that are not ignored by git, with their path relative to root
+fn text_files(root: &Path) -> Vec<(String, String)> {
+    let mut text_files = vec![];
+    for path in files::walk(root) {
+        let text = match files::read_text(&path, MAX_FILE_SIZE) {
+            Ok(Some(text)) => text,
+            Ok(None) => continue,
+            Err(e) => {
+                log::warn!("Skipping {}: {e}", path.display());
+                continue;
+            }
+        };
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        let relative = relative.components().map(|part| part.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        text_files.push((relative, text));
+    }
+    text_files
+}
+
+impl Index {
+    pub fn new(root: &Path, options: &EmbeddingOptions, chunk_size: usize) -> Result<Index, Box<dyn Error>> {
+        Ok(Index {
+            root: root.canonicalize()?,
+            model: options.model.clone(),
+            dimensions: options.dimensions,
+            chunk_size,
+            files: BTreeMap::new(),
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Option<Index>, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(fs::write(path, serde_json::to_string(self)?)?)
+    }
+
+    /// The options to embed questions with, they have to match the ones of the index
+    pub fn embedding_options(&self) -> EmbeddingOptions {
+        EmbeddingOptions {
+            model: self.model.clone(),
+            dimensions: self.dimensions,
+            ..Default::default()
+        }
+    }
+
+    /// Chunks and embeds the files that are new or changed since the last update, and forgets removed files
+    pub async fn update(&mut self, provider: &dyn ChatProvider, options: &EmbeddingOptions) -> Result<UpdateStats, Box<dyn Error>> {
+        let mut stats = UpdateStats::default();
+        let mut files = BTreeMap::new();
+        let mut pending = vec![];
+        for (path, text) in text_files(&self.root) {
+            let file_hash = hash(text.as_bytes());
+            match self.files.remove(&path) {
+                Some(indexed) if indexed.hash == file_hash => {
+                    stats.unchanged += 1;
+                    files.insert(path, indexed);
+                    continue;
+                }
+                Some(_) => stats.changed += 1,
+                None => stats.added += 1,
+            }
+            let chunks = chunk(Language::from_path(Path::new(&path)), &text, self.chunk_size);
+            pending.push(path.clone());
+            files.insert(path, IndexedFile { hash: file_hash, chunks });
+        }
+        stats.removed = self.files.len();
+
+        // the path helps to find chunks that do not mention what they are about
+        let inputs = pending.iter()
+            .flat_map(|path| files[path].chunks.iter().map(move |chunk| format!("{path}\n{}", chunk.text)))
+            .collect::<Vec<_>>();
+        let mut embedded = provider.embed(&inputs, options).await?;
+        stats.usage = embedded.usage;
+        let mut vectors = embedded.vectors.drain(..);
+        for path in &pending {
+            for chunk in &mut files.get_mut(path).expect("pending files are indexed").chunks {
+                chunk.embedding = vectors.next().unwrap_or_default();
+            }
+        }
+        stats.chunks = inputs.len();
+        self.files = files;
+        Ok(stats)
+    }
+
+    /// The `k` chunks most similar to the embedded question, most similar first
+    pub fn search(&self, query: &[f32], k: usize) -> Vec<Hit<'_>> {
+        let chunks = self.files.iter()
+            .flat_map(|(path, file)| file.chunks.iter().map(move |chunk| (path.as_str(), chunk)))
+            .collect::<Vec<_>>();
+        embeddings::top_k(query, chunks.iter().map(|(_, chunk)| chunk.embedding.as_slice()), k).into_iter()
+            .map(|(index, score)| Hit { path: chunks[index].0, chunk: chunks[index].1, score })
+            .collect()
+    }
+}
+
+// earlier versions stored the sources as a user message of their own before the question
+const SOURCES_MESSAGE: &str = "Sources for my next question:";
+
+/// The question with the retrieved chunks as numbered sources before it, sent and stored as one message
+/// so the sources belong to the exchange
+pub fn format_context(hits: &[Hit], question: &str) -> String {
+    let mut context = "Sources:".to_string();
+    for (number, hit) in hits.iter().enumerate() {
+        context.push_str(&format!("\n\n[{}] {}\n{}", number + 1, hit.citation(), hit.chunk.text));
+    }
+    context.push_str(&format!("\n\nQuestion: {question}"));
+    context
+}
+
+/// Whether the message holds only the sources of the question after it, as stored by earlier versions
+pub fn is_sources_message(msg: &Message) -> bool {
+    msg.role == Role::User && msg.text().starts_with(SOURCES_MESSAGE)
+}
@@ -0,0 +1,163 @@
+use serde_json::Value;
+
+/// Checks `instance` against a json schema and returns every violation as `path: problem`.
+/// Supports the keywords allowed in structured outputs: types, enum, const, properties,
+/// required, additionalProperties, items, anyOf, allOf, local `$ref`s and the size limits.
+/// `pattern` and `format` are not checked.
+pub fn validate(schema: &Value, instance: &Value) -> Vec<String> {
+    let mut errors = vec![];
+    validate_at(schema, schema, instance, "", &mut errors);
+    errors
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_i64() || number.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn has_type(value: &Value, expected: &str) -> bool {
+    let actual = type_name(value);
+    actual == expected || (expected == "number" && actual == "integer")
+        || (expected == "integer" && value.as_f64().is_some_and(|number| number.fract() == 0.0))
+}
+
+// resolves `#`, `#/$defs/name` and other json pointers into the root schema
+fn resolve<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    let pointer = reference.strip_prefix('#')?;
+    if pointer.is_empty() {
+        return Some(root);
+    }
+    root.pointer(pointer)
+}
+
+fn display_path(path: &str) -> &str {
+    if path.is_empty() { "/" } else { path }
+}
+
+fn validate_at(root: &Value, schema: &Value, instance: &Value, path: &str, errors: &mut Vec<String>) {
+    let Some(schema) = schema.as_object() else {
+        // `true` accepts everything, `false` nothing
+        if schema == &Value::Bool(false) {
+            errors.push(format!("{}: no value is allowed here", display_path(path)));
+        }
+        return;
+    };
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        match resolve(root, reference) {
+            Some(target) => validate_at(root, target, instance, path, errors),
+            None => errors.push(format!("{}: unknown reference {reference}", display_path(path))),
+        }
+    }
+    if let Some(expected) = schema.get("type") {
+        let types = match expected {
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => expected.as_str().into_iter().collect::<Vec<_>>(),
+        };
+        if !types.is_empty() && !types.iter().any(|expected| has_type(instance, expected)) {
+            errors.push(format!("{}: expected {}, got {}", display_path(path), types.join(" or "), type_name(instance)));
+            return;
+        }
+    }
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(instance) {
+            errors.push(format!("{}: {instance} is not one of {}", display_path(path), Value::Array(allowed.clone())));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if expected != instance {
+            errors.push(format!("{}: expected {expected}, got {instance}", display_path(path)));
+        }
+    }
+    if let Some(variants) = schema.get("anyOf").and_then(Value::as_array) {
+        let matches = variants.iter().any(|variant| {
+            let mut variant_errors = vec![];
+            validate_at(root, variant, instance, path, &mut variant_errors);
+            variant_errors.is_empty()
+        });
+        if !matches {
+            errors.push(format!("{}: does not match any of the allowed schemas", display_path(path)));
+        }
+    }
+    for variant in schema.get("allOf").and_then(Value::as_array).into_iter().flatten() {
+        validate_at(root, variant, instance, path, errors);
+    }
+    match instance {
+        Value::Object(object) => {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for name in schema.get("required").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+                if !object.contains_key(name) {
+                    errors.push(format!("{}: missing required property '{name}'", display_path(path)));
+                }
+            }
+            for (name, value) in object {
+                let property_path = format!("{path}/{name}");
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(property) => validate_at(root, property, value, &property_path, errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => errors.push(format!("{}: property '{name}' is not allowed", display_path(path))),
+                        Some(additional @ Value::Object(_)) => validate_at(root, additional, value, &property_path, errors),
+                        _ => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    validate_at(root, item_schema, item, &format!("{path}/{index}"), errors);
+                }
+            }
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+                if (items.len() as u64) < min {
+                    errors.push(format!("{}: expected at least {min} items, got {}", display_path(path), items.len()));
+                }
+            }
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+                if items.len() as u64 > max {
+                    errors.push(format!("{}: expected at most {max} items, got {}", display_path(path), items.len()));
+                }
+            }
+        }
+        Value::String(text) => {
+            let length = text.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+                if length < min {
+                    errors.push(format!("{}: expected at least {min} characters, got {length}", display_path(path)));
+                }
+            }
+            if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+                if length > max {
+                    errors.push(format!("{}: expected at most {max} characters, got {length}", display_path(path)));
+                }
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            let limit = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+            if limit("minimum").is_some_and(|min| number < min) || limit("exclusiveMinimum").is_some_and(|min| number <= min) {
+                errors.push(format!("{}: {number} is below the minimum", display_path(path)));
+            }
+            if limit("maximum").is_some_and(|max| number > max) || limit("exclusiveMaximum").is_some_and(|max| number >= max) {
+                errors.push(format!("{}: {number} is above the maximum", display_path(path)));
+            }
+        }
+        Value::Null | Value::Bool(_) => {}
+    }
+}
+
+/// Finds the json in a reply, models without a json mode like to wrap it in a markdown fence
+pub fn extract_json(text: &str) -> &str {
+    let text = text.trim();
+    let Some(fenced) = text.strip_prefix("```") else {
+        return text;
+    };
+    let body = fenced.split_once('\n').map_or("", |(_, body)| body);
+    body.trim_end().strip_suffix("```").unwrap_or(body).trim()
+}
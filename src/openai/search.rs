@@ -0,0 +1,251 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::openai::{store, Message, Messages, Role};
+
+pub const DEFAULT_SEARCH_INDEX_FILE: &str = "rustgpt/search-index.json";
+const SNIPPET_BEFORE: usize = 40;
+const SNIPPET_AFTER: usize = 100;
+
+/// Lowercase words of letters, digits and underscores
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// The searchable text of a message, its content and the arguments of its tool calls
+pub fn message_text(msg: &Message) -> String {
+    let mut text = msg.text().into_owned();
+    if let Some(refusal) = &msg.refusal {
+        text.push_str(refusal);
+    }
+    for tool_call in msg.tool_calls.iter().flatten() {
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(&format!("{}({})", tool_call.function.name, tool_call.function.arguments));
+    }
+    text
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    /// index of the message in the history
+    pub message: usize,
+    pub role: Role,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedSession {
+    // the history file is indexed again when its size or modification time changes
+    modified: u128,
+    size: u64,
+    pub timestamp: DateTime<Utc>,
+    pub documents: Vec<Document>,
+    // every word and the documents containing it
+    terms: BTreeMap<String, BTreeSet<usize>>,
+}
+
+impl IndexedSession {
+    fn new(messages: &Messages, modified: u128, size: u64, timestamp: DateTime<Utc>) -> IndexedSession {
+        let mut session = IndexedSession { modified, size, timestamp, documents: vec![], terms: BTreeMap::new() };
+        for (index, msg) in messages.0.iter().enumerate() {
+            let text = message_text(msg);
+            if text.trim().is_empty() {
+                continue;
+            }
+            let document = session.documents.len();
+            for term in tokenize(&text) {
+                session.terms.entry(term).or_default().insert(document);
+            }
+            session.documents.push(Document { message: index, role: msg.role, text });
+        }
+        session
+    }
+
+    // the documents with a word starting with `prefix`
+    fn matching(&self, prefix: &str) -> BTreeSet<usize> {
+        self.terms.range(prefix.to_string()..)
+            .take_while(|(term, _)| term.starts_with(prefix))
+            .flat_map(|(_, documents)| documents.iter().copied())
+            .collect()
+    }
+}
+
+/// A history file to search, with the time shown for its messages
+#[derive(Debug, Clone)]
+pub struct SessionFile {
+    pub name: String,
+    pub path: PathBuf,
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Filter<'a> {
+    pub role: Option<Role>,
+    pub since: Option<DateTime<Utc>>,
+    pub session: Option<&'a str>,
+}
+
+#[derive(Debug)]
+pub struct SearchResult<'a> {
+    pub session: &'a str,
+    pub timestamp: DateTime<Utc>,
+    pub document: &'a Document,
+}
+
+/// An inverted index of the messages of all sessions, kept up to date with the history files
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SearchIndex {
+    sessions: BTreeMap<String, IndexedSession>,
+}
+
+fn modified(path: &Path) -> Result<(u128, u64), Box<dyn Error>> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map(|age| age.as_nanos()).unwrap_or_default();
+    Ok((modified, metadata.len()))
+}
+
+impl SearchIndex {
+    pub fn load(path: &str) -> Result<SearchIndex, Box<dyn Error>> {
+        if !Path::new(path).exists() {
+            return Ok(SearchIndex::default());
+        }
+        match serde_json::from_str(&fs::read_to_string(path)?) {
+            Ok(index) => Ok(index),
+            Err(e) => {
+                log::warn!("Rebuilding unreadable search index {path}: {e}");
+                Ok(SearchIndex::default())
+            }
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(fs::write(path, serde_json::to_string(self)?)?)
+    }
+
+    /// Indexes the sessions that are new or changed and forgets the removed ones, returns whether anything changed
+    pub fn update(&mut self, sessions: &[SessionFile]) -> Result<bool, Box<dyn Error>> {
+        let before = self.sessions.len();
+        self.sessions.retain(|name, _| sessions.iter().any(|session| session.name == *name));
+        let mut changed = self.sessions.len() != before;
+        for session in sessions {
+            let (modified, size) = modified(&session.path)?;
+            let timestamp = session.timestamp.unwrap_or_else(|| {
+                DateTime::from_timestamp_nanos(modified.min(i64::MAX as u128) as i64)
+            });
+            if self.sessions.get(&session.name).is_some_and(|indexed| indexed.modified == modified && indexed.size == size && indexed.timestamp == timestamp) {
+                continue;
+            }
+            let messages = match store::open(&session.path).load() {
+                Ok(messages) => messages,
+                Err(e) => {
+                    log::debug!("Skipping {}, it is not a history: {e}", session.path.display());
+                    continue;
+                }
+            };
+            log::debug!("Indexing session {}", session.name);
+            self.sessions.insert(session.name.clone(), IndexedSession::new(&messages, modified, size, timestamp));
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    /// The messages containing every word of the query, the last word may also be the start of a word.
+    /// The most recent sessions come first.
+    pub fn search(&self, query: &str, filter: &Filter) -> Vec<SearchResult<'_>> {
+        let terms = tokenize(query);
+        if terms.is_empty() {
+            return vec![];
+        }
+        let mut results = vec![];
+        for (name, session) in &self.sessions {
+            if filter.session.is_some_and(|session| session != name) || filter.since.is_some_and(|since| session.timestamp < since) {
+                continue;
+            }
+            let mut documents: Option<BTreeSet<usize>> = None;
+            for (index, term) in terms.iter().enumerate() {
+                let matching = if index + 1 == terms.len() {
+                    session.matching(term)
+                } else {
+                    session.terms.get(term).cloned().unwrap_or_default()
+                };
+                documents = Some(match documents {
+                    Some(documents) => documents.intersection(&matching).copied().collect(),
+                    None => matching,
+                });
+            }
+            for document in documents.into_iter().flatten().map(|index| &session.documents[index]) {
+                if filter.role.is_some_and(|role| role != document.role) {
+                    continue;
+                }
+                results.push(SearchResult { session: name, timestamp: session.timestamp, document });
+            }
+        }
+        results.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then(a.session.cmp(b.session)).then(a.document.message.cmp(&b.document.message)));
+        results
+    }
+}
+
+// the ranges of the words in `text` that start with one of the terms
+fn highlights(text: &str, terms: &[String]) -> Vec<(usize, usize)> {
+    let mut ranges = vec![];
+    let mut start = None;
+    for (index, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        let is_word = c.is_alphanumeric() || c == '_';
+        match (start, is_word) {
+            (None, true) => start = Some(index),
+            (Some(word_start), false) => {
+                let word = text[word_start..index].to_lowercase();
+                if let Some(term) = terms.iter().find(|term| word.starts_with(term.as_str())) {
+                    // only the matching part is highlighted
+                    let end = text[word_start..index].char_indices().nth(term.chars().count()).map_or(index, |(offset, _)| word_start + offset);
+                    ranges.push((word_start, end));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+/// A single line around the first match with every match between `open` and `close`
+pub fn snippet(text: &str, query: &str, open: &str, close: &str) -> String {
+    let terms = tokenize(query);
+    let ranges = highlights(text, &terms);
+    let first = ranges.first().map_or(0, |(start, _)| *start);
+    let mut start = text[..first].char_indices().rev().nth(SNIPPET_BEFORE - 1).map_or(0, |(index, _)| index);
+    if first - start >= SNIPPET_BEFORE {
+        // start at a word boundary
+        start = text[start..first].find(char::is_whitespace).map_or(start, |offset| start + offset + 1);
+    }
+    let end = text[first..].char_indices().nth(SNIPPET_AFTER).map_or(text.len(), |(index, _)| first + index);
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut position = start;
+    for (range_start, range_end) in ranges.into_iter().filter(|(range_start, range_end)| *range_start >= start && *range_end <= end) {
+        snippet.push_str(&text[position..range_start]);
+        snippet.push_str(open);
+        snippet.push_str(&text[range_start..range_end]);
+        snippet.push_str(close);
+        position = range_end;
+    }
+    snippet.push_str(&text[position..end]);
+    if end < text.len() {
+        snippet.push('…');
+    }
+    snippet.split_whitespace().collect::<Vec<_>>().join(" ")
+}
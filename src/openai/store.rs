@@ -0,0 +1,257 @@
+mod journal;
+mod json;
+mod sqlite;
+
+use std::collections::hash_map::DefaultHasher;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::hash::Hasher;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use crate::openai::{tree, Message, Messages, Metadata, Role};
+
+pub use journal::JournalStore;
+pub use json::JsonStore;
+pub use sqlite::SqliteStore;
+
+/// Where the messages of a session are kept, the backend is chosen by the extension of the history file
+pub trait HistoryStore {
+    fn path(&self) -> &Path;
+    /// All messages, an empty history when nothing was stored yet
+    fn load(&self) -> Result<Messages, Box<dyn Error>>;
+    /// The last `count` messages and the index of the first of them
+    fn load_tail(&self, count: usize) -> Result<(usize, Messages), Box<dyn Error>> {
+        let mut messages = self.load()?;
+        let offset = messages.0.len().saturating_sub(count);
+        messages.0.drain(..offset);
+        Ok((offset, messages))
+    }
+    /// The messages from `index` on
+    fn load_from(&self, index: usize) -> Result<Messages, Box<dyn Error>> {
+        let mut messages = self.load()?;
+        messages.0.drain(..index.min(messages.0.len()));
+        Ok(messages)
+    }
+    /// Adds messages to the end of the history
+    fn append(&self, messages: &[Message]) -> Result<(), Box<dyn Error>>;
+    /// Replaces the message at `index`, like an attachment that was read again
+    fn update(&self, index: usize, message: &Message) -> Result<(), Box<dyn Error>>;
+    /// Replaces all messages
+    fn replace(&self, messages: &Messages) -> Result<(), Box<dyn Error>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    // a single json array, the format of older versions
+    Json,
+    // a line per change, appending never rewrites earlier messages
+    Journal,
+    Sqlite,
+}
+
+pub const BACKENDS: [Backend; 3] = [Backend::Json, Backend::Journal, Backend::Sqlite];
+
+impl Backend {
+    pub fn parse(value: &str) -> Option<Backend> {
+        match value {
+            "json" => Some(Backend::Json),
+            "jsonl" | "journal" => Some(Backend::Journal),
+            "sqlite" => Some(Backend::Sqlite),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Backend::Json => "json",
+            Backend::Journal => "jsonl",
+            Backend::Sqlite => "sqlite",
+        }
+    }
+
+    // unknown extensions are read as json, like every history file before there were other backends
+    pub fn from_path(path: &Path) -> Backend {
+        let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase()).unwrap_or_default();
+        match extension.as_str() {
+            "jsonl" => Backend::Journal,
+            "sqlite" | "db" => Backend::Sqlite,
+            _ => Backend::Json,
+        }
+    }
+}
+
+pub fn open(path: &Path) -> Box<dyn HistoryStore> {
+    let path = path.to_path_buf();
+    match Backend::from_path(&path) {
+        Backend::Json => Box::new(JsonStore::new(path)),
+        Backend::Journal => Box::new(JournalStore::new(path)),
+        Backend::Sqlite => Box::new(SqliteStore::new(path)),
+    }
+}
+
+fn create_parent(path: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+// the contents are written next to the file and moved over it, so a crash never leaves half a history
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), Box<dyn Error>> {
+    create_parent(path)?;
+    let temp = with_suffix(path, &format!(".{}.tmp", std::process::id()));
+    let result = File::create(&temp)
+        .and_then(|mut file| file.write_all(contents).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    Ok(result?)
+}
+
+/// An advisory lock on `<history>.lock`, released when dropped. Only other rustgpt runs respect it,
+/// and a run must not lock the same history twice.
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    pub fn exclusive(history: &Path) -> Result<Lock, Box<dyn Error>> {
+        create_parent(history)?;
+        let path = with_suffix(history, ".lock");
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(&path)?;
+        if file.try_lock().is_err() {
+            eprintln!("Waiting for another rustgpt to finish writing {}", history.display());
+            file.lock()?;
+        }
+        Ok(Lock { _file: file })
+    }
+}
+
+/// Copies the history to a store of another backend next to it and keeps the old file as `<file>.bak`.
+/// Returns the path of the new history file.
+pub fn migrate(path: &Path, backend: Backend) -> Result<PathBuf, Box<dyn Error>> {
+    let target = path.with_extension(backend.extension());
+    if target == path {
+        return Ok(target);
+    }
+    if target.exists() {
+        return Err(Box::new(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", target.display()))));
+    }
+    let _lock = Lock::exclusive(path)?;
+    let messages = open(path).load()?;
+    open(&target).replace(&messages)?;
+    fs::rename(path, with_suffix(path, ".bak"))?;
+    // the offset index of a journal is of no use without it
+    let _ = fs::remove_file(with_suffix(path, ".idx"));
+    Ok(target)
+}
+
+// a legacy function result is only linked to its call when the call was loaded with it,
+// so the links are left out and the same message loaded with another part of the history is not a change
+fn fingerprint(msg: &Message) -> u64 {
+    let mut msg = msg.clone();
+    if msg.role == Role::Tool {
+        msg.tool_call_id = None;
+        msg.name = None;
+    }
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&serde_json::to_vec(&msg).unwrap_or_default());
+    hasher.finish()
+}
+
+/// Loads the branch ending at `leaf`, or at the last stored message, from the last `limit` stored
+/// messages or all of them
+pub fn load_branch(store: &dyn HistoryStore, limit: Option<usize>, leaf: Option<&str>) -> Result<(Messages, Snapshot), Box<dyn Error>> {
+    let (offset, mut messages) = match limit {
+        Some(limit) => store.load_tail(limit)?,
+        None => (0, store.load()?),
+    };
+    tree::link(&mut messages, offset);
+    let end = offset + messages.0.len();
+    let last = messages.0.last().map(fingerprint);
+    let path = tree::branch(&messages.0, leaf);
+    let mut stored = messages.0.into_iter().map(Some).collect::<Vec<_>>();
+    let branch = path.iter().filter_map(|position| stored[*position].take()).collect::<Vec<_>>();
+    let snapshot = Snapshot {
+        indices: path.iter().map(|position| offset + position).collect(),
+        fingerprints: branch.iter().map(fingerprint).collect(),
+        end,
+        last,
+    };
+    Ok((Messages(branch), snapshot))
+}
+
+/// Remembers what a command loaded, so only the messages it changed or added are written back
+/// instead of the whole history. Other runs may write the history in the meantime, the messages
+/// they added are kept and the new messages are added after them.
+pub struct Snapshot {
+    // the position in the store of every loaded message
+    indices: Vec<usize>,
+    fingerprints: Vec<u64>,
+    // the number of stored messages and the last of them, to notice that the history was rewritten
+    end: usize,
+    last: Option<u64>,
+}
+
+impl Snapshot {
+    /// Forgets the loaded messages from `position` on, so the messages saved in their place start a new branch
+    pub fn fork(&mut self, position: usize) {
+        self.indices.truncate(position);
+        self.fingerprints.truncate(position);
+    }
+
+    /// Writes the changes under the history lock, so it must not be held by the caller.
+    /// Returns the id of the last new message.
+    pub fn save(&self, store: &dyn HistoryStore, messages: &Messages) -> Result<Option<String>, Box<dyn Error>> {
+        let len = self.indices.len();
+        if messages.0.len() < len {
+            return Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, "Loaded messages were removed, the history has to be replaced")));
+        }
+        let _lock = Lock::exclusive(store.path())?;
+        let start = self.end.saturating_sub(1);
+        let mut stored = store.load_from(start)?;
+        tree::link(&mut stored, start);
+        let (rewritten, added) = match self.last {
+            Some(last) => (stored.0.first().map(fingerprint) != Some(last), stored.0.len().saturating_sub(1)),
+            None => (false, stored.0.len()),
+        };
+        if rewritten {
+            // the loaded messages may have moved, so changes to them are not written
+            eprintln!("{} was changed by another rustgpt, only the new messages are added", store.path().display());
+        } else {
+            for ((msg, fingerprint_before), index) in messages.0.iter().zip(&self.fingerprints).zip(&self.indices) {
+                if fingerprint(msg) != *fingerprint_before {
+                    store.update(*index, msg)?;
+                }
+            }
+        }
+        let mut new = messages.0[len..].to_vec();
+        if new.is_empty() {
+            return Ok(None);
+        }
+        // a branch that ended with the last stored message continues after the messages added since
+        let continues_last = self.indices.last().map(|index| index + 1) == Some(self.end) || self.end == 0;
+        let mut parent = if rewritten || (added > 0 && continues_last) {
+            if !rewritten {
+                eprintln!("Another rustgpt added {added} messages, the new messages are added after them");
+            }
+            stored.0.last().and_then(|msg| msg.id.clone())
+        } else {
+            len.checked_sub(1).and_then(|last| messages.0[last].id.clone())
+        };
+        for msg in &mut new {
+            msg.metadata.get_or_insert_with(Metadata::now);
+            let id = msg.id.get_or_insert_with(tree::new_id).clone();
+            msg.parent = parent.replace(id);
+        }
+        store.append(&new)?;
+        Ok(parent)
+    }
+}
@@ -0,0 +1,249 @@
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::openai::{Message, Messages};
+use super::{create_parent, with_suffix, write_atomic, HistoryStore};
+
+// a line of the journal, replaying the lines in order gives the history
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    Append(Message),
+    Update { index: usize, message: Message },
+}
+
+struct OffsetIndex {
+    file: fs::File,
+    // the length of the journal the offsets are known of
+    covered: u64,
+    // the number of messages with a known offset
+    indexed: usize,
+}
+
+/// The history as json lines, every change is a line added to the end of the file.
+/// Replacing the history writes a new file with only the current messages, which also compacts the journal.
+pub struct JournalStore {
+    path: PathBuf,
+}
+
+impl JournalStore {
+    pub fn new(path: PathBuf) -> JournalStore {
+        JournalStore { path }
+    }
+
+    fn lines(records: &[Record]) -> Result<String, Box<dyn Error>> {
+        let mut lines = String::new();
+        for record in records {
+            lines.push_str(&serde_json::to_string(record)?);
+            lines.push('\n');
+        }
+        Ok(lines)
+    }
+
+    // `<journal>.idx` holds the length of the journal it covers, then the byte offset of every append
+    // record, all as little endian u64. It is only written with the journal, under the history lock.
+    fn index_path(&self) -> PathBuf {
+        with_suffix(&self.path, ".idx")
+    }
+
+    // the byte offsets of the append records from the byte `start` on, and the end of the last complete line
+    fn scan(&self, start: u64) -> Result<(Vec<u64>, u64), Box<dyn Error>> {
+        if !self.path.exists() {
+            return Ok((vec![], 0));
+        }
+        let mut file = fs::File::open(&self.path)?;
+        file.seek(SeekFrom::Start(start))?;
+        let mut reader = BufReader::new(file);
+        let mut offsets = vec![];
+        let mut offset = start;
+        let mut line = vec![];
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            // a line cut off by a crash is left to the replay to warn about
+            if read == 0 || !line.ends_with(b"\n") {
+                return Ok((offsets, offset));
+            }
+            // records are written by serde, so the kind is the first key of the line
+            if line.starts_with(b"{\"append\":") {
+                offsets.push(offset);
+            }
+            offset += read as u64;
+        }
+    }
+
+    fn read_offset(index: &mut fs::File, position: usize) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        index.seek(SeekFrom::Start(8 + 8 * position as u64))?;
+        index.read_exact(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    // None when there is no index or it does not fit the journal, after which the journal is scanned
+    fn read_index(&self) -> Result<Option<OffsetIndex>, Box<dyn Error>> {
+        let Ok(mut index) = fs::File::open(self.index_path()) else {
+            return Ok(None);
+        };
+        let len = index.metadata()?.len();
+        let mut header = [0; 8];
+        if len % 8 != 0 || index.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+        let covered = u64::from_le_bytes(header);
+        let journal_len = fs::metadata(&self.path).map_or(0, |metadata| metadata.len());
+        if covered > journal_len {
+            return Ok(None);
+        }
+        // offsets past the covered length belong to a write that is not done yet
+        let mut indexed = (len / 8 - 1) as usize;
+        while indexed > 0 && Self::read_offset(&mut index, indexed - 1)? >= covered {
+            indexed -= 1;
+        }
+        Ok(Some(OffsetIndex { file: index, covered, indexed }))
+    }
+
+    // the number of messages and the byte offset of the message at `position(count)`,
+    // only the records written since the index was last updated are scanned
+    fn locate(&self, position: impl FnOnce(usize) -> usize) -> Result<(usize, Option<u64>), Box<dyn Error>> {
+        let mut index = self.read_index()?;
+        let (covered, indexed) = index.as_ref().map_or((0, 0), |index| (index.covered, index.indexed));
+        let (scanned, _) = self.scan(covered)?;
+        let count = indexed + scanned.len();
+        let position = position(count);
+        let offset = match &mut index {
+            Some(index) if position < indexed => Some(Self::read_offset(&mut index.file, position)?),
+            _ => scanned.get(position - indexed).copied(),
+        };
+        Ok((count, offset))
+    }
+
+    // brings the index up to the end of the journal, after every write
+    fn update_index(&self) -> Result<(), Box<dyn Error>> {
+        let (covered, indexed) = self.read_index()?.map_or((0, 0), |index| (index.covered, index.indexed));
+        let (offsets, end) = self.scan(covered)?;
+        let mut bytes = vec![];
+        if covered == 0 {
+            bytes.extend(end.to_le_bytes());
+            bytes.extend(offsets.iter().flat_map(|offset| offset.to_le_bytes()));
+            return write_atomic(&self.index_path(), &bytes);
+        }
+        let mut index = OpenOptions::new().write(true).open(self.index_path())?;
+        // the offsets first, so readers never see a covered length without them
+        index.set_len(8 + 8 * indexed as u64)?;
+        index.seek(SeekFrom::End(0))?;
+        index.write_all(&offsets.iter().flat_map(|offset| offset.to_le_bytes()).collect::<Vec<_>>())?;
+        index.seek(SeekFrom::Start(0))?;
+        Ok(index.write_all(&end.to_le_bytes())?)
+    }
+
+    // the lines are only counted from where the replay started
+    fn line(offset: u64, number: usize) -> String {
+        if offset == 0 { (number + 1).to_string() } else { format!("{} after byte {offset}", number + 1) }
+    }
+
+    // replays the records from the byte `offset` on, where the message at `first` was appended.
+    // Records before it can only update earlier messages, as later ones did not exist yet.
+    fn replay(&self, offset: u64, first: usize) -> Result<Messages, Box<dyn Error>> {
+        if !self.path.exists() {
+            return Ok(Messages(vec![]));
+        }
+        let mut file = fs::File::open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut messages = vec![];
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record = match serde_json::from_str(&line) {
+                Ok(record) => record,
+                // a line cut off by a crash is the last one, the changes before it are kept
+                Err(e) if e.is_eof() => {
+                    log::warn!("Ignoring incomplete line {} of {}", Self::line(offset, number), self.path.display());
+                    break;
+                }
+                Err(e) => return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {e}", self.path.display(), Self::line(offset, number))))),
+            };
+            match record {
+                Record::Append(msg) => messages.push(msg),
+                Record::Update { index, message } => match index.checked_sub(first).and_then(|index| messages.get_mut(index)) {
+                    Some(msg) => *msg = message,
+                    None => log::warn!("Ignoring update of missing message {index} in {}", self.path.display()),
+                },
+            }
+        }
+        // older histories could be migrated with legacy function calls
+        Ok(Messages::from_stored(messages, first))
+    }
+
+    // a single write, so a crash can only cut off the last line
+    fn write_records(&self, records: &[Record]) -> Result<(), Box<dyn Error>> {
+        create_parent(&self.path)?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(Self::lines(records)?.as_bytes())?;
+        self.index_written()
+    }
+
+    // the journal is complete without the index, a broken index is only slower to read
+    fn index_written(&self) -> Result<(), Box<dyn Error>> {
+        if let Err(e) = self.update_index() {
+            log::warn!("Could not update {}: {e}", self.index_path().display());
+            let _ = fs::remove_file(self.index_path());
+        }
+        Ok(())
+    }
+}
+
+impl HistoryStore for JournalStore {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn load(&self) -> Result<Messages, Box<dyn Error>> {
+        self.replay(0, 0)
+    }
+
+    // only the records of the tail are decoded, the lines before it are just counted
+    fn load_tail(&self, count: usize) -> Result<(usize, Messages), Box<dyn Error>> {
+        let mut start = 0;
+        let (_, offset) = self.locate(|len| {
+            start = len.saturating_sub(count);
+            start
+        })?;
+        let messages = match offset {
+            Some(offset) => self.replay(offset, start)?,
+            None => Messages(vec![]),
+        };
+        Ok((start, messages))
+    }
+
+    fn load_from(&self, index: usize) -> Result<Messages, Box<dyn Error>> {
+        match self.locate(|_| index)? {
+            (_, Some(offset)) => self.replay(offset, index),
+            (_, None) => Ok(Messages(vec![])),
+        }
+    }
+
+    fn append(&self, messages: &[Message]) -> Result<(), Box<dyn Error>> {
+        let records = messages.iter().cloned().map(Record::Append).collect::<Vec<_>>();
+        self.write_records(&records)
+    }
+
+    fn update(&self, index: usize, message: &Message) -> Result<(), Box<dyn Error>> {
+        self.write_records(&[Record::Update { index, message: message.clone() }])
+    }
+
+    fn replace(&self, messages: &Messages) -> Result<(), Box<dyn Error>> {
+        let records = messages.0.iter().cloned().map(Record::Append).collect::<Vec<_>>();
+        // the offsets of the old journal must not be used for the new one, not even after a crash
+        if let Err(e) = fs::remove_file(self.index_path()) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(Box::new(e));
+            }
+        }
+        write_atomic(&self.path, Self::lines(&records)?.as_bytes())?;
+        self.index_written()
+    }
+}
@@ -0,0 +1,70 @@
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crate::openai::{Message, Messages};
+use super::{write_atomic, HistoryStore};
+
+/// The history as one json array, every change writes the whole file. Use the journal or sqlite
+/// for long histories.
+pub struct JsonStore {
+    path: PathBuf,
+}
+
+impl JsonStore {
+    pub fn new(path: PathBuf) -> JsonStore {
+        JsonStore { path }
+    }
+}
+
+impl HistoryStore for JsonStore {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn load(&self) -> Result<Messages, Box<dyn Error>> {
+        if !self.path.exists() {
+            return Ok(Messages(vec![]));
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(&self.path)?)?)
+    }
+
+    fn append(&self, messages: &[Message]) -> Result<(), Box<dyn Error>> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        if !self.path.exists() {
+            return self.replace(&Messages(messages.to_vec()));
+        }
+        // the messages are added before the closing bracket without parsing the history
+        let mut contents = fs::read(&self.path)?;
+        let len = contents.trim_ascii_end().len();
+        if len == 0 || contents[len - 1] != b']' {
+            return Err(Box::new(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a json array", self.path.display()))));
+        }
+        // the messages are objects, so only an empty array has the opening bracket before the closing one
+        let empty = contents[..len - 1].trim_ascii_end().last() == Some(&b'[');
+        contents.truncate(len - 1);
+        for (index, msg) in messages.iter().enumerate() {
+            if !empty || index > 0 {
+                contents.push(b',');
+            }
+            contents.extend(serde_json::to_vec(msg)?);
+        }
+        contents.push(b']');
+        write_atomic(&self.path, &contents)
+    }
+
+    fn update(&self, index: usize, message: &Message) -> Result<(), Box<dyn Error>> {
+        let mut messages = self.load()?;
+        match messages.0.get_mut(index) {
+            Some(msg) => *msg = message.clone(),
+            None => return Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, format!("No message with index {index}")))),
+        }
+        self.replace(&messages)
+    }
+
+    fn replace(&self, messages: &Messages) -> Result<(), Box<dyn Error>> {
+        write_atomic(&self.path, &serde_json::to_vec(messages)?)
+    }
+}
@@ -0,0 +1,107 @@
+use std::error::Error;
+use std::io;
+use std::path::{Path, PathBuf};
+use rusqlite::{params, Connection, OptionalExtension};
+use crate::openai::{Message, Messages};
+use super::{create_parent, HistoryStore};
+
+/// The history as a table with a row per message, the messages themselves are stored as json
+pub struct SqliteStore {
+    path: PathBuf,
+}
+
+impl SqliteStore {
+    pub fn new(path: PathBuf) -> SqliteStore {
+        SqliteStore { path }
+    }
+
+    fn connect(&self) -> Result<Connection, Box<dyn Error>> {
+        create_parent(&self.path)?;
+        let connection = Connection::open(&self.path)?;
+        connection.execute_batch("CREATE TABLE IF NOT EXISTS messages (position INTEGER PRIMARY KEY, message TEXT NOT NULL)")?;
+        Ok(connection)
+    }
+
+    // positions start at 0 and have no gaps, so the next one is one past the highest
+    fn len(connection: &Connection) -> Result<usize, Box<dyn Error>> {
+        let last: Option<i64> = connection.query_row("SELECT MAX(position) FROM messages", [], |row| row.get(0)).optional()?.flatten();
+        Ok(last.map_or(0, |last| last as usize + 1))
+    }
+
+    fn query(connection: &Connection, sql: &str, param: i64) -> Result<Vec<Message>, Box<dyn Error>> {
+        let mut statement = connection.prepare(sql)?;
+        let rows = statement.query_map([param], |row| row.get::<_, String>(0))?;
+        let mut messages = vec![];
+        for row in rows {
+            messages.push(serde_json::from_str(&row?)?);
+        }
+        Ok(messages)
+    }
+
+    fn insert(connection: &Connection, start: usize, messages: &[Message]) -> Result<(), Box<dyn Error>> {
+        let mut statement = connection.prepare("INSERT INTO messages (position, message) VALUES (?1, ?2)")?;
+        for (index, msg) in messages.iter().enumerate() {
+            statement.execute(params![(start + index) as i64, serde_json::to_string(msg)?])?;
+        }
+        Ok(())
+    }
+}
+
+impl HistoryStore for SqliteStore {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn load(&self) -> Result<Messages, Box<dyn Error>> {
+        if !self.path.exists() {
+            return Ok(Messages(vec![]));
+        }
+        let connection = self.connect()?;
+        let messages = Self::query(&connection, "SELECT message FROM messages WHERE position >= ?1 ORDER BY position", 0)?;
+        Ok(Messages::from(messages))
+    }
+
+    fn load_tail(&self, count: usize) -> Result<(usize, Messages), Box<dyn Error>> {
+        if !self.path.exists() {
+            return Ok((0, Messages(vec![])));
+        }
+        let connection = self.connect()?;
+        let offset = Self::len(&connection)?.saturating_sub(count);
+        let messages = Self::query(&connection, "SELECT message FROM messages WHERE position >= ?1 ORDER BY position", offset as i64)?;
+        Ok((offset, Messages::from_stored(messages, offset)))
+    }
+
+    fn load_from(&self, index: usize) -> Result<Messages, Box<dyn Error>> {
+        if !self.path.exists() {
+            return Ok(Messages(vec![]));
+        }
+        let connection = self.connect()?;
+        let messages = Self::query(&connection, "SELECT message FROM messages WHERE position >= ?1 ORDER BY position", index as i64)?;
+        Ok(Messages::from_stored(messages, index))
+    }
+
+    fn append(&self, messages: &[Message]) -> Result<(), Box<dyn Error>> {
+        let mut connection = self.connect()?;
+        let transaction = connection.transaction()?;
+        let start = Self::len(&transaction)?;
+        Self::insert(&transaction, start, messages)?;
+        Ok(transaction.commit()?)
+    }
+
+    fn update(&self, index: usize, message: &Message) -> Result<(), Box<dyn Error>> {
+        let connection = self.connect()?;
+        let updated = connection.execute("UPDATE messages SET message = ?2 WHERE position = ?1", params![index as i64, serde_json::to_string(message)?])?;
+        if updated == 0 {
+            return Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, format!("No message with index {index}"))));
+        }
+        Ok(())
+    }
+
+    fn replace(&self, messages: &Messages) -> Result<(), Box<dyn Error>> {
+        let mut connection = self.connect()?;
+        let transaction = connection.transaction()?;
+        transaction.execute("DELETE FROM messages", [])?;
+        Self::insert(&transaction, 0, &messages.0)?;
+        Ok(transaction.commit()?)
+    }
+}
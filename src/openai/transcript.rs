@@ -0,0 +1,150 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use crate::openai::{context, Message, Role};
+
+/// A message with its number in the branch, the number other commands take to refer to it
+#[derive(Debug, Clone, Serialize)]
+pub struct Numbered {
+    pub number: usize,
+    #[serde(flatten)]
+    pub message: Message,
+}
+
+impl Display for Numbered {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}. {}", self.number, self.message)
+    }
+}
+
+/// Which messages of a branch are shown. Numbers start at 1 and count every message of the branch,
+/// including the system messages that are only shown when asked for by role.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    // the first and the last number, both included
+    pub range: Option<(usize, usize)>,
+    pub last: Option<usize>,
+    pub roles: Vec<Role>,
+    pub no_functions: bool,
+}
+
+/// Parses `3..7` like a rust range, so without 7, or `3..=7`, `3..`, `..7` and a single number
+pub fn parse_range(value: &str) -> Option<(usize, usize)> {
+    let number = |value: &str| value.parse::<usize>().ok().filter(|number| *number > 0);
+    let (first, last) = match value.split_once("..") {
+        Some((first, last)) => {
+            let first = if first.is_empty() { 1 } else { number(first)? };
+            let last = match last.strip_prefix('=') {
+                Some(last) => number(last)?,
+                None if last.is_empty() => usize::MAX,
+                None => number(last)?.checked_sub(1)?,
+            };
+            (first, last)
+        }
+        None => (number(value)?, number(value)?),
+    };
+    (first <= last).then_some((first, last))
+}
+
+/// The role given to `--role`, function results are stored as tool messages
+pub fn parse_role(value: &str) -> Option<Role> {
+    match value {
+        "function" => Some(Role::Tool),
+        _ => Role::parse(value),
+    }
+}
+
+impl Selection {
+    pub fn apply(&self, messages: &[Message]) -> Vec<Numbered> {
+        let mut selected = messages.iter().enumerate()
+            .map(|(index, msg)| Numbered { number: index + 1, message: msg.clone() })
+            .filter(|numbered| self.range.is_none_or(|(first, last)| (first..=last).contains(&numbered.number)))
+            .filter(|numbered| if self.roles.is_empty() {
+                numbered.message.role != Role::System
+            } else {
+                self.roles.contains(&numbered.message.role)
+            })
+            .filter_map(|mut numbered| {
+                if self.no_functions {
+                    if numbered.message.role == Role::Tool {
+                        return None;
+                    }
+                    // replies that only call functions are left out, the text of the others is kept
+                    if numbered.message.tool_calls.take().is_some() && numbered.message.text().is_empty() && numbered.message.refusal.is_none() {
+                        return None;
+                    }
+                }
+                Some(numbered)
+            })
+            .collect::<Vec<_>>();
+        if let Some(last) = self.last {
+            selected.drain(..selected.len().saturating_sub(last));
+        }
+        selected
+    }
+}
+
+/// Totals of the shown messages, the token usage and times are only known for messages stored with metadata
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Stats {
+    pub messages: usize,
+    pub roles: BTreeMap<String, usize>,
+    pub estimated_tokens: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    // replies with a measured latency, and the sum of those latencies
+    pub timed_replies: usize,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last: Option<DateTime<Utc>>,
+}
+
+impl Stats {
+    pub fn new(messages: &[Numbered]) -> Stats {
+        let mut stats = Stats::default();
+        for Numbered { message, .. } in messages {
+            stats.messages += 1;
+            *stats.roles.entry(message.role.to_string()).or_default() += 1;
+            stats.estimated_tokens += context::estimate_tokens(message);
+            let Some(metadata) = &message.metadata else {
+                continue;
+            };
+            if let Some(usage) = &metadata.usage {
+                stats.prompt_tokens += usage.prompt_tokens;
+                stats.completion_tokens += usage.completion_tokens;
+            }
+            if let Some(latency) = metadata.latency_ms {
+                stats.timed_replies += 1;
+                stats.latency_ms += latency;
+            }
+            if let Some(created_at) = metadata.created_at {
+                stats.first = Some(stats.first.map_or(created_at, |first| first.min(created_at)));
+                stats.last = Some(stats.last.map_or(created_at, |last| last.max(created_at)));
+            }
+        }
+        stats
+    }
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let roles = self.roles.iter()
+            .map(|(role, count)| format!("{count} {role}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(f, "messages:         {} ({roles})", self.messages)?;
+        writeln!(f, "estimated tokens: {}", self.estimated_tokens)?;
+        writeln!(f, "usage:            {} prompt, {} completion tokens", self.prompt_tokens, self.completion_tokens)?;
+        if self.timed_replies > 0 {
+            writeln!(f, "average latency:  {}ms over {} replies", self.latency_ms / self.timed_replies as u64, self.timed_replies)?;
+        }
+        if let (Some(first), Some(last)) = (self.first, self.last) {
+            let format = "%Y-%m-%d %H:%M:%S";
+            writeln!(f, "from {} to {}", first.with_timezone(&chrono::Local).format(format), last.with_timezone(&chrono::Local).format(format))?;
+        }
+        Ok(())
+    }
+}
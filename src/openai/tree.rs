@@ -0,0 +1,117 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use crate::openai::{rag, Message, Messages, Role};
+
+/// A new random message id
+pub fn new_id() -> String {
+    format!("{:016x}", RandomState::new().build_hasher().finish())
+}
+
+// histories written before there were branches have no ids, those messages are named after their
+// position in the store so they stay the same as long as the history is only appended to
+fn implicit_id(index: usize) -> String {
+    format!("#{index}")
+}
+
+/// Gives the messages stored without an id the id of their position, and the message before them as
+/// parent. `offset` is the position in the store of the first message.
+pub fn link(messages: &mut Messages, offset: usize) {
+    for index in 0..messages.0.len() {
+        if messages.0[index].id.is_some() {
+            continue;
+        }
+        let parent = match index {
+            0 => offset.checked_sub(1).map(implicit_id),
+            _ => messages.0[index - 1].id.clone(),
+        };
+        let msg = &mut messages.0[index];
+        msg.id = Some(implicit_id(offset + index));
+        msg.parent = parent;
+    }
+}
+
+/// The positions of the messages from the root to `leaf`, or to the last message when there is no
+/// such message. Parents that are not in `messages` end the branch.
+pub fn branch(messages: &[Message], leaf: Option<&str>) -> Vec<usize> {
+    let positions = messages.iter().enumerate()
+        .filter_map(|(position, msg)| Some((msg.id.as_deref()?, position)))
+        .collect::<HashMap<_, _>>();
+    let leaf = match leaf {
+        Some(leaf) => positions.get(leaf).copied().or_else(|| {
+            log::warn!("No message {leaf}, showing the last branch");
+            messages.len().checked_sub(1)
+        }),
+        None => messages.len().checked_sub(1),
+    };
+    let mut path = vec![];
+    let mut position = leaf;
+    while let Some(current) = position {
+        // a broken history could contain a cycle
+        if path.len() > messages.len() {
+            break;
+        }
+        path.push(current);
+        position = messages[current].parent.as_deref().and_then(|parent| positions.get(parent).copied());
+    }
+    path.reverse();
+    path
+}
+
+/// Every branch as the positions of its messages, from the root to a message without replies.
+/// Branches are ordered by where they fork, so adding to a branch does not change the order.
+pub fn branches(messages: &[Message]) -> Vec<Vec<usize>> {
+    let parents = messages.iter().filter_map(|msg| msg.parent.as_deref()).collect::<HashSet<_>>();
+    let mut branches = messages.iter()
+        .filter(|msg| msg.id.as_deref().is_none_or(|id| !parents.contains(id)))
+        .map(|leaf| branch(messages, leaf.id.as_deref()))
+        .collect::<Vec<_>>();
+    branches.sort();
+    branches
+}
+
+/// The position of the user message the last exchange of the branch starts with,
+/// the sources stored before it by earlier versions of `chat --rag` belong to the exchange
+pub fn last_exchange<'a>(branch: impl DoubleEndedIterator<Item = &'a Message> + ExactSizeIterator + Clone) -> Option<usize> {
+    let start = branch.clone().rposition(|msg| msg.role == Role::User)?;
+    match start.checked_sub(1).and_then(|previous| branch.clone().nth(previous)) {
+        Some(msg) if rag::is_sources_message(msg) => Some(start - 1),
+        _ => Some(start),
+    }
+}
+
+/// Removes the messages of `branch` from position `from` on that are not part of another branch,
+/// returns how many were removed
+pub fn prune(messages: &mut Messages, branch: &[usize], from: usize) -> usize {
+    let shared = branches(&messages.0).into_iter()
+        .filter(|other| other.last() != branch.last())
+        .flatten()
+        .collect::<HashSet<_>>();
+    let ids = branch.iter().skip(from)
+        .filter(|position| !shared.contains(*position))
+        .filter_map(|position| messages.0[*position].id.clone())
+        .collect::<HashSet<_>>();
+    remove(messages, |msg| msg.id.as_ref().is_some_and(|id| ids.contains(id)))
+}
+
+/// Removes the messages matching `remove`, their replies follow the parent of the removed message instead
+pub fn remove(messages: &mut Messages, remove: impl Fn(&Message) -> bool) -> usize {
+    let before = messages.0.len();
+    let mut parents = HashMap::new();
+    messages.0.retain(|msg| {
+        let keep = !remove(msg);
+        if !keep {
+            if let Some(id) = &msg.id {
+                parents.insert(id.clone(), msg.parent.clone());
+            }
+        }
+        keep
+    });
+    for msg in &mut messages.0 {
+        // the parent may have been removed too
+        while let Some(parent) = msg.parent.as_ref().and_then(|parent| parents.get(parent)) {
+            msg.parent = parent.clone();
+        }
+    }
+    before - messages.0.len()
+}
@@ -0,0 +1,245 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use crate::openai::Usage;
+
+pub const DEFAULT_USAGE_FILE: &str = "rustgpt/usage.jsonl";
+
+/// Prices in USD per million tokens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Price {
+    pub prompt: f64,
+    pub completion: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_prompt: Option<f64>,
+}
+
+impl Price {
+    const fn new(prompt: f64, completion: f64, cached_prompt: Option<f64>) -> Price {
+        Price { prompt, completion, cached_prompt }
+    }
+}
+
+// prices of the hosted models, configured prices take precedence over these
+const DEFAULT_PRICES: &[(&str, Price)] = &[
+    ("gpt-3.5-turbo", Price::new(0.5, 1.5, None)),
+    ("gpt-4", Price::new(30.0, 60.0, None)),
+    ("gpt-4-turbo", Price::new(10.0, 30.0, None)),
+    ("gpt-4o", Price::new(2.5, 10.0, Some(1.25))),
+    ("gpt-4o-mini", Price::new(0.15, 0.6, Some(0.075))),
+    ("gpt-4.1", Price::new(2.0, 8.0, Some(0.5))),
+    ("gpt-4.1-mini", Price::new(0.4, 1.6, Some(0.1))),
+    ("gpt-4.1-nano", Price::new(0.1, 0.4, Some(0.025))),
+    ("o1", Price::new(15.0, 60.0, Some(7.5))),
+    ("o3-mini", Price::new(1.1, 4.4, Some(0.55))),
+    ("text-embedding-3-small", Price::new(0.02, 0.0, None)),
+    ("text-embedding-3-large", Price::new(0.13, 0.0, None)),
+    ("text-embedding-ada-002", Price::new(0.1, 0.0, None)),
+    ("claude-3-haiku", Price::new(0.25, 1.25, Some(0.03))),
+    ("claude-3-5-haiku", Price::new(0.8, 4.0, Some(0.08))),
+    ("claude-3-5-sonnet", Price::new(3.0, 15.0, Some(0.3))),
+    ("claude-3-7-sonnet", Price::new(3.0, 15.0, Some(0.3))),
+    ("claude-sonnet-4", Price::new(3.0, 15.0, Some(0.3))),
+    ("claude-opus-4", Price::new(15.0, 75.0, Some(1.5))),
+    ("gemini-1.5-flash", Price::new(0.075, 0.3, Some(0.01875))),
+    ("gemini-1.5-pro", Price::new(1.25, 5.0, Some(0.3125))),
+    ("gemini-2.0-flash", Price::new(0.1, 0.4, Some(0.025))),
+    ("gemini-2.5-flash", Price::new(0.3, 2.5, Some(0.075))),
+    ("gemini-2.5-pro", Price::new(1.25, 10.0, Some(0.31))),
+];
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Budget {
+    /// maximum spend in USD per local calendar day
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily: Option<f64>,
+    /// maximum spend in USD per local calendar month
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub timestamp: DateTime<Utc>,
+    pub model: String,
+    pub session: String,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    #[serde(default)]
+    pub cached_tokens: u64,
+}
+
+impl UsageRecord {
+    pub fn new(model: &str, session: &str, usage: &Usage) -> UsageRecord {
+        UsageRecord {
+            timestamp: Utc::now(),
+            model: model.to_string(),
+            session: session.to_string(),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            cached_tokens: usage.cached_tokens(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupBy {
+    Model,
+    Session,
+    Day,
+}
+
+impl GroupBy {
+    pub fn parse(value: &str) -> Option<GroupBy> {
+        match value {
+            "model" => Some(GroupBy::Model),
+            "session" => Some(GroupBy::Session),
+            "day" => Some(GroupBy::Day),
+            _ => None,
+        }
+    }
+
+    fn key(&self, record: &UsageRecord) -> String {
+        match self {
+            GroupBy::Model => record.model.clone(),
+            GroupBy::Session => record.session.clone(),
+            GroupBy::Day => record.timestamp.with_timezone(&Local).date_naive().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cached_tokens: u64,
+    pub cost: f64,
+    // requests for models without a known price
+    pub unpriced: u64,
+}
+
+/// Looks up the price of a model, configured prices win over the defaults.
+/// Dated model versions like `gpt-4o-2024-08-06` use the price of the longest matching prefix.
+pub fn find_price<'a>(prices: &'a HashMap<String, Price>, model: &str) -> Option<&'a Price> {
+    let configured = prices.iter().map(|(name, price)| (name.as_str(), price));
+    let defaults = DEFAULT_PRICES.iter().map(|(name, price)| (*name, price));
+    let mut best: Option<(&str, &Price)> = None;
+    for (name, price) in configured.chain(defaults) {
+        if !model.starts_with(name) {
+            continue;
+        }
+        if best.is_none_or(|(best_name, _)| name.len() > best_name.len()) {
+            best = Some((name, price));
+        }
+    }
+    best.map(|(_, price)| price)
+}
+
+pub fn cost(record: &UsageRecord, prices: &HashMap<String, Price>) -> Option<f64> {
+    let price = find_price(prices, &record.model)?;
+    let cached = record.cached_tokens.min(record.prompt_tokens);
+    let uncached = record.prompt_tokens - cached;
+    let cached_price = price.cached_prompt.unwrap_or(price.prompt);
+    Some((uncached as f64 * price.prompt
+        + cached as f64 * cached_price
+        + record.completion_tokens as f64 * price.completion) / 1_000_000.0)
+}
+
+pub fn append_record(path: &str, record: &UsageRecord) -> Result<(), Box<dyn Error>> {
+    let path = Path::new(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+pub fn read_records(path: &str) -> Result<Vec<UsageRecord>, Box<dyn Error>> {
+    let path = Path::new(path);
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let contents = fs::read_to_string(path)?;
+    let mut records = vec![];
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(record) => records.push(record),
+            Err(e) => log::warn!("Skipping unreadable usage record '{line}': {e}"),
+        }
+    }
+    Ok(records)
+}
+
+pub fn totals(records: &[UsageRecord], prices: &HashMap<String, Price>, since: Option<DateTime<Utc>>, group_by: GroupBy) -> BTreeMap<String, UsageTotals> {
+    let mut groups: BTreeMap<String, UsageTotals> = BTreeMap::new();
+    for record in records.iter().filter(|record| since.is_none_or(|since| record.timestamp >= since)) {
+        let totals = groups.entry(group_by.key(record)).or_default();
+        totals.requests += 1;
+        totals.prompt_tokens += record.prompt_tokens;
+        totals.completion_tokens += record.completion_tokens;
+        totals.cached_tokens += record.cached_tokens;
+        match cost(record, prices) {
+            Some(cost) => totals.cost += cost,
+            None => totals.unpriced += 1,
+        }
+    }
+    groups
+}
+
+fn spent_since(records: &[UsageRecord], prices: &HashMap<String, Price>, since: DateTime<Utc>) -> f64 {
+    records.iter()
+        .filter(|record| record.timestamp >= since)
+        .filter_map(|record| cost(record, prices))
+        .sum()
+}
+
+fn local_midnight(date: NaiveDate) -> DateTime<Utc> {
+    let midnight = date.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+    match Local.from_local_datetime(&midnight).earliest() {
+        Some(local) => local.with_timezone(&Utc),
+        None => midnight.and_utc(),
+    }
+}
+
+/// Returns an error once the daily or monthly budget has been used up
+pub fn check_budget(budget: &Budget, records: &[UsageRecord], prices: &HashMap<String, Price>) -> Result<(), Box<dyn Error>> {
+    let today = Local::now().date_naive();
+    if let Some(limit) = budget.daily {
+        let spent = spent_since(records, prices, local_midnight(today));
+        if spent >= limit {
+            return Err(Box::new(io::Error::other(format!("Daily budget of ${limit:.2} exceeded, spent ${spent:.2} today"))));
+        }
+    }
+    if let Some(limit) = budget.monthly {
+        let first_of_month = today.with_day(1).expect("every month has a first day");
+        let spent = spent_since(records, prices, local_midnight(first_of_month));
+        if spent >= limit {
+            return Err(Box::new(io::Error::other(format!("Monthly budget of ${limit:.2} exceeded, spent ${spent:.2} this month"))));
+        }
+    }
+    Ok(())
+}
+
+/// Parses `YYYY-MM-DD` as local midnight, or a relative age like `7d`, `12h` or `2w`
+pub fn parse_since(value: &str) -> Result<DateTime<Utc>, Box<dyn Error>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(local_midnight(date));
+    }
+    let invalid = || Box::new(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid --since value '{value}', expected YYYY-MM-DD or an age like 7d"))) as Box<dyn Error>;
+    // the unit is the last character, which may be more than one byte in a typo
+    let (index, unit) = value.char_indices().last().ok_or_else(invalid)?;
+    let amount = value[..index].parse::<i64>().ok().filter(|amount| *amount >= 0).ok_or_else(invalid)?;
+    let age = match unit {
+        'h' => chrono::Duration::try_hours(amount),
+        'd' => chrono::Duration::try_days(amount),
+        'w' => chrono::Duration::try_weeks(amount),
+        _ => return Err(invalid()),
+    };
+    age.and_then(|age| Utc::now().checked_sub_signed(age)).ok_or_else(invalid)
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::error::Error;
 use std::process::{Child, Command, Stdio};
 use std::str;
@@ -6,6 +7,42 @@ use std::io::{Read, Write};
 use std::path::PathBuf;
 use std::thread;
 use std::time::Duration;
+use crate::openai::{FunctionParameters, FunctionProperty, OpenaiFunction};
+
+// the functions the model can call to run powershell commands
+pub fn functions() -> Vec<OpenaiFunction> {
+    vec![
+        OpenaiFunction {
+            name: "powershell".to_string(),
+            description: "Call a powershell command".to_string(),
+            parameters: FunctionParameters {
+                r#type: "object".to_string(),
+                properties: HashMap::from([("command".into(),
+                                            FunctionProperty {
+                                                r#type: "string".into(),
+                                                description: Some("the powershell command".into()),
+                                                r#enum: vec![],
+                                            })]),
+                required: vec!["command".into()],
+            },
+        },
+        OpenaiFunction {
+            name: "theme".to_string(),
+            description: "Call a powershell command to change the windows theme".to_string(),
+            parameters: FunctionParameters {
+                r#type: "object".to_string(),
+                properties: HashMap::from([("command".into(),
+                                            FunctionProperty {
+                                                r#type: "string".into(),
+                                                description: Some("themeA for dark mode and C for light mode".into()),
+                                                r#enum: vec!["& \"C:\\Windows\\Resources\\Themes\\themeA.theme\"".into(),
+                                                             "& \"C:\\Windows\\Resources\\Themes\\themeC.theme\"".into()],
+                                            })]),
+                required: vec!["command".into()],
+            },
+        },
+    ]
+}
 
 pub fn get_instance() -> std::io::Result<Child> {
     Command::new("powershell.exe")
@@ -0,0 +1,143 @@
+use std::env;
+use std::error::Error;
+use std::io;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use crate::{anthropic, gemini, ollama};
+use crate::openai::{self, Message, Messages, Model, OpenaiFunction, ResponseFormat, ToolChoice, Usage};
+use crate::openai::config::Settings;
+use crate::openai::embeddings::{EmbeddingOptions, Embeddings};
+
+pub const DEFAULT_PROVIDER: &str = "openai";
+
+/// A chat request in the shape of the openai api, every provider translates it to its own format
+#[derive(Debug)]
+pub struct ChatRequest {
+    pub model: String,
+    pub messages: Messages,
+    pub temperature: f32,
+    /// functions the model can call, several in one reply when it wants to
+    pub tools: Vec<OpenaiFunction>,
+    pub tool_choice: Option<ToolChoice>,
+    /// providers without a json mode are asked for json in the system prompt
+    pub response_format: Option<ResponseFormat>,
+}
+
+#[derive(Debug)]
+pub struct ChatResponse {
+    pub message: Message,
+    pub usage: Option<Usage>,
+    // the id of the reply at the provider, when it has one
+    pub request_id: Option<String>,
+    // why the reply ended, in the words of the provider
+    pub finish_reason: Option<String>,
+}
+
+/// Parts of the reply as they are streamed by the provider
+#[derive(Debug)]
+pub enum StreamEvent<'a> {
+    Role(&'a str),
+    Content(&'a str),
+    /// part of the explanation why the model declines to answer
+    Refusal(&'a str),
+    /// the name of the next tool call
+    ToolCall(&'a str),
+    /// part of the arguments of the last started tool call
+    ToolArguments(&'a str),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProviderSettings {
+    /// overrides the default api url of the provider
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+    /// environment variable holding the api key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+    /// how long ollama keeps the model loaded after a request, like `5m`, `1h` or `-1` for ever
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<String>,
+}
+
+impl ProviderSettings {
+    pub fn base_url<'a>(&'a self, default: &'a str) -> &'a str {
+        self.base_url.as_deref().unwrap_or(default).trim_end_matches('/')
+    }
+
+    pub fn api_key(&self, default_env: &str) -> Result<String, Box<dyn Error>> {
+        let name = self.api_key_env.as_deref().unwrap_or(default_env);
+        env::var(name).map_err(|_| Box::new(io::Error::new(io::ErrorKind::NotFound, format!("Environment variable {name} is not set"))) as Box<dyn Error>)
+    }
+}
+
+#[async_trait(?Send)]
+pub trait ChatProvider {
+    fn name(&self) -> &str;
+
+    /// Streams the next message, every received part is passed to `on_event` as it arrives
+    async fn chat(&self, request: ChatRequest, on_event: &mut dyn FnMut(StreamEvent)) -> Result<ChatResponse, Box<dyn Error>>;
+
+    /// Gets the next message without showing the streamed parts
+    async fn complete(&self, request: ChatRequest) -> Result<ChatResponse, Box<dyn Error>> {
+        self.chat(request, &mut |_| {}).await
+    }
+
+    async fn list_models(&self) -> Result<Vec<Model>, Box<dyn Error>>;
+
+    /// Embeds the inputs in batches of `options.batch_size`, the vectors are in the order of the inputs
+    async fn embed(&self, _inputs: &[String], _options: &EmbeddingOptions) -> Result<Embeddings, Box<dyn Error>> {
+        Err(unsupported(self.name(), "embeddings"))
+    }
+}
+
+/// Collects streamed bytes and hands out complete lines, a line can be split over several chunks
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    buffer: Vec<u8>,
+}
+
+impl LineBuffer {
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut lines = vec![];
+        while let Some(index) = self.buffer.iter().position(|&x| x == b'\n') {
+            let line = self.buffer.drain(..=index).collect::<Vec<u8>>();
+            lines.push(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string());
+        }
+        lines
+    }
+
+    /// Returns the last line when the stream did not end with a newline
+    pub fn finish(&mut self) -> Option<String> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let line = String::from_utf8_lossy(&self.buffer).trim_end_matches('\r').to_string();
+        self.buffer.clear();
+        Some(line)
+    }
+}
+
+pub fn unsupported(provider: &str, feature: &str) -> Box<dyn Error> {
+    Box::new(io::Error::new(io::ErrorKind::Unsupported, format!("The {provider} provider does not support {feature}")))
+}
+
+pub fn api_error(provider: &str, status: reqwest::StatusCode, body: &str) -> Box<dyn Error> {
+    log::error!("Received Error '{status}' from {provider} api: {body:?}");
+    Box::new(io::Error::new(io::ErrorKind::InvalidInput, format!("Error {status} from {provider} api")))
+}
+
+/// Creates the provider selected by the `provider` setting
+pub fn from_settings(settings: &Settings) -> Result<Box<dyn ChatProvider>, Box<dyn Error>> {
+    let name = settings.provider();
+    let empty = ProviderSettings::default();
+    let provider_settings = settings.providers().get(name).unwrap_or(&empty);
+    let client = reqwest::Client::new();
+    match name {
+        "openai" => Ok(Box::new(openai::OpenAiProvider::new(client, provider_settings)?)),
+        "anthropic" => Ok(Box::new(anthropic::AnthropicProvider::new(client, provider_settings)?)),
+        "ollama" => Ok(Box::new(ollama::OllamaProvider::new(client, provider_settings))),
+        "gemini" => Ok(Box::new(gemini::GeminiProvider::new(client, provider_settings)?)),
+        _ => Err(Box::new(io::Error::new(io::ErrorKind::InvalidInput, format!("Unknown provider '{name}'")))),
+    }
+}
@@ -0,0 +1,169 @@
+mod common;
+
+use std::collections::HashMap;
+use serde_json::json;
+use rustgpt::anthropic::{self, ContentBlock, ImageSource, StreamDecoder};
+use rustgpt::openai::{ChatHistory, ContentPart, FunctionCall, FunctionParameters, FunctionProperty, ImageUrl, Message, Messages, OpenaiFunction, ResponseFormat, Role, ToolCall, ToolChoice};
+use rustgpt::provider::{ChatRequest, ChatResponse};
+
+fn decode(name: &str) -> (Result<ChatResponse, String>, String) {
+    common::decode::<StreamDecoder>("anthropic", name, 7)
+}
+
+#[test]
+fn decodes_text_stream() {
+    let (response, printed) = decode("text_stream.sse");
+    let response = response.unwrap();
+    assert_eq!(printed, "assistant: Hello! How can I help you today?");
+    assert_eq!(response.message.role, Role::Assistant);
+    assert_eq!(response.message.text(), "Hello! How can I help you today?");
+    assert!(response.message.tool_calls.is_none());
+    let usage = response.usage.unwrap();
+    assert_eq!(usage.prompt_tokens, 35);
+    assert_eq!(usage.cached_tokens(), 10);
+    assert_eq!(usage.completion_tokens, 12);
+    assert_eq!(response.request_id.as_deref(), Some("msg_01XFDUDYJgAACzvnptvVoYEL"));
+    assert_eq!(response.finish_reason.as_deref(), Some("end_turn"));
+}
+
+#[test]
+fn decodes_tool_use_stream() {
+    let (response, printed) = decode("tool_use_stream.sse");
+    let message = response.unwrap().message;
+    assert_eq!(printed, "assistant: Let me list the files.powershell{\"command\": \"Get-ChildItem\"}theme{\"command\": \"C\"}");
+    assert_eq!(message.text(), "Let me list the files.");
+    let tool_calls = message.tool_calls.unwrap();
+    assert_eq!(tool_calls.len(), 2);
+    assert_eq!(tool_calls[0].id, "toolu_01T1x1fJ34qAmk2tNTrN7Up6");
+    assert_eq!(tool_calls[0].function.name, "powershell");
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&tool_calls[0].function.arguments).unwrap(), json!({"command": "Get-ChildItem"}));
+    assert_eq!(tool_calls[1].id, "toolu_01EjYLDhQeXnc5Aq5JMu3o9S");
+    assert_eq!(tool_calls[1].function.name, "theme");
+}
+
+#[test]
+fn returns_stream_errors() {
+    let (response, _) = decode("error_stream.sse");
+    assert!(response.unwrap_err().contains("Overloaded"));
+}
+
+#[test]
+fn maps_messages_to_request() {
+    let mut messages = Messages::new();
+    messages.set_system_message("You translate commands");
+    messages.add_user_message("list files");
+    messages.add_user_message("in this directory");
+    messages.push(Message {
+        role: Role::Assistant,
+        tool_calls: Some(vec![
+            ToolCall::new("toolu_01", FunctionCall { name: "powershell".into(), arguments: "{\"command\":\"ls\"}".into() }),
+            ToolCall::new("toolu_02", FunctionCall { name: "powershell".into(), arguments: "{\"command\":\"pwd\"}".into() }),
+        ]),
+        ..Default::default()
+    });
+    messages.add_tool_message("toolu_02", "C:\\");
+    messages.add_tool_message("toolu_01", "a.txt");
+    messages.add_message(Role::Assistant, "There is one file");
+    let request = anthropic::to_request(ChatRequest {
+        model: "claude-3-5-haiku-latest".to_string(),
+        messages,
+        temperature: 0.1,
+        tools: vec![OpenaiFunction {
+            name: "powershell".into(),
+            description: "Call a powershell command".into(),
+            parameters: FunctionParameters {
+                r#type: "object".into(),
+                properties: HashMap::from([("command".into(), FunctionProperty {
+                    r#type: "string".into(),
+                    description: None,
+                    r#enum: vec![],
+                })]),
+                required: vec!["command".into()],
+            },
+        }],
+        tool_choice: Some(ToolChoice::Function("powershell".into())),
+        response_format: None,
+    });
+
+    assert_eq!(request.system.as_deref(), Some("You translate commands"));
+    let roles = request.messages.iter().map(|msg| msg.role.as_str()).collect::<Vec<_>>();
+    assert_eq!(roles, ["user", "assistant", "user", "assistant"]);
+    assert_eq!(request.messages[0].content.len(), 2);
+    assert_eq!(request.messages[1].content, [
+        ContentBlock::ToolUse { id: "toolu_01".into(), name: "powershell".into(), input: json!({"command": "ls"}) },
+        ContentBlock::ToolUse { id: "toolu_02".into(), name: "powershell".into(), input: json!({"command": "pwd"}) },
+    ]);
+    assert_eq!(request.messages[2].content, [
+        ContentBlock::ToolResult { tool_use_id: "toolu_02".into(), content: "C:\\".into() },
+        ContentBlock::ToolResult { tool_use_id: "toolu_01".into(), content: "a.txt".into() },
+    ]);
+    assert_eq!(request.tool_choice, Some(json!({"type": "tool", "name": "powershell"})));
+    assert_eq!(request.tools[0].name, "powershell");
+    assert_eq!(serde_json::to_value(&request.tools[0].input_schema).unwrap(),
+               json!({"type": "object", "properties": {"command": {"type": "string"}}, "required": ["command"]}));
+}
+
+#[test]
+fn tool_results_without_call_become_text() {
+    let mut messages = Messages::new();
+    messages.push(Message {
+        tool_call_id: Some("toolu_01".to_string()),
+        name: Some("powershell".to_string()),
+        ..Message::new(Role::Tool, "a.txt")
+    });
+    messages.add_user_message("what does that mean?");
+    let request = anthropic::to_request(ChatRequest {
+        model: "claude-3-5-haiku-latest".to_string(),
+        messages,
+        temperature: 0.5,
+        tools: vec![],
+        tool_choice: Some(ToolChoice::Auto),
+        response_format: None,
+    });
+    assert!(request.tool_choice.is_none());
+    assert!(request.system.is_none());
+    assert_eq!(request.messages.len(), 1);
+    assert_eq!(request.messages[0].content, [
+        ContentBlock::Text { text: "Result of powershell:\na.txt".into() },
+        ContentBlock::Text { text: "what does that mean?".into() },
+    ]);
+}
+
+#[test]
+fn asks_for_json_in_system_prompt() {
+    let mut messages = Messages::new();
+    messages.set_system_message("You extract data");
+    messages.add_user_message("Alice is 30");
+    let request = anthropic::to_request(ChatRequest {
+        model: "claude-3-5-haiku-latest".to_string(),
+        messages,
+        temperature: 0.0,
+        tools: vec![],
+        tool_choice: None,
+        response_format: Some(ResponseFormat::JsonObject),
+    });
+    assert_eq!(request.system.as_deref(), Some("You extract data\n\nReply with a single JSON object and nothing else."));
+}
+
+#[test]
+fn maps_images_to_image_blocks() {
+    let image = |url: &str| ContentPart::ImageUrl { image_url: ImageUrl { url: url.into(), detail: None } };
+    let messages = Messages(vec![Message::with_parts(Role::User, vec![
+        ContentPart::Text { text: "what changed?".into() },
+        image("data:image/png;base64,iVBORw0KGgo="),
+        image("https://example.com/after.png"),
+    ])]);
+    let request = anthropic::to_request(ChatRequest {
+        model: "claude-3-5-haiku-latest".to_string(),
+        messages,
+        temperature: 0.5,
+        tools: vec![],
+        tool_choice: None,
+        response_format: None,
+    });
+    assert_eq!(request.messages[0].content, [
+        ContentBlock::Text { text: "what changed?".into() },
+        ContentBlock::Image { source: ImageSource::Base64 { media_type: "image/png".into(), data: "iVBORw0KGgo=".into() } },
+        ContentBlock::Image { source: ImageSource::Url { url: "https://example.com/after.png".into() } },
+    ]);
+}
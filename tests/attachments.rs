@@ -0,0 +1,62 @@
+use std::fs;
+use serde_json::json;
+use rustgpt::openai::{ChatHistory, Messages};
+use rustgpt::openai::attachments::{self, Status};
+
+#[test]
+fn refreshes_changed_files() {
+    let dir = std::env::temp_dir().join(format!("rustgpt-attachments-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("main.rs");
+    let path = file.to_string_lossy().to_string();
+    fs::write(&file, "fn main() {\n    println!(\"hi\");\n}\n").unwrap();
+
+    let mut history = Messages::new();
+    history.push(attachments::attach(&path, 1024).unwrap().unwrap());
+    history.add_user_message("what does it print?");
+    let saved = serde_json::to_value(&history).unwrap();
+    assert_eq!(saved[0]["attachment"], json!({"path": path, "hash": attachments::hash("fn main() {\n    println!(\"hi\");\n}\n"), "max_size": 1024}));
+    assert_eq!(saved[0]["content"], json!(format!("{path}:\n```rust\nfn main() {{\n    println!(\"hi\");\n}}\n```")));
+
+    let changes = attachments::refresh(&mut history);
+    assert_eq!(changes.len(), 1);
+    assert_eq!((changes[0].index, &changes[0].status), (0, &Status::Unchanged));
+
+    fs::write(&file, "fn main() {\n    println!(\"hello\");\n    println!(\"world\");\n}\n").unwrap();
+    let changes = attachments::refresh(&mut history);
+    assert_eq!(changes[0].status, Status::Changed { added: 2, removed: 1 });
+    assert!(history.0[0].text().contains("world"));
+    assert_eq!(attachments::refresh(&mut history)[0].status, Status::Unchanged);
+
+    // a removed file keeps its last copy
+    fs::remove_file(&file).unwrap();
+    assert!(matches!(attachments::refresh(&mut history)[0].status, Status::Unreadable(_)));
+    assert!(history.0[0].text().contains("world"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn skips_binary_files() {
+    let file = std::env::temp_dir().join(format!("rustgpt-attachments-{}.bin", std::process::id()));
+    fs::write(&file, [0u8, 1, 2]).unwrap();
+    assert!(attachments::attach(&file.to_string_lossy(), 1024).unwrap().is_none());
+    fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn refreshes_with_the_limit_of_the_attachment() {
+    let file = std::env::temp_dir().join(format!("rustgpt-attachments-{}.txt", std::process::id()));
+    let path = file.to_string_lossy().to_string();
+    fs::write(&file, "small").unwrap();
+    let mut history = Messages::new();
+    history.push(attachments::attach(&path, 16).unwrap().unwrap());
+
+    fs::write(&file, "larger than sixteen bytes").unwrap();
+    assert_eq!(attachments::refresh(&mut history)[0].status, Status::Unreadable("binary or too large".to_string()));
+    assert!(history.0[0].text().contains("small"));
+
+    // attachments stored without a limit are read again with the default one
+    history.0[0].attachment.as_mut().unwrap().max_size = None;
+    assert!(matches!(attachments::refresh(&mut history)[0].status, Status::Changed { .. }));
+    fs::remove_file(&file).unwrap();
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use serde_json::json;
+use rustgpt::openai::Model;
+use rustgpt::openai::catalog::{self, Capabilities};
+use rustgpt::openai::config::Settings;
+use rustgpt::provider;
+use common::Response;
+
+fn model(id: &str, owned_by: &str) -> Model {
+    Model { id: id.to_string(), object: "model".to_string(), created: 0, owned_by: owned_by.to_string() }
+}
+
+// settings for the ollama api at `base_url`, with the models cache in `dir`
+fn settings(dir: &Path, base_url: &str) -> Settings {
+    serde_json::from_value(json!({
+        "model": "llama3.2",
+        "history_file": dir.join("history.json"),
+        "config_file": dir.join("config.json"),
+        "models_cache_file": dir.join("models.json"),
+        "capabilities": {"llama3": {"context_length": 8192}, "gpt-4o-mini": {"vision": false}},
+        "provider": "ollama",
+        "providers": {"ollama": {"base_url": base_url}},
+    })).unwrap()
+}
+
+fn ids(models: &[Model]) -> Vec<&str> {
+    models.iter().map(|model| model.id.as_str()).collect()
+}
+
+#[test]
+fn filters_by_id_and_owner() {
+    let gpt = model("gpt-4o-Mini", "system");
+    assert!(catalog::matches(&gpt, None, None));
+    assert!(catalog::matches(&gpt, Some("4O-MINI"), Some("System")));
+    assert!(!catalog::matches(&gpt, Some("turbo"), None));
+    assert!(!catalog::matches(&gpt, None, Some("openai")));
+}
+
+#[test]
+fn merges_configured_capabilities_over_the_defaults() {
+    let configured = HashMap::from([("gpt-4o-mini".to_string(), Capabilities { vision: Some(false), ..Default::default() })]);
+    // the longest prefix wins, unset fields keep the built in values
+    let capabilities = catalog::find_capabilities(&configured, "gpt-4o-mini-2024-07-18");
+    assert_eq!((capabilities.context_length, capabilities.tools, capabilities.vision), (Some(128_000), Some(true), Some(false)));
+    let capabilities = catalog::find_capabilities(&configured, "gpt-4o-2024-08-06");
+    assert_eq!(capabilities.vision, Some(true));
+    let capabilities = catalog::find_capabilities(&configured, "my-local-model");
+    assert!(capabilities.context_length.is_none() && capabilities.tools.is_none());
+
+    let dir = common::temp_dir("catalog-info");
+    let info = catalog::model_info(&settings(&dir, "http://127.0.0.1:1"), model("llama3.2", "ollama"));
+    assert_eq!(info.capabilities.context_length, Some(8192));
+    assert!(info.price.is_none());
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn caches_the_model_list() {
+    let dir = common::temp_dir("catalog-cache");
+    let (base_url, requests) = common::serve(|_| Response::json(&json!({"models": [{"name": "llama3.2"}, {"name": "qwen2.5"}]})));
+    let settings = settings(&dir, &base_url);
+    let provider = provider::from_settings(&settings).unwrap();
+
+    let models = catalog::get_models(provider.as_ref(), &settings, false).await.unwrap();
+    assert_eq!(ids(&models), ["llama3.2", "qwen2.5"]);
+    assert_eq!(requests.recv().unwrap().path, "/api/tags");
+    // the second call is answered from the cache
+    let models = catalog::get_models(provider.as_ref(), &settings, false).await.unwrap();
+    assert_eq!(ids(&models), ["llama3.2", "qwen2.5"]);
+    assert!(requests.try_recv().is_err());
+    // unless a refresh is asked for
+    catalog::get_models(provider.as_ref(), &settings, true).await.unwrap();
+    assert_eq!(requests.recv().unwrap().path, "/api/tags");
+
+    // a stale cache is still used when the api can not be reached
+    let unreachable = self::settings(&dir, "http://127.0.0.1:1");
+    let provider = provider::from_settings(&unreachable).unwrap();
+    let models = catalog::get_models(provider.as_ref(), &unreachable, true).await.unwrap();
+    assert_eq!(ids(&models), ["llama3.2", "qwen2.5"]);
+    assert!(catalog::validate_model(provider.as_ref(), &unreachable, "llama3.2").await.is_ok());
+    let error = catalog::validate_model(provider.as_ref(), &unreachable, "llama3").await.unwrap_err();
+    assert!(error.to_string().contains("did you mean llama3.2?"), "{error}");
+    let _ = fs::remove_dir_all(dir);
+}
+
+#[tokio::test]
+async fn ignores_the_cache_of_another_provider() {
+    let dir = common::temp_dir("catalog-provider");
+    let cache = json!({"fetched_at": chrono::Utc::now(), "provider": "openai", "models": [{"id": "gpt-4o"}]});
+    fs::write(dir.join("models.json"), cache.to_string()).unwrap();
+    let (base_url, requests) = common::serve(|_| Response::json(&json!({"models": [{"name": "llama3.2"}]})));
+    let settings = settings(&dir, &base_url);
+    let provider = provider::from_settings(&settings).unwrap();
+
+    let models = catalog::get_models(provider.as_ref(), &settings, false).await.unwrap();
+    assert_eq!(ids(&models), ["llama3.2"]);
+    assert_eq!(requests.recv().unwrap().path, "/api/tags");
+    let _ = fs::remove_dir_all(dir);
+}
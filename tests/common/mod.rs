@@ -0,0 +1,171 @@
+// helpers shared by the integration tests, every test binary uses only some of them
+#![allow(dead_code)]
+
+use std::error::Error;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use serde_json::Value;
+use rustgpt::provider::{ChatResponse, StreamEvent};
+use rustgpt::{anthropic, gemini, ollama, openai};
+
+/// An empty directory for a test, removed first when an earlier run left it behind
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rustgpt-test-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A recorded response of a backend, from `tests/fixtures/<backend>`
+pub fn fixture(backend: &str, name: &str) -> Vec<u8> {
+    fs::read(format!("{}/tests/fixtures/{backend}/{name}", env!("CARGO_MANIFEST_DIR"))).unwrap()
+}
+
+#[derive(Debug)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub path: String,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+}
+
+pub struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+    // streamed responses are sent in http chunks of this size
+    chunk_size: Option<usize>,
+}
+
+impl Response {
+    pub fn json(body: &Value) -> Response {
+        Response { status: 200, content_type: "application/json", body: body.to_string().into_bytes(), chunk_size: None }
+    }
+
+    /// A recorded response sent in small chunks, so lines are split like on a real connection
+    pub fn stream(status: u16, content_type: &'static str, body: Vec<u8>, chunk_size: usize) -> Response {
+        Response { status, content_type, body, chunk_size: Some(chunk_size) }
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> RecordedRequest {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        if header.trim().is_empty() {
+            break;
+        }
+        if let Some((key, value)) = header.split_once(':') {
+            if key.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap();
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).unwrap();
+    let mut parts = request_line.split_whitespace();
+    RecordedRequest {
+        method: parts.next().unwrap().to_string(),
+        path: parts.next().unwrap().to_string(),
+        body: String::from_utf8(body).unwrap(),
+    }
+}
+
+/// A local http server that answers every connection with `respond` and passes on the requests
+pub fn serve(mut respond: impl FnMut(&RecordedRequest) -> Response + Send + 'static) -> (String, Receiver<RecordedRequest>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let request = read_request(&mut BufReader::new(stream.try_clone().unwrap()));
+            let response = respond(&request);
+            // the test may already be done with the requests
+            let _ = sender.send(request);
+
+            let Response { status, content_type, body, chunk_size } = response;
+            match chunk_size {
+                Some(chunk_size) => {
+                    write!(stream, "HTTP/1.1 {status} OK\r\nContent-Type: {content_type}\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n").unwrap();
+                    for chunk in body.chunks(chunk_size) {
+                        write!(stream, "{:x}\r\n", chunk.len()).unwrap();
+                        stream.write_all(chunk).unwrap();
+                        write!(stream, "\r\n").unwrap();
+                        stream.flush().unwrap();
+                    }
+                    write!(stream, "0\r\n\r\n").unwrap();
+                }
+                None => {
+                    write!(stream, "HTTP/1.1 {status} OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).unwrap();
+                    stream.write_all(&body).unwrap();
+                }
+            }
+        }
+    });
+    (base_url, receiver)
+}
+
+/// Answers the connections with `responses` in order
+pub fn serve_all(responses: Vec<Response>) -> (String, Receiver<RecordedRequest>) {
+    let mut responses = responses.into_iter();
+    serve(move |_| responses.next().expect("more requests than responses"))
+}
+
+/// The stream decoders of the backends, they take the same calls but share no trait
+pub trait Decoder: Default {
+    fn feed(&mut self, chunk: &[u8], on_event: &mut dyn FnMut(StreamEvent)) -> Result<(), Box<dyn Error>>;
+    fn finish(self, on_event: &mut dyn FnMut(StreamEvent)) -> Result<ChatResponse, Box<dyn Error>>;
+}
+
+macro_rules! impl_decoder {
+    ($($decoder:ty),*) => {$(
+        impl Decoder for $decoder {
+            fn feed(&mut self, chunk: &[u8], on_event: &mut dyn FnMut(StreamEvent)) -> Result<(), Box<dyn Error>> {
+                <$decoder>::feed(self, chunk, on_event)
+            }
+
+            fn finish(self, on_event: &mut dyn FnMut(StreamEvent)) -> Result<ChatResponse, Box<dyn Error>> {
+                <$decoder>::finish(self, on_event)
+            }
+        }
+    )*};
+}
+
+impl_decoder!(openai::StreamDecoder, anthropic::StreamDecoder, gemini::StreamDecoder, ollama::StreamDecoder);
+
+/// Appends the streamed parts the way the chat prints them
+pub fn printer(printed: &mut String) -> impl FnMut(StreamEvent) + '_ {
+    |event| match event {
+        StreamEvent::Role(role) => printed.push_str(&format!("{role}: ")),
+        StreamEvent::Content(text) | StreamEvent::Refusal(text) | StreamEvent::ToolCall(text) | StreamEvent::ToolArguments(text) => printed.push_str(text),
+    }
+}
+
+fn decode_chunks<D: Decoder>(data: &[u8], chunk_size: usize, on_event: &mut dyn FnMut(StreamEvent)) -> Result<ChatResponse, Box<dyn Error>> {
+    let mut decoder = D::default();
+    for chunk in data.chunks(chunk_size) {
+        decoder.feed(chunk, on_event)?;
+    }
+    decoder.finish(on_event)
+}
+
+/// Feeds the fixture `name` of `backend` to a decoder in chunks of `chunk_size` bytes,
+/// returns the response and what was printed while decoding
+pub fn decode<D: Decoder>(backend: &str, name: &str, chunk_size: usize) -> (Result<ChatResponse, String>, String) {
+    let mut printed = String::new();
+    let response = decode_chunks::<D>(&fixture(backend, name), chunk_size, &mut printer(&mut printed));
+    (response.map_err(|e| e.to_string()), printed)
+}
@@ -0,0 +1,105 @@
+use serde_json::json;
+use rustgpt::openai::{tree, Message, Messages, Metadata, Summary};
+use rustgpt::openai::context::{self, ContextSettings, ContextStrategy};
+
+// every message is 10 tokens of text and 4 tokens of overhead
+fn message(role: &str, name: &str) -> Message {
+    serde_json::from_value(json!({"role": role, "content": format!("{name:<40}")})).unwrap()
+}
+
+fn conversation() -> Messages {
+    let mut pinned = message("user", "pinned");
+    pinned.pinned = true;
+    Messages(vec![
+        message("system", "system"),
+        message("user", "q1"),
+        message("assistant", "a1"),
+        pinned,
+        message("assistant", "a2"),
+        message("user", "q3"),
+        message("assistant", "a3"),
+        message("user", "q4"),
+    ])
+}
+
+fn names(messages: &Messages) -> Vec<String> {
+    messages.0.iter().map(|msg| serde_json::to_value(msg).unwrap()["content"].as_str().unwrap().trim_end().to_string()).collect()
+}
+
+fn settings(strategy: ContextStrategy, max_tokens: usize) -> ContextSettings {
+    serde_json::from_value(json!({"strategy": strategy, "max_tokens": max_tokens, "window": 2, "keep_recent": 2})).unwrap()
+}
+
+#[test]
+fn estimates_tokens_per_message() {
+    assert_eq!(context::estimate_text_tokens("12345678"), 2);
+    assert_eq!(context::estimate_tokens(&message("user", "q1")), 14);
+    assert_eq!(context::estimate_history_tokens(&conversation().0), 8 * 14);
+}
+
+#[test]
+fn none_sends_everything() {
+    let history = conversation();
+    assert_eq!(names(&context::trim(&history, &settings(ContextStrategy::None, 1))), names(&history));
+}
+
+#[test]
+fn drop_oldest_keeps_system_and_pinned_messages() {
+    let history = conversation();
+    let trimmed = context::trim(&history, &settings(ContextStrategy::DropOldest, 5 * 14));
+    assert_eq!(names(&trimmed), ["system", "pinned", "q3", "a3", "q4"]);
+    // the last message is kept even when it does not fit on its own
+    let trimmed = context::drop_oldest(&history, 0);
+    assert_eq!(names(&trimmed), ["system", "pinned", "q4"]);
+    // a history that fits is left alone
+    assert_eq!(context::drop_oldest(&history, 1000).0.len(), 8);
+}
+
+#[test]
+fn drop_oldest_does_not_start_with_a_reply() {
+    let history = Messages(vec![message("user", "q1"), message("assistant", "a1"), message("assistant", "a2"), message("user", "q2")]);
+    // dropping q1 would leave the answers without their question
+    assert_eq!(names(&context::drop_oldest(&history, 3 * 14)), ["q2"]);
+}
+
+#[test]
+fn sliding_window_keeps_system_and_pinned_messages() {
+    let trimmed = context::trim(&conversation(), &settings(ContextStrategy::SlidingWindow, 0));
+    assert_eq!(names(&trimmed), ["system", "pinned", "a3", "q4"]);
+}
+
+#[test]
+fn summarize_splits_off_the_older_messages() {
+    let history = conversation();
+    let (old, recent) = context::split_for_summary(&history, &settings(ContextStrategy::Summarize, 5 * 14)).unwrap();
+    assert_eq!(names(&old), ["q1", "a1", "a2", "q3"]);
+    assert_eq!(names(&recent), ["system", "pinned", "a3", "q4"]);
+    // nothing to summarize when the history fits
+    assert!(context::split_for_summary(&history, &settings(ContextStrategy::Summarize, 1000)).is_none());
+    // without the api the summary falls back to dropping the oldest messages
+    let trimmed = context::trim(&history, &settings(ContextStrategy::Summarize, 5 * 14));
+    assert_eq!(names(&trimmed), ["system", "pinned", "q3", "a3", "q4"]);
+}
+
+#[test]
+fn summaries_are_reused_and_extended() {
+    let mut history = conversation();
+    tree::link(&mut history, 0);
+    let settings = settings(ContextStrategy::Summarize, 5 * 14);
+    let (old, _) = context::split_for_summary(&history, &settings).unwrap();
+    assert!(context::cached_summary(&history, &old).is_none());
+
+    // the reply stores the summary of the messages up to a1
+    let until = history.0[2].id.clone().unwrap();
+    history.0[4].metadata = Some(Metadata { summary: Some(Summary { text: "q1 and a1".into(), until }), ..Default::default() });
+    let (summary, newer) = context::cached_summary(&history, &old).unwrap();
+    assert_eq!(summary.text, "q1 and a1");
+    assert_eq!(names(&Messages(newer.to_vec())), ["a2", "q3"]);
+
+    // a summary of every older message is sent as is
+    let until = history.0[5].id.clone().unwrap();
+    history.0[6].metadata = Some(Metadata { summary: Some(Summary { text: "up to q3".into(), until }), ..Default::default() });
+    let (summary, newer) = context::cached_summary(&history, &old).unwrap();
+    assert_eq!(summary.text, "up to q3");
+    assert!(newer.is_empty());
+}
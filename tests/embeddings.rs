@@ -0,0 +1,82 @@
+mod common;
+
+use std::sync::mpsc::Receiver;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde_json::{json, Value};
+use rustgpt::openai::OpenAiProvider;
+use rustgpt::openai::embeddings::{self, EmbeddingOptions, EncodingFormat};
+use rustgpt::provider::{ChatProvider, ProviderSettings};
+use common::{RecordedRequest, Response};
+
+// answers every request with the next response
+fn serve(responses: Vec<Value>) -> (String, Receiver<RecordedRequest>) {
+    common::serve_all(responses.iter().map(Response::json).collect())
+}
+
+fn provider(base_url: &str) -> OpenAiProvider {
+    std::env::set_var("RUSTGPT_TEST_EMBEDDINGS_KEY", "test");
+    let settings = ProviderSettings {
+        base_url: Some(base_url.to_string()),
+        api_key_env: Some("RUSTGPT_TEST_EMBEDDINGS_KEY".to_string()),
+        keep_alive: None,
+    };
+    OpenAiProvider::new(reqwest::Client::new(), &settings).unwrap()
+}
+
+fn base64_vector(values: &[f32]) -> String {
+    STANDARD.encode(values.iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<_>>())
+}
+
+#[tokio::test]
+async fn embeds_in_batches() {
+    let (base_url, requests) = serve(vec![
+        // the api does not promise to keep the order of the inputs
+        json!({"object": "list", "data": [
+            {"object": "embedding", "index": 1, "embedding": [0.0, 1.0]},
+            {"object": "embedding", "index": 0, "embedding": [1.0, 0.0]},
+        ], "model": "text-embedding-3-small", "usage": {"prompt_tokens": 4, "total_tokens": 4}}),
+        json!({"object": "list", "data": [
+            {"object": "embedding", "index": 0, "embedding": [0.6, 0.8]},
+        ], "model": "text-embedding-3-small", "usage": {"prompt_tokens": 2, "total_tokens": 2}}),
+    ]);
+    let options = EmbeddingOptions { dimensions: Some(2), batch_size: 2, ..Default::default() };
+    let inputs = ["first", "second", "third"].map(String::from);
+    let embeddings = provider(&base_url).embed(&inputs, &options).await.unwrap();
+
+    assert_eq!(embeddings.vectors, [vec![1.0, 0.0], vec![0.0, 1.0], vec![0.6, 0.8]]);
+    assert_eq!(embeddings.usage.prompt_tokens, 6);
+    assert_eq!(requests.recv().unwrap().json(), json!({
+        "model": "text-embedding-3-small",
+        "input": ["first", "second"],
+        "dimensions": 2,
+        "encoding_format": "float",
+    }));
+    assert_eq!(requests.recv().unwrap().json()["input"], json!(["third"]));
+}
+
+#[tokio::test]
+async fn decodes_base64_embeddings() {
+    let (base_url, requests) = serve(vec![json!({"object": "list", "data": [
+        {"object": "embedding", "index": 0, "embedding": base64_vector(&[0.25, -1.5, 3.0])},
+    ], "model": "text-embedding-3-small"})]);
+    let options = EmbeddingOptions { encoding_format: EncodingFormat::Base64, ..Default::default() };
+    let embeddings = provider(&base_url).embed(&["text".to_string()], &options).await.unwrap();
+    assert_eq!(embeddings.vectors, [vec![0.25, -1.5, 3.0]]);
+    assert_eq!(requests.recv().unwrap().json()["encoding_format"], json!("base64"));
+}
+
+#[test]
+fn ranks_by_cosine_similarity() {
+    assert!((embeddings::cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]) - 1.0).abs() < 1e-6);
+    assert!(embeddings::cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]).abs() < 1e-6);
+    assert_eq!(embeddings::cosine_similarity(&[0.0, 0.0], &[1.0, 1.0]), 0.0);
+
+    let mut vector = vec![3.0, 4.0];
+    embeddings::normalize(&mut vector);
+    assert_eq!(vector, [0.6, 0.8]);
+
+    let candidates = [vec![0.0, 1.0], vec![1.0, 0.1], vec![-1.0, 0.0], vec![1.0, 1.0]];
+    let ranked = embeddings::top_k(&[1.0, 0.0], candidates.iter().map(Vec::as_slice), 2);
+    assert_eq!(ranked.iter().map(|(index, _)| *index).collect::<Vec<_>>(), [1, 3]);
+}
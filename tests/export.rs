@@ -0,0 +1,56 @@
+use serde_json::{json, Value};
+use rustgpt::openai::Messages;
+use rustgpt::openai::export::{self, Format};
+use rustgpt::powershell;
+
+fn conversation() -> Messages {
+    serde_json::from_value(json!([
+        {"role": "user", "content": [
+            {"type": "text", "text": "What is in <this> folder?"},
+            {"type": "image_url", "image_url": {"url": "rustgpt-image:abc.png"}},
+        ]},
+        {"role": "assistant", "content": null, "tool_calls": [
+            {"id": "call_1", "type": "function", "function": {"name": "powershell", "arguments": "{\"command\":\"ls\"}"}},
+        ]},
+        {"role": "tool", "content": "a.txt", "tool_call_id": "call_1"},
+        {"role": "assistant", "content": "There is one file:\n\n```sh\ncat a.txt\n```", "pinned": true},
+        {"role": "user", "content": "Delete it"},
+        {"role": "assistant", "content": null, "refusal": "I can not delete files."},
+    ])).unwrap()
+}
+
+#[test]
+fn exports_markdown() {
+    let markdown = export::markdown(&conversation(), "session", "rustgpt/images");
+    assert!(markdown.starts_with("# session\n\n## User\n\nWhat is in <this> folder?\n\n![image](rustgpt/images/abc.png)\n\n## Assistant\n\n"), "{markdown}");
+    assert!(markdown.contains("Call to `powershell`:\n\n```json\n{\n  \"command\": \"ls\"\n}\n```"), "{markdown}");
+    assert!(markdown.contains("## Tool\n\n```\na.txt\n```"), "{markdown}");
+    assert!(markdown.ends_with("## Assistant\n\n> I can not delete files.\n"), "{markdown}");
+}
+
+#[test]
+fn exports_standalone_html() {
+    let html = export::html(&conversation(), "session");
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<p>What is in &lt;this&gt; folder?</p>"), "{html}");
+    assert!(html.contains("<pre><code class=\"language-sh\">cat a.txt</code></pre>"), "{html}");
+    assert!(html.contains("<img src=\"rustgpt-image:abc.png\" alt=\"image\">"), "{html}");
+    assert!(html.contains("hljs.highlightAll()"));
+}
+
+#[test]
+fn exports_finetuning_example() {
+    let line = export::finetune_jsonl(&conversation(), &powershell::functions()).unwrap();
+    assert!(!line.contains('\n'));
+    let example: Value = serde_json::from_str(&line).unwrap();
+    let messages = example["messages"].as_array().unwrap();
+    // the conversation ends with the last answer, the refusal and the request it refuses are left out
+    assert_eq!(messages.iter().map(|msg| msg["role"].as_str().unwrap()).collect::<Vec<_>>(), ["user", "assistant", "tool", "assistant"]);
+    assert!(messages[3].get("pinned").is_none());
+    assert_eq!(messages[2]["tool_call_id"], json!("call_1"));
+    assert_eq!(example["tools"][0]["function"]["name"], json!("powershell"));
+    assert_eq!(Format::parse("jsonl-finetune"), Some(Format::FinetuneJsonl));
+
+    let no_reply: Messages = serde_json::from_value(json!([{"role": "user", "content": "hi"}])).unwrap();
+    assert!(export::finetune_jsonl(&no_reply, &[]).is_err());
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use rustgpt::openai::files;
+
+// a small project with an ignored file
+fn project_dir(name: &str) -> PathBuf {
+    let dir = common::temp_dir(name);
+    fs::create_dir_all(dir.join("src/nested")).unwrap();
+    fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+    fs::write(dir.join("src/nested/lib.rs"), "pub fn f() {}").unwrap();
+    fs::write(dir.join("src/notes.txt"), "notes").unwrap();
+    fs::write(dir.join(".gitignore"), "*.txt\n").unwrap();
+    fs::create_dir(dir.join(".git")).unwrap();
+    dir
+}
+
+fn names(root: &Path, paths: Vec<PathBuf>) -> Vec<String> {
+    paths.iter().map(|path| path.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/")).collect()
+}
+
+#[test]
+fn expands_directories_and_globs() {
+    let root = project_dir("expand");
+    let dir = root.to_string_lossy().to_string();
+    assert_eq!(names(&root, files::expand(&[&dir]).unwrap()), ["src/main.rs", "src/nested/lib.rs"]);
+
+    let pattern = format!("{dir}/src/**/*.rs");
+    let explicit = format!("{dir}/src/notes.txt");
+    let main = format!("{dir}/src/main.rs");
+    // explicitly named files are attached even when ignored, and only once
+    assert_eq!(names(&root, files::expand(&[&pattern, &explicit, &main]).unwrap()),
+               ["src/main.rs", "src/nested/lib.rs", "src/notes.txt"]);
+
+    let pattern = format!("{dir}/src/*.rs");
+    assert_eq!(names(&root, files::expand(&[&pattern]).unwrap()), ["src/main.rs"]);
+    assert!(files::expand(&[&format!("{dir}/missing.rs")]).is_err());
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn skips_binary_and_large_files() {
+    let root = project_dir("read");
+    fs::write(root.join("image.bin"), [0x89, b'P', b'N', b'G', 0, 0]).unwrap();
+    assert_eq!(files::read_text(&root.join("image.bin"), 1024).unwrap(), None);
+    assert_eq!(files::read_text(&root.join("src/main.rs"), 5).unwrap(), None);
+    assert_eq!(files::read_text(&root.join("src/main.rs"), 1024).unwrap().as_deref(), Some("fn main() {}"));
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn fences_contents_with_language() {
+    assert_eq!(files::language(Path::new("src/main.rs")), "rust");
+    assert_eq!(files::fence("src/main.rs", "rust", "fn main() {}\n"), "src/main.rs:\n```rust\nfn main() {}\n```");
+    // contents with a fence of their own get a longer one
+    let readme = "````\n```sh\ncargo run\n```\n````";
+    assert_eq!(files::fence("README.md", "markdown", readme), format!("README.md:\n`````markdown\n{readme}\n`````"));
+}
@@ -0,0 +1,167 @@
+mod common;
+
+use std::collections::HashMap;
+use serde_json::{json, Value};
+use rustgpt::gemini::{self, StreamDecoder};
+use rustgpt::openai::{ChatHistory, FunctionCall, FunctionParameters, FunctionProperty, JsonSchema, Message, Messages, OpenaiFunction, ResponseFormat, Role, ToolCall, ToolChoice};
+use rustgpt::provider::{ChatProvider, ChatRequest, ChatResponse, ProviderSettings};
+use common::Response;
+
+fn decode(name: &str) -> (ChatResponse, String) {
+    let (response, printed) = common::decode::<StreamDecoder>("gemini", name, 11);
+    (response.unwrap(), printed)
+}
+
+#[test]
+fn decodes_text_stream() {
+    let (response, printed) = decode("text_stream.sse");
+    assert_eq!(printed, "assistant: The sky is blue because of Rayleigh scattering.");
+    assert_eq!(response.message.role, Role::Assistant);
+    assert_eq!(response.message.text(), "The sky is blue because of Rayleigh scattering.");
+    let usage = response.usage.unwrap();
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (9, 11));
+    assert_eq!(response.request_id.as_deref(), Some("mHBdaKqJB8m5nvgP3tjB8Qc"));
+    assert_eq!(response.finish_reason.as_deref(), Some("STOP"));
+}
+
+#[test]
+fn decodes_function_calls() {
+    let (response, _) = decode("function_call_stream.sse");
+    let tool_calls = response.message.tool_calls.unwrap();
+    // gemini has no ids, every call gets one that no other reply uses
+    assert!(tool_calls.iter().all(|tool_call| tool_call.id.starts_with("call_")));
+    assert_ne!(tool_calls[0].id, tool_calls[1].id);
+    assert_eq!(tool_calls[0].function.name, "powershell");
+    assert_eq!(serde_json::from_str::<Value>(&tool_calls[0].function.arguments).unwrap(), json!({"command": "Get-ChildItem"}));
+    assert_eq!(tool_calls[1].function.name, "theme");
+    assert_eq!(response.usage.unwrap().cached_tokens(), 32);
+}
+
+#[test]
+fn maps_messages_to_request() {
+    let mut messages = Messages::new();
+    messages.set_system_message("You translate commands");
+    messages.add_user_message("list files");
+    messages.push(Message {
+        role: Role::Assistant,
+        tool_calls: Some(vec![ToolCall::new("call_0", FunctionCall { name: "theme".into(), arguments: "{\"command\":\"C\"}".into() })]),
+        ..Default::default()
+    });
+    messages.add_tool_message("call_0", "done");
+    let request = gemini::to_request(ChatRequest {
+        model: "gemini-2.0-flash".to_string(),
+        messages,
+        temperature: 0.5,
+        tools: vec![OpenaiFunction {
+            name: "theme".into(),
+            description: "Change the windows theme".into(),
+            parameters: FunctionParameters {
+                r#type: "object".into(),
+                properties: HashMap::from([("command".into(), FunctionProperty {
+                    r#type: "string".into(),
+                    description: None,
+                    r#enum: vec!["A".into(), "C".into()],
+                })]),
+                required: vec!["command".into()],
+            },
+        }],
+        tool_choice: Some(ToolChoice::Required),
+        response_format: None,
+    });
+
+    assert_eq!(serde_json::to_value(&request).unwrap(), json!({
+        "contents": [
+            {"role": "user", "parts": [{"text": "list files"}]},
+            {"role": "model", "parts": [{"functionCall": {"name": "theme", "args": {"command": "C"}}}]},
+            {"role": "user", "parts": [{"functionResponse": {"name": "theme", "response": {"content": "done"}}}]},
+        ],
+        "systemInstruction": {"parts": [{"text": "You translate commands"}]},
+        "tools": [{"functionDeclarations": [{
+            "name": "theme",
+            "description": "Change the windows theme",
+            "parameters": {
+                "type": "OBJECT",
+                "properties": {"command": {"type": "STRING", "format": "enum", "enum": ["A", "C"]}},
+                "required": ["command"],
+            },
+        }]}],
+        "toolConfig": {"functionCallingConfig": {"mode": "ANY"}},
+        "generationConfig": {"temperature": 0.5},
+    }));
+}
+
+#[test]
+fn maps_json_schema_to_generation_config() {
+    let mut messages = Messages::new();
+    messages.add_user_message("Alice is 30");
+    let schema = json!({"type": "object", "properties": {"age": {"type": "integer"}}, "required": ["age"]});
+    let request = gemini::to_request(ChatRequest {
+        model: "gemini-2.0-flash".to_string(),
+        messages,
+        temperature: 0.5,
+        tools: vec![],
+        tool_choice: None,
+        response_format: Some(ResponseFormat::JsonSchema {
+            json_schema: JsonSchema { name: "person".into(), schema: schema.clone(), strict: true },
+        }),
+    });
+    assert_eq!(serde_json::to_value(&request.generation_config).unwrap(), json!({
+        "temperature": 0.5,
+        "responseMimeType": "application/json",
+        "responseJsonSchema": schema,
+    }));
+}
+
+#[tokio::test]
+async fn lists_models_of_every_page() {
+    let (base_url, requests) = common::serve_all(vec![
+        Response::json(&json!({"models": [
+            {"name": "models/gemini-2.0-flash", "supportedGenerationMethods": ["generateContent", "countTokens"]},
+            {"name": "models/text-embedding-004", "supportedGenerationMethods": ["embedContent"]},
+        ], "nextPageToken": "a+b/c=="})),
+        Response::json(&json!({"models": [
+            {"name": "models/gemini-1.5-pro", "supportedGenerationMethods": ["generateContent"]},
+        ]})),
+    ]);
+    std::env::set_var("RUSTGPT_TEST_GEMINI_KEY", "test");
+    let provider = gemini::GeminiProvider::new(reqwest::Client::new(), &ProviderSettings {
+        base_url: Some(base_url),
+        api_key_env: Some("RUSTGPT_TEST_GEMINI_KEY".to_string()),
+        keep_alive: None,
+    }).unwrap();
+    let models = provider.list_models().await.unwrap();
+
+    let ids = models.iter().map(|model| model.id.as_str()).collect::<Vec<_>>();
+    assert_eq!(ids, ["gemini-2.0-flash", "gemini-1.5-pro"]);
+    assert_eq!(requests.recv().unwrap().path, "/models?pageSize=1000");
+    // the token is encoded, a plus would otherwise become a space
+    assert_eq!(requests.recv().unwrap().path, "/models?pageSize=1000&pageToken=a%2Bb%2Fc%3D%3D");
+}
+
+#[test]
+fn names_the_results_of_every_turn() {
+    let call = |id: &str, name: &str| Message {
+        role: Role::Assistant,
+        tool_calls: Some(vec![ToolCall::new(id, FunctionCall { name: name.into(), arguments: "{}".into() })]),
+        ..Default::default()
+    };
+    let mut messages = Messages::new();
+    // older versions numbered the calls of every reply from zero
+    messages.add_user_message("list files");
+    messages.push(call("call_0", "powershell"));
+    messages.add_tool_message("call_0", "a.txt");
+    messages.add_user_message("now dark");
+    messages.push(call("call_0", "theme"));
+    messages.add_tool_message("call_0", "done");
+    let request = gemini::to_request(ChatRequest {
+        model: "gemini-2.0-flash".to_string(),
+        messages,
+        temperature: 0.5,
+        tools: vec![],
+        tool_choice: None,
+        response_format: None,
+    });
+    let body = serde_json::to_value(&request).unwrap();
+    assert_eq!(body["contents"][2]["parts"][0]["functionResponse"]["name"], json!("powershell"));
+    assert_eq!(body["contents"][4]["parts"][0]["functionResponse"]["name"], json!("theme"));
+}
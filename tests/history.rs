@@ -0,0 +1,93 @@
+use serde_json::json;
+use rustgpt::openai::{ChatHistory, Content, ContentPart, FunctionCall, Message, Messages, Role, ToolCall};
+use rustgpt::openai::context::drop_orphaned_tool_messages;
+
+#[test]
+fn loads_legacy_function_calls() {
+    let history: Messages = serde_json::from_value(json!([
+        {"role": "user", "content": "list files"},
+        {"role": "assistant", "content": "", "function_call": {"name": "powershell", "arguments": "{\"command\":\"ls\"}"}},
+        {"role": "function", "content": "a.txt", "name": "powershell"},
+    ])).unwrap();
+
+    let tool_calls = history.0[1].tool_calls.as_ref().unwrap();
+    assert_eq!(tool_calls.len(), 1);
+    assert_eq!(tool_calls[0].function.name, "powershell");
+    assert!(history.0[1].function_call.is_none());
+    assert_eq!(history.0[2].role, Role::Tool);
+    assert_eq!(history.0[2].tool_call_id.as_ref(), Some(&tool_calls[0].id));
+
+    let saved = serde_json::to_value(&history).unwrap();
+    assert!(saved[1].get("function_call").is_none());
+    assert_eq!(saved[1]["tool_calls"][0]["type"], json!("function"));
+}
+
+#[test]
+fn drops_tool_results_without_call() {
+    let mut history = Messages::new();
+    history.add_tool_message("call_0", "trimmed");
+    history.add_user_message("list files");
+    history.push(Message {
+        role: Role::Assistant,
+        tool_calls: Some(vec![ToolCall::new("call_1", FunctionCall { name: "powershell".into(), arguments: "{}".into() })]),
+        ..Default::default()
+    });
+    history.add_tool_message("call_1", "a.txt");
+
+    drop_orphaned_tool_messages(&mut history);
+    let roles = history.0.iter().map(|msg| msg.role).collect::<Vec<_>>();
+    assert_eq!(roles, [Role::User, Role::Assistant, Role::Tool]);
+}
+
+#[test]
+fn round_trips_content_parts_and_refusals() {
+    let messages = json!([
+        {"role": "system", "content": "Be brief"},
+        {"role": "user", "content": [
+            {"type": "text", "text": "What is this?"},
+            {"type": "image_url", "image_url": {"url": "https://example.com/cat.png", "detail": "low"}},
+            {"type": "input_audio", "input_audio": {"data": "UklGRg==", "format": "wav"}},
+        ]},
+        {"role": "assistant", "content": null, "refusal": "I can not help with that."},
+    ]);
+    let history: Messages = serde_json::from_value(messages.clone()).unwrap();
+
+    assert_eq!(history.0[0].content, Some(Content::Text("Be brief".into())));
+    assert_eq!(history.0[1].text(), "What is this?");
+    let Some(Content::Parts(parts)) = &history.0[1].content else {
+        panic!("expected content parts, got {:?}", history.0[1].content);
+    };
+    assert!(matches!(&parts[1], ContentPart::ImageUrl { image_url } if image_url.detail.as_deref() == Some("low")));
+    assert!(history.0[2].content.is_none());
+    assert_eq!(history.0[2].refusal.as_deref(), Some("I can not help with that."));
+    assert_eq!(serde_json::to_value(&history).unwrap(), messages);
+}
+
+#[test]
+fn round_trips_metadata() {
+    let messages = json!([
+        {"role": "user", "content": "hello", "metadata": {"created_at": "2025-01-14T10:21:31Z"}},
+        {"role": "assistant", "content": "hi", "metadata": {
+            "created_at": "2025-01-14T10:21:33Z",
+            "model": "gpt-4o",
+            "provider": "openai",
+            "request_id": "chatcmpl-123",
+            "finish_reason": "stop",
+            "usage": {"prompt_tokens": 9, "completion_tokens": 2, "total_tokens": 11},
+            "time_to_first_token_ms": 420,
+            "latency_ms": 1250,
+            "system": "Be brief",
+            "tools": ["powershell"],
+            "temperature": 0.5,
+        }},
+    ]);
+    let history: Messages = serde_json::from_value(messages.clone()).unwrap();
+
+    let metadata = history.0[1].metadata.as_ref().unwrap();
+    assert_eq!(metadata.finish_reason.as_deref(), Some("stop"));
+    assert_eq!(metadata.usage.as_ref().unwrap().completion_tokens, 2);
+    assert_eq!((metadata.time_to_first_token_ms, metadata.latency_ms), (Some(420), Some(1250)));
+    assert_eq!((metadata.system.as_deref(), metadata.tools.as_deref(), metadata.temperature), (Some("Be brief"), Some(&["powershell".to_string()][..]), Some(0.5)));
+    assert!(metadata.to_string().contains("openai/gpt-4o"));
+    assert_eq!(serde_json::to_value(&history).unwrap(), messages);
+}
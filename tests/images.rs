@@ -0,0 +1,71 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use image::{ImageFormat, RgbImage};
+use rustgpt::openai::{ContentPart, Message, Messages, Role};
+use rustgpt::openai::images::{self, IMAGE_REF_SCHEME};
+
+fn write_png(dir: &Path, width: u32, height: u32) -> String {
+    let path = dir.join(format!("screenshot-{width}x{height}.png"));
+    RgbImage::from_pixel(width, height, image::Rgb([200, 30, 30])).save(&path).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+fn stored_url(part: &ContentPart) -> &str {
+    let ContentPart::ImageUrl { image_url } = part else {
+        panic!("expected an image, got {part:?}");
+    };
+    &image_url.url
+}
+
+#[test]
+fn stores_images_by_hash() {
+    let dir = common::temp_dir("store");
+    let store = dir.join("store").to_string_lossy().into_owned();
+    let path = write_png(&dir, 40, 20);
+
+    let part = images::store(&store, &path, None, Some("low")).unwrap();
+    let url = stored_url(&part);
+    assert!(url.starts_with(IMAGE_REF_SCHEME) && url.ends_with(".png"), "{url}");
+    assert_eq!(images::store(&store, &path, None, Some("low")).unwrap(), part);
+    assert_eq!(fs::read_dir(&store).unwrap().count(), 1);
+
+    let error = images::store(&store, &path, None, Some("medium")).unwrap_err();
+    assert!(error.to_string().contains("medium"), "{error}");
+    fs::write(dir.join("notes.txt"), "not an image").unwrap();
+    assert!(images::store(&store, &dir.join("notes.txt").to_string_lossy(), None, None).is_err());
+}
+
+#[test]
+fn downscales_large_images() {
+    let dir = common::temp_dir("downscale");
+    let (bytes, format) = images::load(&write_png(&dir, 400, 100), Some(200)).unwrap();
+    assert_eq!(format, ImageFormat::Png);
+    let image = image::load_from_memory(&bytes).unwrap();
+    assert_eq!((image.width(), image.height()), (200, 50));
+
+    let original = write_png(&dir, 100, 50);
+    assert_eq!(images::load(&original, Some(200)).unwrap().0, fs::read(&original).unwrap());
+}
+
+#[test]
+fn resolves_references_before_sending() {
+    let dir = common::temp_dir("resolve");
+    let store = dir.join("store").to_string_lossy().into_owned();
+    let image = images::store(&store, &write_png(&dir, 8, 8), None, None).unwrap();
+    let history = Messages(vec![Message::with_parts(Role::User, vec![ContentPart::Text { text: "what is this?".into() }, image])]);
+
+    // the history keeps the short reference, only the request gets the image data
+    let saved = serde_json::to_string(&history).unwrap();
+    assert!(saved.contains(IMAGE_REF_SCHEME) && !saved.contains("base64"));
+    let mut messages = history.clone();
+    images::resolve(&mut messages, &store).unwrap();
+    let url = &messages.0[0].images()[0].url;
+    let (media_type, data) = images::parse_data_url(url).unwrap();
+    assert_eq!(media_type, "image/png");
+    assert!(!data.is_empty());
+
+    fs::remove_dir_all(&store).unwrap();
+    assert!(images::resolve(&mut history.clone(), &store).is_err());
+}
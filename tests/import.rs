@@ -0,0 +1,81 @@
+use std::fs::{self, File};
+use std::io::Write;
+use serde_json::{json, Value};
+use zip::write::SimpleFileOptions;
+use rustgpt::openai::Role;
+use rustgpt::openai::import;
+use rustgpt::openai::sessions;
+
+fn node(id: &str, parent: Option<&str>, children: &[&str], message: Value) -> (String, Value) {
+    (id.to_string(), json!({"id": id, "message": message, "parent": parent, "children": children}))
+}
+
+fn message(role: &str, content: Value) -> Value {
+    json!({"author": {"role": role}, "content": content, "create_time": 1700000000.5})
+}
+
+fn text(text: &str) -> Value {
+    json!({"content_type": "text", "parts": [text]})
+}
+
+fn export() -> Value {
+    let mapping = [
+        node("root", None, &["system"], Value::Null),
+        node("system", Some("root"), &["first", "edited"],
+             json!({"author": {"role": "system"}, "content": text("hidden prompt"), "metadata": {"is_visually_hidden_from_conversation": true}})),
+        node("first", Some("system"), &[], message("user", text("first try"))),
+        node("edited", Some("system"), &["code"], message("user", json!({"content_type": "multimodal_text", "parts": [
+            {"content_type": "image_asset_pointer", "asset_pointer": "file-service://file-1"}, "plot this"]}))),
+        node("code", Some("edited"), &["output"], message("assistant", json!({"content_type": "code", "language": "python", "text": "plot()"}))),
+        node("output", Some("code"), &["answer"], json!({"author": {"role": "tool", "name": "python"}, "content": {"content_type": "execution_output", "text": "done"}})),
+        node("answer", Some("output"), &[], message("assistant", text("Here is the plot"))),
+    ];
+    json!([{
+        "title": "Plotting: a test!",
+        "create_time": 1700000000.25,
+        "update_time": 1700000100.0,
+        "mapping": mapping.into_iter().collect::<serde_json::Map<_, _>>(),
+        "current_node": "answer",
+        "conversation_id": "67a1b2c3-d4e5",
+    }])
+}
+
+#[test]
+fn imports_current_branch_from_zip() {
+    let path = std::env::temp_dir().join(format!("rustgpt-chatgpt-export-{}.zip", std::process::id()));
+    let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+    zip.start_file("conversations.json", SimpleFileOptions::default()).unwrap();
+    zip.write_all(export().to_string().as_bytes()).unwrap();
+    zip.finish().unwrap();
+
+    let importer = import::find("chatgpt").unwrap();
+    let conversations = importer.import(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(conversations.len(), 1);
+    let conversation = &conversations[0];
+    assert_eq!(conversation.id, "67a1b2c3-d4e5");
+    assert_eq!(conversation.title, "Plotting: a test!");
+    assert_eq!(conversation.created_at.unwrap().timestamp_millis(), 1700000000250);
+    assert_eq!(conversation.updated_at.unwrap().timestamp(), 1700000100);
+
+    let messages = conversation.messages.0.iter().map(|msg| (msg.role, msg.text().into_owned())).collect::<Vec<_>>();
+    assert_eq!(messages, [
+        (Role::User, "[image]\n\nplot this".to_string()),
+        (Role::Assistant, "```python\nplot()\n```".to_string()),
+        (Role::Assistant, "Result of python:\ndone".to_string()),
+        (Role::Assistant, "Here is the plot".to_string()),
+    ]);
+    assert_eq!(sessions::session_name(importer.name(), &conversation.title, &conversation.id), "chatgpt-plotting-a-test-67a1b2c3");
+}
+
+#[test]
+fn follows_latest_replies_without_current_node() {
+    let mut export = export();
+    export[0].as_object_mut().unwrap().remove("current_node");
+    let path = std::env::temp_dir().join(format!("rustgpt-chatgpt-conversations-{}.json", std::process::id()));
+    fs::write(&path, export.to_string()).unwrap();
+    let conversations = import::find("chatgpt").unwrap().import(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(conversations[0].messages.0.len(), 4);
+    assert!(import::find("unknown").is_none());
+}
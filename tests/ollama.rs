@@ -0,0 +1,184 @@
+mod common;
+
+use std::collections::HashMap;
+use std::sync::mpsc::Receiver;
+use serde_json::{json, Value};
+use rustgpt::ollama::{self, OllamaProvider};
+use rustgpt::openai::{ChatHistory, FunctionCall, FunctionParameters, FunctionProperty, Message, Messages, OpenaiFunction, ResponseFormat, Role, ToolCall};
+use rustgpt::provider::{ChatProvider, ChatRequest, ProviderSettings};
+use common::{RecordedRequest, Response};
+
+// replays one recorded response per connection, split in small http chunks
+fn serve(responses: Vec<(u16, &str)>) -> (String, Receiver<RecordedRequest>) {
+    common::serve_all(responses.into_iter()
+        .map(|(status, name)| Response::stream(status, "application/x-ndjson", common::fixture("ollama", name), 23))
+        .collect())
+}
+
+fn provider(base_url: &str) -> OllamaProvider {
+    let settings = ProviderSettings {
+        base_url: Some(base_url.to_string()),
+        api_key_env: None,
+        keep_alive: Some("10m".to_string()),
+    };
+    OllamaProvider::new(reqwest::Client::new(), &settings)
+}
+
+fn request(tools: Vec<OpenaiFunction>) -> ChatRequest {
+    let mut messages = Messages::new();
+    messages.set_system_message("Be brief");
+    messages.add_user_message("Why is the sky blue?");
+    ChatRequest {
+        model: "llama3.2".to_string(),
+        messages,
+        temperature: 0.5,
+        tools,
+        tool_choice: None,
+        response_format: None,
+    }
+}
+
+#[tokio::test]
+async fn streams_chat() {
+    let (base_url, requests) = serve(vec![(200, "chat_stream.ndjson")]);
+    let mut printed = String::new();
+    let response = provider(&base_url).chat(request(vec![]), &mut common::printer(&mut printed)).await.unwrap();
+
+    assert_eq!(printed, "assistant: The sky is blue.");
+    assert_eq!(response.message.text(), "The sky is blue.");
+    assert_eq!(response.message.role, Role::Assistant);
+    let usage = response.usage.unwrap();
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens), (26, 4));
+    assert_eq!(response.finish_reason.as_deref(), Some("stop"));
+
+    let recorded = requests.recv().unwrap();
+    assert_eq!((recorded.method.as_str(), recorded.path.as_str()), ("POST", "/api/chat"));
+    let body: Value = serde_json::from_str(&recorded.body).unwrap();
+    assert_eq!(body["stream"], json!(true));
+    assert_eq!(body["keep_alive"], json!("10m"));
+    assert_eq!(body["options"]["temperature"], json!(0.5));
+    assert_eq!(body["messages"][0], json!({"role": "system", "content": "Be brief"}));
+    assert!(body.get("tools").is_none());
+    assert!(body.get("format").is_none());
+}
+
+#[test]
+fn maps_json_mode_to_format() {
+    let mut request = request(vec![]);
+    request.response_format = Some(ResponseFormat::JsonObject);
+    let body = serde_json::to_value(ollama::to_request(request, None)).unwrap();
+    assert_eq!(body["format"], json!("json"));
+}
+
+#[tokio::test]
+async fn streams_tool_calls() {
+    let (base_url, requests) = serve(vec![(200, "tool_call_stream.ndjson")]);
+    let functions = vec![OpenaiFunction {
+        name: "powershell".into(),
+        description: "Call a powershell command".into(),
+        parameters: FunctionParameters {
+            r#type: "object".into(),
+            properties: HashMap::from([("command".into(), FunctionProperty {
+                r#type: "string".into(),
+                description: Some("the powershell command".into()),
+                r#enum: vec![],
+            })]),
+            required: vec!["command".into()],
+        },
+    }];
+    let mut request = request(functions);
+    request.messages.push(Message {
+        role: Role::Assistant,
+        tool_calls: Some(vec![ToolCall::new("call_0", FunctionCall { name: "powershell".into(), arguments: "{\"command\":\"pwd\"}".into() })]),
+        ..Default::default()
+    });
+    request.messages.add_tool_message("call_0", "C:\\");
+    let response = provider(&base_url).complete(request).await.unwrap();
+
+    let tool_calls = response.message.tool_calls.unwrap();
+    // ollama has no ids, every call gets one that no other reply uses
+    assert!(tool_calls.iter().all(|tool_call| tool_call.id.starts_with("call_")));
+    assert_ne!(tool_calls[0].id, tool_calls[1].id);
+    assert_eq!(tool_calls[0].function.name, "powershell");
+    assert_eq!(serde_json::from_str::<Value>(&tool_calls[0].function.arguments).unwrap(), json!({"command": "Get-ChildItem"}));
+    assert_eq!(tool_calls[1].function.name, "theme");
+
+    let body: Value = serde_json::from_str(&requests.recv().unwrap().body).unwrap();
+    assert_eq!(body["tools"][0]["type"], json!("function"));
+    assert_eq!(body["tools"][0]["function"]["name"], json!("powershell"));
+    assert_eq!(body["messages"][2]["tool_calls"][0]["function"]["arguments"], json!({"command": "pwd"}));
+    assert_eq!(body["messages"][3], json!({"role": "tool", "content": "C:\\", "tool_name": "powershell"}));
+}
+
+#[tokio::test]
+async fn returns_errors() {
+    let (base_url, _requests) = serve(vec![(404, "error.ndjson"), (200, "error.ndjson")]);
+    let provider = provider(&base_url);
+    let error = provider.complete(request(vec![])).await.unwrap_err();
+    assert!(error.to_string().contains("404"), "{error}");
+    let error = provider.complete(request(vec![])).await.unwrap_err();
+    assert!(error.to_string().contains("not found"), "{error}");
+}
+
+#[tokio::test]
+async fn lists_local_models() {
+    let (base_url, requests) = serve(vec![(200, "tags.json")]);
+    let models = provider(&base_url).list_models().await.unwrap();
+    let ids = models.iter().map(|model| model.id.as_str()).collect::<Vec<_>>();
+    assert_eq!(ids, ["llama3.2:latest", "qwen2.5-coder:7b"]);
+    assert_eq!(models[0].owned_by, "ollama");
+    assert_eq!(models[0].created, 1736514221);
+    assert_eq!(requests.recv().unwrap().path, "/api/tags");
+}
+
+#[tokio::test]
+async fn manages_local_models() {
+    let (base_url, requests) = serve(vec![(200, "pull_stream.ndjson"), (200, "show.json"), (200, "delete.json")]);
+    let provider = provider(&base_url);
+
+    let mut statuses = vec![];
+    provider.pull("llama3.2", &mut |status| statuses.push((status.status.clone(), status.completed))).await.unwrap();
+    assert_eq!(statuses.len(), 7);
+    assert_eq!(statuses[2], ("pulling dde5aa3fc5ff".to_string(), Some(1009688688)));
+    assert_eq!(statuses.last().unwrap().0, "success");
+    let recorded = requests.recv().unwrap();
+    assert_eq!(recorded.path, "/api/pull");
+    assert_eq!(serde_json::from_str::<Value>(&recorded.body).unwrap()["model"], json!("llama3.2"));
+
+    let details = provider.show("llama3.2").await.unwrap();
+    assert_eq!(details["capabilities"], json!(["completion", "tools"]));
+    assert_eq!(requests.recv().unwrap().path, "/api/show");
+
+    provider.delete("llama3.2").await.unwrap();
+    let recorded = requests.recv().unwrap();
+    assert_eq!((recorded.method.as_str(), recorded.path.as_str()), ("DELETE", "/api/delete"));
+}
+
+#[test]
+fn maps_roles_ollama_does_not_know() {
+    let mut request = request(vec![]);
+    request.messages.0[0].role = Role::Developer;
+    request.messages.push(Message { name: Some("powershell".into()), ..Message::new(Role::Function, "C:\\") });
+    let body = serde_json::to_value(ollama::to_request(request, None)).unwrap();
+    assert_eq!(body["messages"][0]["role"], json!("system"));
+    assert_eq!(body["messages"][2], json!({"role": "tool", "content": "C:\\", "tool_name": "powershell"}));
+}
+
+#[test]
+fn names_the_results_of_every_turn() {
+    let call = |id: &str, name: &str| Message {
+        role: Role::Assistant,
+        tool_calls: Some(vec![ToolCall::new(id, FunctionCall { name: name.into(), arguments: "{}".into() })]),
+        ..Default::default()
+    };
+    let mut request = request(vec![]);
+    // older versions numbered the calls of every reply from zero
+    request.messages.push(call("call_0", "powershell"));
+    request.messages.add_tool_message("call_0", "C:\\");
+    request.messages.add_user_message("Now dark");
+    request.messages.push(call("call_0", "theme"));
+    request.messages.add_tool_message("call_0", "done");
+    let body = serde_json::to_value(ollama::to_request(request, None)).unwrap();
+    assert_eq!(body["messages"][3]["tool_name"], json!("powershell"));
+    assert_eq!(body["messages"][6]["tool_name"], json!("theme"));
+}
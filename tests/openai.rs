@@ -0,0 +1,33 @@
+mod common;
+
+use rustgpt::openai::{Role, StreamDecoder};
+
+#[test]
+fn decodes_text_stream_split_anywhere() {
+    // every chunk size splits the lines in other places, the final usage event included
+    for chunk_size in [1, 7, 64, 4096] {
+        let (response, printed) = common::decode::<StreamDecoder>("openai", "text_stream.sse", chunk_size);
+        let response = response.unwrap();
+        assert_eq!(printed, "assistant: The sky is blue because of Rayleigh scattering.", "{chunk_size}");
+        assert_eq!(response.message.role, Role::Assistant);
+        assert_eq!(response.message.text(), "The sky is blue because of Rayleigh scattering.");
+        let usage = response.usage.expect("usage of the last event");
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (14, 9));
+        assert_eq!(response.request_id.as_deref(), Some("chatcmpl-AqZ4rK8f3b1XyQ2"));
+        assert_eq!(response.finish_reason.as_deref(), Some("stop"));
+    }
+}
+
+#[test]
+fn decodes_parallel_tool_calls() {
+    // the last line has no newline
+    let (response, printed) = common::decode::<StreamDecoder>("openai", "tool_call_stream.sse", 5);
+    let response = response.unwrap();
+    assert_eq!(printed, "assistant: powershell{\"command\":\"pwd\"}theme{}");
+    let tool_calls = response.message.tool_calls.unwrap();
+    assert_eq!(tool_calls.len(), 2);
+    assert_eq!((tool_calls[0].id.as_str(), tool_calls[0].function.arguments.as_str()), ("call_x1", "{\"command\":\"pwd\"}"));
+    assert_eq!(tool_calls[1].function.name, "theme");
+    assert!(response.message.content.is_none());
+    assert_eq!(response.finish_reason.as_deref(), Some("tool_calls"));
+}
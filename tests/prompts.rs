@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+use std::fs;
+use rustgpt::openai::prompts;
+
+#[test]
+fn renders_variables() {
+    let file = std::env::temp_dir().join(format!("rustgpt-prompt-{}.txt", std::process::id()));
+    fs::write(&file, "be nice").unwrap();
+    let variables = HashMap::from([prompts::parse_variable("name=Ada").unwrap(), prompts::parse_variable("os=plan9").unwrap()]);
+    let template = format!("Hi {{{{ name }}}} on {{{{os}}}} at {{{{date}}}}, {{{{file:{}}}}}.", file.display());
+    let rendered = prompts::render(&template, &variables).unwrap();
+    // given variables win over the built in ones
+    assert!(rendered.starts_with("Hi Ada on plan9 at "), "{rendered}");
+    assert!(rendered.ends_with(", be nice."), "{rendered}");
+    assert!(!prompts::render("{{cwd}}", &HashMap::new()).unwrap().is_empty());
+    assert!(prompts::render("{{missing}}", &HashMap::new()).is_err());
+    assert!(prompts::parse_variable("novalue").is_err());
+    fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn template_files_replace_built_in_prompts() {
+    let dir = std::env::temp_dir().join(format!("rustgpt-prompts-{}", std::process::id()));
+    let dir_name = dir.to_string_lossy().to_string();
+    let _ = fs::remove_dir_all(&dir);
+    assert!(prompts::find(&dir_name, prompts::POWERSHELL_PROMPT).unwrap().path.is_none());
+
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("pwsh.md"), "Use {{shell}}").unwrap();
+    fs::write(dir.join("reviewer.md"), "Review code").unwrap();
+    let names = prompts::list(&dir_name).unwrap().into_iter().map(|prompt| prompt.name).collect::<Vec<_>>();
+    assert_eq!(names, ["chat", "pwsh", "reviewer"]);
+    assert_eq!(prompts::find(&dir_name, "pwsh").unwrap().template, "Use {{shell}}");
+    assert!(prompts::find(&dir_name, "unknown").is_err());
+    assert!(prompts::path(&dir_name, "../escape").is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
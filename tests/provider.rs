@@ -0,0 +1,101 @@
+mod common;
+
+use serde_json::{json, Value};
+use rustgpt::openai::config::Settings;
+use rustgpt::openai::embeddings::EmbeddingOptions;
+use rustgpt::provider::{self, ChatProvider};
+use common::Response;
+
+const KEY_ENV: &str = "RUSTGPT_TEST_PROVIDER_KEY";
+
+// every provider is given the same url and a key that is set
+fn settings(provider: &str, base_url: &str) -> Settings {
+    std::env::set_var(KEY_ENV, "test");
+    let provider_settings = json!({"base_url": base_url, "api_key_env": KEY_ENV});
+    serde_json::from_value(json!({
+        "model": "model",
+        "history_file": "history.json",
+        "config_file": "config.json",
+        "provider": provider,
+        "providers": {"openai": provider_settings, "anthropic": provider_settings, "gemini": provider_settings, "ollama": provider_settings},
+    })).unwrap()
+}
+
+fn from_settings(provider: &str, base_url: &str) -> Box<dyn ChatProvider> {
+    provider::from_settings(&settings(provider, base_url)).unwrap()
+}
+
+fn options(model: &str) -> EmbeddingOptions {
+    EmbeddingOptions { model: model.to_string(), batch_size: 2, ..Default::default() }
+}
+
+fn inputs() -> Vec<String> {
+    ["first", "second", "third"].map(String::from).to_vec()
+}
+
+#[test]
+fn selects_the_configured_provider() {
+    for name in ["openai", "anthropic", "ollama", "gemini"] {
+        assert_eq!(from_settings(name, "http://127.0.0.1:1").name(), name);
+    }
+    let error = provider::from_settings(&settings("mistral", "http://127.0.0.1:1")).err().unwrap();
+    assert_eq!(error.to_string(), "Unknown provider 'mistral'");
+}
+
+#[test]
+fn fails_without_the_api_key() {
+    let settings: Settings = serde_json::from_value(json!({
+        "model": "model",
+        "history_file": "history.json",
+        "config_file": "config.json",
+        "providers": {"openai": {"api_key_env": "RUSTGPT_TEST_UNSET_KEY"}},
+    })).unwrap();
+    let error = provider::from_settings(&settings).err().unwrap();
+    assert_eq!(error.to_string(), "Environment variable RUSTGPT_TEST_UNSET_KEY is not set");
+}
+
+#[tokio::test]
+async fn embeds_with_openai() {
+    let data = |vectors: &[[f32; 2]]| -> Value {
+        let data = vectors.iter().enumerate().map(|(index, vector)| json!({"index": index, "embedding": vector})).collect::<Vec<_>>();
+        json!({"data": data, "usage": {"prompt_tokens": vectors.len()}})
+    };
+    let (base_url, requests) = common::serve_all(vec![Response::json(&data(&[[1.0, 0.0], [0.0, 1.0]])), Response::json(&data(&[[0.6, 0.8]]))]);
+    let embeddings = from_settings("openai", &base_url).embed(&inputs(), &options("text-embedding-3-small")).await.unwrap();
+
+    assert_eq!(embeddings.vectors, [vec![1.0, 0.0], vec![0.0, 1.0], vec![0.6, 0.8]]);
+    assert_eq!(embeddings.usage.prompt_tokens, 3);
+    let request = requests.recv().unwrap();
+    assert_eq!((request.method.as_str(), request.path.as_str()), ("POST", "/embeddings"));
+    assert_eq!(request.json()["input"], json!(["first", "second"]));
+    assert_eq!(requests.recv().unwrap().json()["input"], json!(["third"]));
+}
+
+#[tokio::test]
+async fn embeds_with_ollama() {
+    let (base_url, requests) = common::serve_all(vec![
+        Response::json(&json!({"model": "nomic-embed-text", "embeddings": [[1.0, 0.0], [0.0, 1.0]], "prompt_eval_count": 4})),
+        Response::json(&json!({"model": "nomic-embed-text", "embeddings": [[0.6, 0.8]], "prompt_eval_count": 2})),
+    ]);
+    let embeddings = from_settings("ollama", &base_url).embed(&inputs(), &options("nomic-embed-text")).await.unwrap();
+
+    assert_eq!(embeddings.vectors, [vec![1.0, 0.0], vec![0.0, 1.0], vec![0.6, 0.8]]);
+    assert_eq!((embeddings.usage.prompt_tokens, embeddings.usage.total_tokens), (6, 6));
+    let request = requests.recv().unwrap();
+    assert_eq!((request.method.as_str(), request.path.as_str()), ("POST", "/api/embed"));
+    assert_eq!(request.json(), json!({"model": "nomic-embed-text", "input": ["first", "second"]}));
+    assert_eq!(requests.recv().unwrap().json()["input"], json!(["third"]));
+}
+
+#[tokio::test]
+async fn rejects_a_wrong_number_of_embeddings() {
+    let (base_url, _requests) = common::serve_all(vec![Response::json(&json!({"embeddings": [[1.0, 0.0]]}))]);
+    let error = from_settings("ollama", &base_url).embed(&inputs()[..2], &options("nomic-embed-text")).await.unwrap_err();
+    assert_eq!(error.to_string(), "Expected 2 embeddings, got 1");
+}
+
+#[tokio::test]
+async fn anthropic_has_no_embeddings() {
+    let error = from_settings("anthropic", "http://127.0.0.1:1").embed(&inputs(), &options("claude")).await.unwrap_err();
+    assert_eq!(error.to_string(), "The anthropic provider does not support embeddings");
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use serde_json::{json, Value};
+use rustgpt::openai::OpenAiProvider;
+use rustgpt::openai::embeddings::EmbeddingOptions;
+use rustgpt::openai::rag::{self, Chunk, Index, Language};
+use rustgpt::provider::ProviderSettings;
+use common::{RecordedRequest, Response};
+
+// embeds every input as [length, 1]
+fn serve() -> (String, Receiver<RecordedRequest>) {
+    common::serve(|request| {
+        let inputs: Vec<String> = serde_json::from_value(request.json()["input"].clone()).unwrap();
+        let data = inputs.iter().enumerate()
+            .map(|(index, input)| json!({"index": index, "embedding": [input.len() as f32, 1.0]}))
+            .collect::<Vec<_>>();
+        Response::json(&json!({"data": data, "usage": {"prompt_tokens": inputs.len(), "total_tokens": inputs.len()}}))
+    })
+}
+
+fn inputs(request: RecordedRequest) -> Value {
+    request.json()["input"].clone()
+}
+
+fn lines(chunks: &[Chunk]) -> Vec<(usize, usize)> {
+    chunks.iter().map(|chunk| (chunk.start_line, chunk.end_line)).collect()
+}
+
+#[test]
+fn chunks_rust_between_items() {
+    let source = "\
+use std::fmt;
+
+/// A point
+#[derive(Debug)]
+struct Point {
+    x: i32,
+}
+
+impl Point {
+    fn new() -> Point {
+        Point { x: 0 }
+    }
+
+    fn x(&self) -> i32 {
+        self.x
+    }
+}
+";
+    // the doc comment and attribute stay with the struct
+    assert_eq!(lines(&rag::chunk(Language::Rust, source, 70)), [(1, 2), (3, 8), (9, 13), (14, 17)]);
+    assert_eq!(lines(&rag::chunk(Language::Rust, source, 1000)), [(1, 17)]);
+}
+
+#[test]
+fn chunks_markdown_between_sections() {
+    let text = "\
+# Install
+
+Run the installer.
+
+```sh
+# not a heading
+cargo install rustgpt
+```
+
+## Configure
+
+Edit the config.
+";
+    let chunks = rag::chunk(Language::Markdown, text, 90);
+    assert_eq!(lines(&chunks), [(1, 9), (10, 12)]);
+    assert!(chunks[0].text.contains("# not a heading"));
+}
+
+#[tokio::test]
+async fn updates_only_changed_files() {
+    let root = common::temp_dir("update");
+    fs::write(root.join("a.md"), "# A\n\nalpha").unwrap();
+    fs::write(root.join("b.txt"), "beta").unwrap();
+    fs::write(root.join("binary.bin"), [0u8, 159, 146, 150]).unwrap();
+    fs::write(root.join(".gitignore"), "ignored.txt\n").unwrap();
+    fs::write(root.join("ignored.txt"), "ignored").unwrap();
+    fs::create_dir(root.join(".git")).unwrap();
+
+    let (base_url, requests) = serve();
+    std::env::set_var("RUSTGPT_TEST_RAG_KEY", "test");
+    let provider = OpenAiProvider::new(reqwest::Client::new(), &ProviderSettings {
+        base_url: Some(base_url),
+        api_key_env: Some("RUSTGPT_TEST_RAG_KEY".to_string()),
+        keep_alive: None,
+    }).unwrap();
+    let options = EmbeddingOptions::default();
+    let mut index = Index::new(&root, &options, 100).unwrap();
+
+    let stats = index.update(&provider, &options).await.unwrap();
+    assert_eq!((stats.added, stats.chunks), (2, 2));
+    assert_eq!(inputs(requests.recv().unwrap()), json!(["a.md\n# A\n\nalpha", "b.txt\nbeta"]));
+    assert_eq!(index.files.keys().collect::<Vec<_>>(), ["a.md", "b.txt"]);
+
+    let index_dir = common::temp_dir("update-index");
+    let path = rag::index_path(&index_dir.to_string_lossy(), &root).unwrap();
+    index.save(&path).unwrap();
+    let mut index = Index::load(&path).unwrap().unwrap();
+    fs::write(root.join("b.txt"), "beta, changed").unwrap();
+    fs::remove_file(root.join("a.md")).unwrap();
+    fs::write(root.join("c.rs"), "fn main() {}").unwrap();
+
+    let stats = index.update(&provider, &options).await.unwrap();
+    assert_eq!((stats.added, stats.changed, stats.unchanged, stats.removed), (1, 1, 0, 1));
+    assert_eq!(inputs(requests.recv().unwrap()), json!(["b.txt\nbeta, changed", "c.rs\nfn main() {}"]));
+
+    let stats = index.update(&provider, &options).await.unwrap();
+    assert_eq!((stats.unchanged, stats.chunks), (2, 0));
+    assert!(requests.try_recv().is_err());
+
+    let hits = index.search(&[19.0, 1.0], 1);
+    assert_eq!(hits[0].citation(), "b.txt:1-1");
+    let context = rag::format_context(&hits, "what is b?");
+    assert!(context.contains("[1] b.txt:1-1\nbeta, changed"), "{context}");
+    assert!(context.ends_with("Question: what is b?"), "{context}");
+    fs::remove_dir_all(&root).unwrap();
+    fs::remove_dir_all(&index_dir).unwrap();
+}
+
+#[test]
+fn names_index_after_directory() {
+    let root = common::temp_dir("name");
+    let path = rag::index_path("rustgpt/index", &root).unwrap();
+    let name = path.file_name().unwrap().to_string_lossy();
+    assert!(path.starts_with(Path::new("rustgpt/index")));
+    assert!(name.starts_with(&*root.file_name().unwrap().to_string_lossy()), "{name}");
+    fs::remove_dir_all(&root).unwrap();
+}
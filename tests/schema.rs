@@ -0,0 +1,54 @@
+use serde_json::json;
+use rustgpt::openai::schema::{extract_json, validate};
+
+#[test]
+fn accepts_matching_json() {
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "name": {"type": "string", "minLength": 1},
+            "age": {"type": ["integer", "null"], "minimum": 0},
+            "tags": {"type": "array", "items": {"$ref": "#/$defs/tag"}},
+        },
+        "required": ["name", "age", "tags"],
+        "additionalProperties": false,
+        "$defs": {"tag": {"type": "string", "enum": ["admin", "user"]}},
+    });
+    assert!(validate(&schema, &json!({"name": "Alice", "age": 30, "tags": ["admin"]})).is_empty());
+    assert!(validate(&schema, &json!({"name": "Bob", "age": null, "tags": []})).is_empty());
+}
+
+#[test]
+fn reports_every_problem_with_its_path() {
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "name": {"type": "string"},
+            "age": {"type": "integer", "maximum": 150},
+            "tags": {"type": "array", "items": {"type": "string", "enum": ["admin", "user"]}},
+        },
+        "required": ["name", "age"],
+        "additionalProperties": false,
+    });
+    let errors = validate(&schema, &json!({"age": 200.5, "tags": ["root"], "email": "a@b.c"}));
+    assert_eq!(errors, [
+        "/: missing required property 'name'",
+        "/age: expected integer, got number",
+        "/: property 'email' is not allowed",
+        "/tags/0: \"root\" is not one of [\"admin\",\"user\"]",
+    ]);
+}
+
+#[test]
+fn checks_any_of() {
+    let schema = json!({"anyOf": [{"type": "string"}, {"type": "number", "minimum": 10}]});
+    assert!(validate(&schema, &json!("ten")).is_empty());
+    assert!(validate(&schema, &json!(12)).is_empty());
+    assert_eq!(validate(&schema, &json!(3)), ["/: does not match any of the allowed schemas"]);
+}
+
+#[test]
+fn strips_markdown_fences() {
+    assert_eq!(extract_json("```json\n{\"a\": 1}\n```"), "{\"a\": 1}");
+    assert_eq!(extract_json("  {\"a\": 1}\n"), "{\"a\": 1}");
+}
@@ -0,0 +1,60 @@
+use std::fs;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use rustgpt::openai::Role;
+use rustgpt::openai::search::{self, Filter, SearchIndex, SessionFile};
+
+fn session(dir: &std::path::Path, name: &str, messages: serde_json::Value, timestamp: &str) -> SessionFile {
+    let path = dir.join(format!("{name}.json"));
+    fs::write(&path, messages.to_string()).unwrap();
+    SessionFile { name: name.to_string(), path, timestamp: Some(timestamp.parse::<DateTime<Utc>>().unwrap()) }
+}
+
+#[test]
+fn searches_all_sessions() {
+    let dir = std::env::temp_dir().join(format!("rustgpt-search-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut sessions = vec![
+        session(&dir, "old", json!([
+            {"role": "user", "content": "How do I list files?"},
+            {"role": "assistant", "content": null, "tool_calls": [
+                {"id": "call_1", "type": "function", "function": {"name": "powershell", "arguments": "{\"command\":\"Get-ChildItem -Recurse\"}"}},
+            ]},
+        ]), "2024-05-01T10:00:00Z"),
+        session(&dir, "new", json!([
+            {"role": "user", "content": "Which files changed in the last commit?"},
+            {"role": "assistant", "content": "Run `git show --stat` to list the changed files."},
+        ]), "2024-05-08T10:00:00Z"),
+    ];
+    let mut index = SearchIndex::default();
+    assert!(index.update(&sessions).unwrap());
+    assert!(!index.update(&sessions).unwrap());
+
+    let found = |index: &SearchIndex, query: &str, filter: &Filter| index.search(query, filter).iter()
+        .map(|result| format!("{}#{}", result.session, result.document.message + 1))
+        .collect::<Vec<_>>();
+    // every word has to match, the most recent session comes first
+    assert_eq!(found(&index, "files", &Filter::default()), ["new#1", "new#2", "old#1"]);
+    assert_eq!(found(&index, "list files", &Filter::default()), ["new#2", "old#1"]);
+    // the last word may be the start of a word, tool call arguments are searched too
+    assert_eq!(found(&index, "get-childi", &Filter::default()), ["old#2"]);
+    assert_eq!(found(&index, "files", &Filter { role: Some(Role::Assistant), ..Default::default() }), ["new#2"]);
+    assert_eq!(found(&index, "files", &Filter { session: Some("old"), ..Default::default() }), ["old#1"]);
+    let since = "2024-05-05T00:00:00Z".parse().unwrap();
+    assert_eq!(found(&index, "files", &Filter { since: Some(since), ..Default::default() }), ["new#1", "new#2"]);
+
+    // changed and removed sessions are indexed again
+    sessions[1] = session(&dir, "new", json!([{"role": "user", "content": "nothing here, really nothing at all"}]), "2024-05-09T10:00:00Z");
+    sessions.remove(0);
+    assert!(index.update(&sessions).unwrap());
+    assert!(found(&index, "files", &Filter::default()).is_empty());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn highlights_snippets() {
+    let text = "A long introduction that goes on and on before it finally mentions the Docker compose file and how to start it.";
+    assert_eq!(search::snippet(text, "docker comp", "[", "]"),
+               "…and on before it finally mentions the [Docker] [comp]ose file and how to start it.");
+    assert_eq!(search::snippet("short text", "text", "[", "]"), "short [text]");
+}
@@ -0,0 +1,238 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use rustgpt::openai::{Attachment, ChatHistory, FunctionCall, Message, Messages, Role};
+use rustgpt::openai::store::{self, Backend, BACKENDS};
+
+fn texts(messages: &Messages) -> Vec<String> {
+    messages.0.iter().map(|msg| msg.text().into_owned()).collect()
+}
+
+fn history_path(dir: &Path, backend: Backend) -> PathBuf {
+    dir.join(format!("conversation.{}", backend.extension()))
+}
+
+#[test]
+fn every_backend_appends_updates_and_replaces() {
+    let dir = common::temp_dir("backends");
+    for backend in BACKENDS {
+        let store = store::open(&history_path(&dir, backend));
+        assert!(store.load().unwrap().0.is_empty(), "{backend:?}");
+
+        store.append(&[Message::new(Role::User, "one")]).unwrap();
+        store.append(&[Message::new(Role::Assistant, "two"), Message::new(Role::User, "three")]).unwrap();
+        assert_eq!(texts(&store.load().unwrap()), ["one", "two", "three"], "{backend:?}");
+
+        store.update(1, &Message::new(Role::Assistant, "second")).unwrap();
+        let (offset, tail) = store.load_tail(2).unwrap();
+        assert_eq!(offset, 1, "{backend:?}");
+        assert_eq!(texts(&tail), ["second", "three"], "{backend:?}");
+
+        store.replace(&Messages(vec![Message::new(Role::User, "only")])).unwrap();
+        store.append(&[Message::new(Role::Assistant, "after")]).unwrap();
+        assert_eq!(texts(&store.load().unwrap()), ["only", "after"], "{backend:?}");
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn every_backend_loads_the_tail_with_its_updates() {
+    let dir = common::temp_dir("tail");
+    for backend in BACKENDS {
+        let store = store::open(&history_path(&dir, backend));
+        assert_eq!(store.load_tail(2).unwrap().0, 0, "{backend:?}");
+        assert!(store.load_from(3).unwrap().0.is_empty(), "{backend:?}");
+
+        store.append(&[Message::new(Role::User, "one"), Message::new(Role::Assistant, "two")]).unwrap();
+        // an update of a message before the tail, written before the tail was appended
+        store.update(0, &Message::new(Role::User, "first")).unwrap();
+        store.append(&[Message::new(Role::User, "three"), Message::new(Role::Assistant, "four")]).unwrap();
+        store.update(2, &Message::new(Role::User, "third")).unwrap();
+        store.update(1, &Message::new(Role::Assistant, "second")).unwrap();
+
+        assert_eq!(texts(&store.load().unwrap()), ["first", "second", "third", "four"], "{backend:?}");
+        let (offset, tail) = store.load_tail(3).unwrap();
+        assert_eq!(offset, 1, "{backend:?}");
+        assert_eq!(texts(&tail), ["second", "third", "four"], "{backend:?}");
+        assert_eq!(texts(&store.load_tail(10).unwrap().1), ["first", "second", "third", "four"], "{backend:?}");
+        assert_eq!(texts(&store.load_from(2).unwrap()), ["third", "four"], "{backend:?}");
+        assert!(store.load_from(4).unwrap().0.is_empty(), "{backend:?}");
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn journal_reads_the_tail_by_its_offset_index() {
+    let dir = common::temp_dir("journal-index");
+    let path = dir.join("conversation.jsonl");
+    let index = dir.join("conversation.jsonl.idx");
+    let store = store::open(&path);
+    store.append(&[Message::new(Role::User, "one"), Message::new(Role::Assistant, "two")]).unwrap();
+    store.update(0, &Message::new(Role::User, "first")).unwrap();
+    // two appends and the length of the journal they cover
+    assert_eq!(fs::metadata(&index).unwrap().len(), 3 * 8);
+
+    // lines written without the index, like by an older version, are scanned
+    let mut contents = fs::read_to_string(&path).unwrap();
+    contents.push_str("{\"append\":{\"role\":\"user\",\"content\":\"three\"}}\n");
+    fs::write(&path, contents).unwrap();
+    let (offset, tail) = store.load_tail(2).unwrap();
+    assert_eq!((offset, texts(&tail)), (1, vec!["two".to_string(), "three".to_string()]));
+    store.append(&[Message::new(Role::Assistant, "four")]).unwrap();
+    assert_eq!(fs::metadata(&index).unwrap().len(), 5 * 8);
+    assert_eq!(texts(&store.load_from(2).unwrap()), ["three", "four"]);
+
+    // an index that does not fit the journal is ignored
+    fs::write(&path, "{\"append\":{\"role\":\"user\",\"content\":\"other\"}}\n").unwrap();
+    assert_eq!(texts(&store.load_tail(2).unwrap().1), ["other"]);
+    store.replace(&Messages(vec![Message::new(Role::User, "only"), Message::new(Role::Assistant, "after")])).unwrap();
+    assert_eq!(fs::metadata(&index).unwrap().len(), 3 * 8);
+    assert_eq!(store.load_tail(1).unwrap().0, 1);
+    assert_eq!(texts(&store.load_from(1).unwrap()), ["after"]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn json_appends_to_older_histories() {
+    let dir = common::temp_dir("json");
+    let path = dir.join("conversation.json");
+    // written by an older version, with whitespace after the array
+    fs::write(&path, "[{\"role\":\"user\",\"content\":\"hello\"}]\n").unwrap();
+    let store = store::open(&path);
+    store.append(&[Message::new(Role::Assistant, "hi")]).unwrap();
+    let saved: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(saved[1]["content"], "hi");
+
+    fs::write(&path, "[ ]").unwrap();
+    store.append(&[Message::new(Role::User, "first")]).unwrap();
+    assert_eq!(texts(&store.load().unwrap()), ["first"]);
+    // the file is replaced as a whole, so no temporary files are left behind
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn journal_ignores_a_cut_off_line() {
+    let dir = common::temp_dir("journal");
+    let path = dir.join("conversation.jsonl");
+    let store = store::open(&path);
+    store.append(&[Message::new(Role::User, "kept")]).unwrap();
+    let mut contents = fs::read_to_string(&path).unwrap();
+    contents.push_str("{\"append\":{\"role\":\"assis");
+    fs::write(&path, contents).unwrap();
+    assert_eq!(texts(&store.load().unwrap()), ["kept"]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn snapshot_writes_new_messages_and_refreshed_attachments() {
+    let dir = common::temp_dir("snapshot");
+    let path = dir.join("conversation.jsonl");
+    let store = store::open(&path);
+    let mut attached = Message::new(Role::User, "main.rs v1");
+    attached.attachment = Some(Attachment { path: "main.rs".into(), hash: "1".into(), max_size: None });
+    store.append(&[Message::new(Role::User, "old"), attached, Message::new(Role::Assistant, "ok")]).unwrap();
+
+    let (mut conversation, snapshot) = store::load_branch(store.as_ref(), Some(2), None).unwrap();
+    conversation.0[0].content = Some(rustgpt::openai::Content::Text("main.rs v2".into()));
+    conversation.0[0].attachment.as_mut().unwrap().hash = "2".into();
+    conversation.add_user_message("question");
+    snapshot.save(store.as_ref(), &conversation).unwrap();
+
+    let saved = store.load().unwrap();
+    assert_eq!(texts(&saved), ["old", "main.rs v2", "ok", "question"]);
+    // only the new message gets a creation time
+    assert!(saved.0[2].metadata.is_none());
+    assert!(saved.0[3].metadata.as_ref().is_some_and(|metadata| metadata.created_at.is_some()));
+    // an update and an append, the earlier lines are left alone
+    assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 5);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn saves_to_legacy_histories_loaded_with_a_tail() {
+    let dir = common::temp_dir("legacy-tail");
+    for backend in [Backend::Journal, Backend::Sqlite] {
+        let store = store::open(&history_path(&dir, backend));
+        let call = Message {
+            function_call: Some(FunctionCall { name: "powershell".into(), arguments: "{}".into() }),
+            ..Message::new(Role::Assistant, "running")
+        };
+        let result = Message { name: Some("powershell".into()), ..Message::new(Role::Function, "a.txt") };
+        store.append(&[Message::new(Role::User, "list files"), call, result]).unwrap();
+
+        // the tail starts with the call, the save only reads the result
+        let (mut conversation, snapshot) = store::load_branch(store.as_ref(), Some(2), None).unwrap();
+        conversation.0[0].set_text("ran");
+        conversation.add_user_message("thanks");
+        snapshot.save(store.as_ref(), &conversation).unwrap();
+
+        let saved = store.load().unwrap();
+        assert_eq!(texts(&saved), ["list files", "ran", "a.txt", "thanks"], "{backend:?}");
+        let id = &saved.0[1].tool_calls.as_ref().unwrap()[0].id;
+        assert_eq!(saved.0[2].tool_call_id.as_ref(), Some(id), "{backend:?}");
+        assert_eq!(store.load_tail(3).unwrap().1.0[1].tool_call_id.as_ref(), Some(id), "{backend:?}");
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn migrates_between_backends() {
+    let dir = common::temp_dir("migrate");
+    let path = history_path(&dir, Backend::Json);
+    store::open(&path).replace(&Messages(vec![Message::new(Role::User, "hello"), Message::new(Role::Assistant, "hi")])).unwrap();
+
+    let sqlite = store::migrate(&path, Backend::Sqlite).unwrap();
+    assert_eq!(sqlite, history_path(&dir, Backend::Sqlite));
+    assert!(!path.exists());
+    assert!(dir.join("conversation.json.bak").exists());
+    assert_eq!(texts(&store::open(&sqlite).load().unwrap()), ["hello", "hi"]);
+
+    let journal = store::migrate(&sqlite, Backend::Journal).unwrap();
+    assert_eq!(texts(&store::open(&journal).load().unwrap()), ["hello", "hi"]);
+    assert_eq!(Backend::from_path(&journal), Backend::Journal);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn concurrent_turns_are_merged() {
+    let dir = common::temp_dir("merge");
+    for backend in BACKENDS {
+        let store = store::open(&history_path(&dir, backend));
+        store.append(&[Message::new(Role::User, "hello"), Message::new(Role::Assistant, "hi")]).unwrap();
+
+        // two runs load the same history and finish one after the other
+        let (mut first, first_snapshot) = store::load_branch(store.as_ref(), None, None).unwrap();
+        let (mut second, second_snapshot) = store::load_branch(store.as_ref(), None, None).unwrap();
+        first.add_user_message("first question");
+        first.add_message(Role::Assistant, "first answer");
+        second.add_user_message("second question");
+        second.add_message(Role::Assistant, "second answer");
+        first_snapshot.save(store.as_ref(), &first).unwrap();
+        second_snapshot.save(store.as_ref(), &second).unwrap();
+
+        let (branch, _) = store::load_branch(store.as_ref(), None, None).unwrap();
+        assert_eq!(texts(&branch), ["hello", "hi", "first question", "first answer", "second question", "second answer"], "{backend:?}");
+    }
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rewritten_history_keeps_the_new_turn() {
+    let dir = common::temp_dir("rewritten");
+    let store = store::open(&dir.join("conversation.jsonl"));
+    let mut attached = Message::new(Role::User, "v1");
+    attached.attachment = Some(Attachment { path: "a.txt".into(), hash: "1".into(), max_size: None });
+    store.append(&[attached, Message::new(Role::Assistant, "ok")]).unwrap();
+
+    let (mut conversation, snapshot) = store::load_branch(store.as_ref(), None, None).unwrap();
+    conversation.0[0].attachment.as_mut().unwrap().hash = "2".into();
+    conversation.add_user_message("question");
+    // cleared by another run in the meantime
+    store.replace(&Messages::new()).unwrap();
+    snapshot.save(store.as_ref(), &conversation).unwrap();
+
+    assert_eq!(texts(&store.load().unwrap()), ["question"]);
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,69 @@
+use rustgpt::openai::{FunctionCall, Message, Metadata, Role, ToolCall, Usage};
+use rustgpt::openai::transcript::{self, Selection, Stats};
+
+fn conversation() -> Vec<Message> {
+    let call = Message::assistant(String::new(), vec![ToolCall::new("call_0", FunctionCall { name: "run".into(), arguments: "{}".into() })]);
+    let result = Message { tool_call_id: Some("call_0".into()), ..Message::new(Role::Tool, "done") };
+    vec![
+        Message::new(Role::System, "Be brief"),
+        Message::new(Role::User, "hello"),
+        Message::new(Role::Assistant, "hi"),
+        Message::new(Role::User, "run it"),
+        call,
+        result,
+        Message::new(Role::Assistant, "it ran"),
+    ]
+}
+
+fn numbers(selection: &Selection) -> Vec<usize> {
+    selection.apply(&conversation()).iter().map(|numbered| numbered.number).collect()
+}
+
+#[test]
+fn parses_ranges() {
+    assert_eq!(transcript::parse_range("3..7"), Some((3, 6)));
+    assert_eq!(transcript::parse_range("3..=7"), Some((3, 7)));
+    assert_eq!(transcript::parse_range("3.."), Some((3, usize::MAX)));
+    assert_eq!(transcript::parse_range("..3"), Some((1, 2)));
+    assert_eq!(transcript::parse_range("5"), Some((5, 5)));
+    assert_eq!(transcript::parse_range("7..3"), None);
+    assert_eq!(transcript::parse_range("0..3"), None);
+    assert_eq!(transcript::parse_range("a..b"), None);
+}
+
+#[test]
+fn numbers_every_message_of_the_branch() {
+    // the system message keeps its number even when it is not shown
+    assert_eq!(numbers(&Selection::default()), [2, 3, 4, 5, 6, 7]);
+    assert_eq!(numbers(&Selection { roles: vec![Role::System], ..Default::default() }), [1]);
+    assert_eq!(numbers(&Selection { range: transcript::parse_range("3..6"), ..Default::default() }), [3, 4, 5]);
+    assert_eq!(numbers(&Selection { last: Some(2), ..Default::default() }), [6, 7]);
+    let role = transcript::parse_role("function").unwrap();
+    assert_eq!(numbers(&Selection { roles: vec![role], ..Default::default() }), [6]);
+}
+
+#[test]
+fn filters_are_applied_before_the_last_messages() {
+    let selection = Selection { roles: vec![Role::User], last: Some(1), ..Default::default() };
+    assert_eq!(numbers(&selection), [4]);
+    let selection = Selection { no_functions: true, last: Some(3), ..Default::default() };
+    assert_eq!(numbers(&selection), [3, 4, 7]);
+}
+
+#[test]
+fn counts_stats_of_the_selected_messages() {
+    let mut messages = conversation();
+    messages[2].metadata = Some(Metadata {
+        usage: Some(Usage { prompt_tokens: 10, completion_tokens: 2, total_tokens: 12, prompt_tokens_details: None }),
+        latency_ms: Some(300),
+        ..Metadata::now()
+    });
+    messages[6].metadata = Some(Metadata { latency_ms: Some(500), ..Default::default() });
+    let stats = Stats::new(&Selection::default().apply(&messages));
+    assert_eq!(stats.messages, 6);
+    assert_eq!(stats.roles["assistant"], 3);
+    assert_eq!((stats.prompt_tokens, stats.completion_tokens), (10, 2));
+    assert_eq!((stats.timed_replies, stats.latency_ms), (2, 800));
+    assert_eq!(stats.first, stats.last);
+    assert!(stats.to_string().contains("average latency:  400ms over 2 replies"));
+}
@@ -0,0 +1,144 @@
+mod common;
+
+use std::fs;
+use rustgpt::openai::{ChatHistory, Message, Messages, Role};
+use rustgpt::openai::{rag, store, tree};
+use rustgpt::openai::config::Settings;
+
+fn texts(messages: &Messages) -> Vec<String> {
+    messages.0.iter().map(|msg| msg.text().into_owned()).collect()
+}
+
+fn branch_texts(messages: &Messages, branch: &[usize]) -> Vec<String> {
+    branch.iter().map(|position| messages.0[*position].text().into_owned()).collect()
+}
+
+#[test]
+fn linear_histories_are_a_single_branch() {
+    let mut history: Messages = serde_json::from_value(serde_json::json!([
+        {"role": "user", "content": "hello"},
+        {"role": "assistant", "content": "hi"},
+        {"role": "user", "content": "bye"},
+    ])).unwrap();
+    tree::link(&mut history, 0);
+    assert_eq!(history.0[0].parent, None);
+    assert_eq!(history.0[2].parent, history.0[1].id);
+
+    let branches = tree::branches(&history.0);
+    assert_eq!(branches, [vec![0, 1, 2]]);
+    assert_eq!(tree::branch(&history.0, None), [0, 1, 2]);
+}
+
+#[test]
+fn edits_fork_the_active_branch() {
+    let dir = common::temp_dir("tree");
+    let store = store::open(&dir.join("conversation.jsonl"));
+    store.append(&[Message::new(Role::User, "hello"), Message::new(Role::Assistant, "hi"), Message::new(Role::User, "bye")]).unwrap();
+
+    // edit the first message and answer it
+    let (mut conversation, mut snapshot) = store::load_branch(store.as_ref(), None, None).unwrap();
+    snapshot.fork(0);
+    conversation.0.clear();
+    conversation.add_user_message("howdy");
+    conversation.add_message(Role::Assistant, "hey");
+    let leaf = snapshot.save(store.as_ref(), &conversation).unwrap();
+
+    let (active, _) = store::load_branch(store.as_ref(), None, None).unwrap();
+    assert_eq!(texts(&active), ["howdy", "hey"]);
+    assert_eq!(active.last().unwrap().id, leaf);
+
+    let mut history = store.load().unwrap();
+    tree::link(&mut history, 0);
+    let branches = tree::branches(&history.0);
+    assert_eq!(branches.len(), 2);
+    assert_eq!(branch_texts(&history, &branches[0]), ["hello", "hi", "bye"]);
+    assert_eq!(branch_texts(&history, &branches[1]), ["howdy", "hey"]);
+
+    // the original branch continues after switching back to it
+    let original = history.0[branches[0][2]].id.clone();
+    let (mut conversation, snapshot) = store::load_branch(store.as_ref(), None, original.as_deref()).unwrap();
+    assert_eq!(texts(&conversation), ["hello", "hi", "bye"]);
+    conversation.add_message(Role::Assistant, "see you");
+    snapshot.save(store.as_ref(), &conversation).unwrap();
+    let mut history = store.load().unwrap();
+    tree::link(&mut history, 0);
+    let branches = tree::branches(&history.0);
+    assert_eq!(branch_texts(&history, &branches[0]), ["hello", "hi", "bye", "see you"]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn removed_messages_keep_their_replies() {
+    let mut history = Messages(vec![Message::new(Role::User, "file"), Message::new(Role::User, "question"), Message::new(Role::Assistant, "answer")]);
+    tree::link(&mut history, 0);
+    assert_eq!(tree::remove(&mut history, |msg| msg.text() == "file"), 1);
+    assert_eq!(history.0[0].parent, None);
+    assert_eq!(tree::branches(&history.0), [vec![0, 1]]);
+}
+
+#[test]
+fn pruning_keeps_messages_of_other_branches() {
+    let mut question = Message::new(Role::User, "question");
+    question.id = Some("q".into());
+    let answer = |id: &str, text: &str| Message { id: Some(id.into()), parent: Some("q".into()), ..Message::new(Role::Assistant, text) };
+    let mut history = Messages(vec![Message::new(Role::User, "hello"), Message::new(Role::Assistant, "hi")]);
+    tree::link(&mut history, 0);
+    question.parent = history.0[1].id.clone();
+    history.0.extend([question, answer("a1", "first"), answer("a2", "second")]);
+
+    // undoing the last exchange of the second answer keeps the question for the first one
+    let branch = tree::branch(&history.0, Some("a2"));
+    assert_eq!(branch, [0, 1, 2, 4]);
+    assert_eq!(tree::prune(&mut history, &branch, 2), 1);
+    assert_eq!(texts(&history), ["hello", "hi", "question", "first"]);
+
+    let branch = tree::branch(&history.0, Some("a1"));
+    assert_eq!(tree::prune(&mut history, &branch, 2), 2);
+    assert_eq!(texts(&history), ["hello", "hi"]);
+}
+
+#[test]
+fn undoing_a_rag_turn_removes_its_sources() {
+    // the sources are folded into the question
+    let hits = [];
+    let mut history = Messages(vec![Message::new(Role::User, "hello"), Message::new(Role::Assistant, "hi")]);
+    history.add_user_message(&rag::format_context(&hits, "what is b?"));
+    history.add_message(Role::Assistant, "beta [1]");
+    tree::link(&mut history, 0);
+    let branch = tree::branch(&history.0, None);
+    assert_eq!(tree::last_exchange(history.0.iter()), Some(2));
+    assert_eq!(tree::prune(&mut history, &branch, 2), 2);
+    assert_eq!(texts(&history), ["hello", "hi"]);
+
+    // earlier versions stored them as a user message of their own before the question
+    history.add_user_message("Sources for my next question:\n\n[1] b.txt:1-1\nbeta");
+    history.add_user_message("what is b?");
+    history.add_message(Role::Assistant, "beta [1]");
+    tree::link(&mut history, 0);
+    let branch = tree::branch(&history.0, None);
+    assert_eq!(tree::last_exchange(history.0.iter()), Some(2));
+    assert_eq!(tree::prune(&mut history, &branch, 2), 3);
+    assert_eq!(texts(&history), ["hello", "hi"]);
+}
+
+#[test]
+fn clearing_the_history_resets_the_active_branch() {
+    let dir = common::temp_dir("tree-clear");
+    let settings: Settings = serde_json::from_value(serde_json::json!({
+        "model": "gpt-4o-mini",
+        "history_file": dir.join("history.json"),
+        "config_file": dir.join("config.json"),
+    })).unwrap();
+    settings.write_history(Messages(vec![Message::new(Role::User, "hello"), Message::new(Role::Assistant, "hi")])).unwrap();
+    let mut info = settings.session_info().unwrap();
+    info.title = Some("greetings".into());
+    info.branch = settings.get_history().unwrap().0[0].id.clone();
+    settings.write_session_info(&info).unwrap();
+
+    settings.clear_history().unwrap();
+    assert!(settings.get_history().unwrap().0.is_empty());
+    let info = settings.session_info().unwrap();
+    assert_eq!(info.branch, None);
+    assert_eq!(info.title.as_deref(), Some("greetings"));
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs;
+use chrono::{Duration, Utc};
+use rustgpt::openai::usage::{self, Budget, GroupBy, Price, UsageRecord};
+
+fn record(model: &str, session: &str, age: Duration, prompt_tokens: u64, completion_tokens: u64, cached_tokens: u64) -> UsageRecord {
+    UsageRecord {
+        timestamp: Utc::now() - age,
+        model: model.to_string(),
+        session: session.to_string(),
+        prompt_tokens,
+        completion_tokens,
+        cached_tokens,
+    }
+}
+
+fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-9
+}
+
+#[test]
+fn prices_dated_models_and_cached_tokens() {
+    let prices = HashMap::new();
+    // gpt-4o-mini is a longer match than gpt-4o
+    let price = usage::find_price(&prices, "gpt-4o-mini-2024-07-18").unwrap();
+    assert!(close(price.prompt, 0.15));
+    assert!(usage::find_price(&prices, "my-local-model").is_none());
+
+    let cost = usage::cost(&record("claude-3-5-sonnet-20241022", "s", Duration::zero(), 1_000_000, 100_000, 400_000), &prices).unwrap();
+    // 600k uncached at $3, 400k cached at $0.30 and 100k completion at $15
+    assert!(close(cost, 1.8 + 0.12 + 1.5), "{cost}");
+}
+
+#[test]
+fn configured_prices_win_over_the_defaults() {
+    let prices = HashMap::from([("gpt-4o".to_string(), Price { prompt: 1.0, completion: 2.0, cached_prompt: None })]);
+    let cost = usage::cost(&record("gpt-4o-2024-08-06", "s", Duration::zero(), 1_000_000, 1_000_000, 500_000), &prices).unwrap();
+    // without a cached price the cached tokens cost as much as the others
+    assert!(close(cost, 3.0), "{cost}");
+}
+
+#[test]
+fn totals_by_group_since_a_time() {
+    let records = [
+        record("gpt-4o", "work", Duration::hours(1), 100, 10, 0),
+        record("gpt-4o", "home", Duration::hours(2), 200, 20, 50),
+        record("llama3.2", "work", Duration::hours(3), 300, 30, 0),
+        record("gpt-4o", "work", Duration::days(10), 1000, 100, 0),
+    ];
+    let prices = HashMap::new();
+    let since = Some(Utc::now() - Duration::days(1));
+
+    let by_model = usage::totals(&records, &prices, since, GroupBy::Model);
+    assert_eq!(by_model.keys().collect::<Vec<_>>(), ["gpt-4o", "llama3.2"]);
+    let gpt = &by_model["gpt-4o"];
+    assert_eq!((gpt.requests, gpt.prompt_tokens, gpt.completion_tokens, gpt.cached_tokens), (2, 300, 30, 50));
+    assert!(gpt.cost > 0.0);
+    assert_eq!((by_model["llama3.2"].unpriced, by_model["llama3.2"].cost), (1, 0.0));
+
+    let by_session = usage::totals(&records, &prices, None, GroupBy::Session);
+    assert_eq!(by_session["work"].requests, 3);
+}
+
+#[test]
+fn budgets_stop_requests_once_spent() {
+    let prices = HashMap::from([("model".to_string(), Price { prompt: 1.0, completion: 1.0, cached_prompt: None })]);
+    // $2 spent a moment ago
+    let records = [record("model", "s", Duration::zero(), 1_000_000, 1_000_000, 0)];
+    assert!(usage::check_budget(&Budget::default(), &records, &prices).is_ok());
+    assert!(usage::check_budget(&Budget { daily: Some(5.0), monthly: None }, &records, &prices).is_ok());
+    let error = usage::check_budget(&Budget { daily: Some(2.0), monthly: None }, &records, &prices).unwrap_err();
+    assert!(error.to_string().contains("Daily budget of $2.00 exceeded"), "{error}");
+    let error = usage::check_budget(&Budget { daily: None, monthly: Some(1.5) }, &records, &prices).unwrap_err();
+    assert!(error.to_string().contains("Monthly budget"), "{error}");
+}
+
+#[test]
+fn reads_appended_records_and_skips_broken_lines() {
+    let dir = common::temp_dir("usage");
+    let path = dir.join("usage.jsonl").to_string_lossy().into_owned();
+    assert!(usage::read_records(&path).unwrap().is_empty());
+    usage::append_record(&path, &record("gpt-4o", "s", Duration::zero(), 1, 2, 0)).unwrap();
+    let mut contents = fs::read_to_string(&path).unwrap();
+    contents.push_str("{\"timestamp\":\n");
+    fs::write(&path, contents).unwrap();
+    usage::append_record(&path, &record("gpt-4o", "s", Duration::zero(), 3, 4, 0)).unwrap();
+
+    let records = usage::read_records(&path).unwrap();
+    assert_eq!(records.iter().map(|record| record.prompt_tokens).collect::<Vec<_>>(), [1, 3]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn parses_since_dates_and_ages() {
+    let week = usage::parse_since("1w").unwrap();
+    let age = Utc::now() - week;
+    assert!(age >= Duration::days(7) && age < Duration::days(7) + Duration::minutes(1));
+    assert!(usage::parse_since("12h").is_ok());
+    assert!(usage::parse_since("2025-01-14").is_ok());
+
+    for value in ["", "d", "3", "3x", "3é", "é", "-3d", "9223372036854775807d", "99999999999w"] {
+        let error = usage::parse_since(value).unwrap_err();
+        assert!(error.to_string().contains("Invalid --since value"), "{value}: {error}");
+    }
+}